@@ -0,0 +1,51 @@
+#[macro_use]
+extern crate criterion;
+extern crate rocket;
+extern crate rocket_csrf;
+
+use criterion::Criterion;
+use rocket::http::Method;
+use rocket::local::Client;
+use rocket::response::content::Html;
+use rocket::{handler, Data, Request, Route};
+use rocket_csrf::CsrfFairingBuilder;
+
+const FORM: &str = "<div class=\"item\"><p>Lorem ipsum dolor sit amet.</p>\
+                    <form method=\"post\" action=\"/items/delete\"><input name=\"id\" value=\"1\">\
+                    <button>Delete</button></form></div>";
+const FORMS: usize = 500; //about 80KB, a large page
+
+fn document<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+    let body = format!("<html><head></head><body>{}</body></html>", FORM.repeat(FORMS));
+    handler::Outcome::from(request, Html(body))
+}
+
+fn client(max_chunk_size: u64) -> Client {
+    let rocket = rocket::ignite()
+        .attach(
+            CsrfFairingBuilder::new()
+                .set_secret([0; 32])
+                .set_auto_insert_max_chunk_size(max_chunk_size)
+                .finalize()
+                .unwrap(),
+        )
+        .mount("/", vec![Route::new(Method::Get, "/", document)]);
+    Client::new(rocket).unwrap()
+}
+
+fn sized(c: &mut Criterion) {
+    let client = client(1024 * 1024);
+    c.bench_function("proxy sized body", move |b| {
+        b.iter(|| client.get("/").dispatch().body_bytes())
+    });
+}
+
+fn streamed(c: &mut Criterion) {
+    let client = client(0);
+    c.bench_function("proxy streamed body", move |b| {
+        b.iter(|| client.get("/").dispatch().body_bytes())
+    });
+}
+
+criterion_group!(benches, sized, streamed);
+criterion_main!(benches);
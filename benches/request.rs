@@ -0,0 +1,116 @@
+#[macro_use]
+extern crate criterion;
+extern crate rocket;
+extern crate rocket_csrf;
+
+use criterion::Criterion;
+use rocket::http::{ContentType, Cookie, Method};
+use rocket::local::Client;
+use rocket::response::content::Html;
+use rocket::{handler, Data, Request, Route};
+use rocket_csrf::__private::parse_args;
+use rocket_csrf::CsrfFairingBuilder;
+
+fn form<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+    handler::Outcome::from(request, Html("<form method=\"post\"></form>"))
+}
+
+fn ok<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+    handler::Outcome::from(request, "ok")
+}
+
+fn client() -> (Client, Vec<Cookie<'static>>, String) {
+    //a client, with the cookies and the token of a form it was given
+    let rocket = rocket::ignite()
+        .attach(CsrfFairingBuilder::new().set_secret([0; 32]).finalize().unwrap())
+        .mount(
+            "/",
+            vec![
+                Route::new(Method::Get, "/form", form),
+                Route::new(Method::Get, "/", ok),
+                Route::new(Method::Post, "/", ok),
+            ],
+        );
+    let client = Client::new(rocket).unwrap();
+    let (cookies, token) = {
+        let mut response = client.get("/form").dispatch();
+        let cookies = response.cookies();
+        let body = response.body_string().unwrap();
+        let token = body
+            .split("value=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap()
+            .to_owned();
+        (cookies, token)
+    };
+    (client, cookies, token)
+}
+
+fn get_without_cookie(c: &mut Criterion) {
+    let (client, _, _) = client();
+    c.bench_function("request get issuing a cookie", move |b| {
+        b.iter(|| client.get("/").dispatch().status())
+    });
+}
+
+fn get_with_cookie(c: &mut Criterion) {
+    let (client, cookies, _) = client();
+    c.bench_function("request get with a cookie", move |b| {
+        b.iter(|| {
+            let mut request = client.get("/");
+            for cookie in cookies.iter().cloned() {
+                request = request.cookie(cookie);
+            }
+            request.dispatch().status()
+        })
+    });
+}
+
+fn post(c: &mut Criterion, name: &str, padding: usize) {
+    //a verified form submission, with a body of about this many more bytes
+    let (client, cookies, token) = client();
+    let body = format!(
+        "csrf-token={}&comment={}",
+        token,
+        "abcdefghij&k=v".repeat(padding / 14)
+    );
+    c.bench_function(name, move |b| {
+        b.iter(|| {
+            let mut request = client
+                .post("/")
+                .header(ContentType::Form)
+                .body(body.as_str());
+            for cookie in cookies.iter().cloned() {
+                request = request.cookie(cookie);
+            }
+            request.dispatch().status()
+        })
+    });
+}
+
+fn post_small(c: &mut Criterion) {
+    post(c, "request post small form", 0);
+}
+
+fn post_large(c: &mut Criterion) {
+    post(c, "request post large urlencoded body", 64 * 1024);
+}
+
+fn parse_large(c: &mut Criterion) {
+    //searching the token at the end of a large body, without the rest of the request
+    let body = format!("{}csrf-token=token", "abcdefghij&k=v&".repeat(64 * 1024 / 15));
+    c.bench_function("parse_args large urlencoded body", move |b| {
+        b.iter(|| parse_args(&body).find(|(key, _)| *key == "csrf-token").is_some())
+    });
+}
+
+criterion_group!(
+    benches,
+    get_without_cookie,
+    get_with_cookie,
+    post_small,
+    post_large,
+    parse_large
+);
+criterion_main!(benches);
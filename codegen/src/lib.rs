@@ -0,0 +1,318 @@
+#![deny(missing_docs)]
+//! # Rocket Csrf Codegen
+//!
+//! Derive and attribute macros for [rocket_csrf](https://github.com/fdb-hiroshima/rocket_csrf). Don't depend on
+//! this crate directly, enable the `derive` feature of `rocket_csrf` instead.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use syn::{Data, DeriveInput, Fields, ItemFn, Lit, LitStr, Meta, NestedMeta};
+
+/// Derive `FromForm`, `FromData` and `render_hidden_field` for a csrf protected form.
+///
+/// The struct gets its own csrf verification, with no field to declare for the token:
+///
+/// - `FromForm` require a `csrf-token` field matching the csrf cookie, which need the
+///   `CsrfFairing` to be attached, like `CsrfTokenField`. Use it through `Form<T>`.
+/// - `FromData` read the whole body (up to the `forms` limit) and verify the token wherever it
+///   is, like `CsrfForm`. Use the struct directly as the data guard.
+/// - `render_hidden_field(&token)` give the hidden input to put in the html form.
+///
+/// Only structs with named fields, no generic parameter, and fields implementing
+/// `FromFormValue` for any lifetime are supported. Form keys are the field names.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[derive(CsrfProtectedForm)]
+/// struct Comment {
+///     content: String,
+/// }
+///
+/// #[post("/comment", data = "<comment>")]
+/// fn comment(comment: Comment) -> Redirect {
+///     //...
+/// }
+/// ```
+#[proc_macro_derive(CsrfProtectedForm)]
+pub fn derive_csrf_protected_form(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = match syn::parse(input) {
+        Ok(input) => input,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let name = &input.ident;
+
+    if !input.generics.params.is_empty() {
+        return syn::Error::new(Span::call_site(), "CsrfProtectedForm can't be derived for generic structs")
+            .to_compile_error()
+            .into();
+    }
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => {
+                return syn::Error::new(Span::call_site(), "CsrfProtectedForm require named fields")
+                    .to_compile_error()
+                    .into()
+            }
+        },
+        _ => {
+            return syn::Error::new(Span::call_site(), "CsrfProtectedForm can only be derived for structs")
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    let idents: Vec<_> = fields.iter().map(|f| f.ident.clone().unwrap()).collect();
+    let types: Vec<_> = fields.iter().map(|f| f.ty.clone()).collect();
+    let keys: Vec<_> = idents
+        .iter()
+        .map(|ident| LitStr::new(&ident.to_string(), Span::call_site()))
+        .collect(); //form keys are the field names
+    let (idents1, idents2, idents3, idents4) = (&idents, &idents, &idents, &idents);
+    let (types1, types2) = (&types, &types);
+    let (keys1, keys2) = (&keys, &keys);
+
+    let expanded = quote! {
+        impl #name {
+            #[doc(hidden)]
+            pub fn __csrf_from_items<'f>(
+                items: &mut ::rocket::request::FormItems<'f>,
+                strict: bool,
+                check_token: bool,
+            ) -> ::std::result::Result<Self, ::rocket_csrf::CsrfError> {
+                let mut token_found = !check_token;
+                let token_field = ::rocket_csrf::__private::field_name();
+                #( let mut #idents1: ::std::option::Option<#types1> = None; )*
+                for (key, value) in items {
+                    match key.as_str() {
+                        field if field == token_field => {
+                            if check_token {
+                                <::rocket_csrf::CsrfTokenField as ::rocket::request::FromFormValue>::from_form_value(value)?;
+                                token_found = true;
+                            }
+                        }
+                        #( #keys1 => {
+                            #idents2 = Some(
+                                <#types2 as ::rocket::request::FromFormValue>::from_form_value(value)
+                                    .map_err(|_| ::rocket_csrf::CsrfError::Parse(#keys1.to_owned()))?,
+                            );
+                        } )*
+                        _ if strict => return Err(::rocket_csrf::CsrfError::Parse(key.to_string())),
+                        _ => {}
+                    }
+                }
+                if !token_found {
+                    return Err(::rocket_csrf::CsrfError::InvalidToken);
+                }
+                Ok(#name {
+                    #( #idents3: match #idents4 {
+                        Some(value) => value,
+                        None => ::rocket::request::FromFormValue::default()
+                            .ok_or_else(|| ::rocket_csrf::CsrfError::Parse(#keys2.to_owned()))?,
+                    }, )*
+                })
+            }
+
+            /// Render the hidden field to put in the html form this struct is parsed from.
+            pub fn render_hidden_field(token: &::rocket_csrf::CsrfToken) -> String {
+                ::rocket_csrf::__private::hidden_field(token)
+            }
+        }
+
+        impl<'f> ::rocket::request::FromForm<'f> for #name {
+            type Error = ::rocket_csrf::CsrfError;
+
+            fn from_form(
+                items: &mut ::rocket::request::FormItems<'f>,
+                strict: bool,
+            ) -> ::std::result::Result<Self, ::rocket_csrf::CsrfError> {
+                Self::__csrf_from_items(items, strict, true)
+            }
+        }
+
+        impl ::rocket::data::FromData for #name {
+            type Error = ::rocket_csrf::CsrfError;
+
+            fn from_data(
+                request: &::rocket::Request,
+                data: ::rocket::Data,
+            ) -> ::rocket::data::Outcome<Self, ::rocket_csrf::CsrfError> {
+                let form = match ::rocket_csrf::__private::read_verified_form(request, data) {
+                    Ok(form) => form,
+                    Err(failure) => return ::rocket::Outcome::Failure(failure),
+                };
+                match Self::__csrf_from_items(&mut ::rocket::request::FormItems::from(form.as_str()), true, false) {
+                    Ok(value) => ::rocket::Outcome::Success(value),
+                    Err(e) => ::rocket::Outcome::Failure((::rocket::http::Status::UnprocessableEntity, e)),
+                }
+            }
+        }
+    };
+    expanded.into()
+}
+
+fn route_method(attribute: &str) -> Option<&'static str> {
+    //variant of rocket::http::Method for a route attribute or the method given to #[route]
+    match attribute.to_lowercase().as_str() {
+        "get" => Some("Get"),
+        "put" => Some("Put"),
+        "post" => Some("Post"),
+        "delete" => Some("Delete"),
+        "options" => Some("Options"),
+        "head" => Some("Head"),
+        "trace" => Some("Trace"),
+        "connect" => Some("Connect"),
+        "patch" => Some("Patch"),
+        _ => None,
+    }
+}
+
+fn route_info(function: &ItemFn) -> Option<(String, String)> {
+    //method and path of the Rocket route attribute of a function, if any
+    for attribute in &function.attrs {
+        let list = match attribute.parse_meta() {
+            Ok(Meta::List(list)) => list,
+            _ => continue,
+        };
+        let name = list.ident.to_string();
+        let mut method = if name == "route" { None } else { route_method(&name) };
+        if method.is_none() && name != "route" {
+            continue;
+        } //not a route attribute
+        let mut path = None;
+        for nested in &list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::Word(word)) => {
+                    method = method.or_else(|| route_method(&word.to_string()))
+                }
+                NestedMeta::Literal(Lit::Str(lit)) => path = path.or_else(|| Some(lit.value())),
+                NestedMeta::Meta(Meta::NameValue(name_value)) if name_value.ident == "path" => {
+                    if let Lit::Str(ref lit) = name_value.lit {
+                        path = Some(lit.value());
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let (Some(method), Some(path)) = (method, path) {
+            return Some((method.to_owned(), path));
+        }
+    }
+    None
+}
+
+/// Exempt a route from csrf verification.
+///
+/// Put it above the Rocket route attribute, and give the route to the fairing with
+/// `add_exempt_routes(csrf_exempt_routes![...])`. The fairing find where the route is mounted
+/// when it is attached, so there is no path to keep in sync with the mount point.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[csrf_exempt]
+/// #[post("/webhook", data = "<payload>")]
+/// fn webhook(payload: Json<Event>) {
+///     //...
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn csrf_exempt(args: TokenStream, input: TokenStream) -> TokenStream {
+    exempt(args.into(), input.into()).into()
+}
+
+fn exempt(
+    args: proc_macro2::TokenStream,
+    input: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    //expansion of #[csrf_exempt], on proc_macro2 tokens so it can be tested
+    if !args.is_empty() {
+        return syn::Error::new(Span::call_site(), "csrf_exempt takes no argument")
+            .to_compile_error();
+    }
+    let function: ItemFn = match syn::parse2(input) {
+        Ok(function) => function,
+        Err(e) => return e.to_compile_error(),
+    };
+    let (method, path) = match route_info(&function) {
+        Some(info) => info,
+        None => {
+            return syn::Error::new(
+                Span::call_site(),
+                "csrf_exempt must be placed above a Rocket route attribute",
+            ).to_compile_error()
+        }
+    };
+    let name = &function.ident;
+    let vis = &function.vis;
+    let method = syn::Ident::new(&method, Span::call_site());
+
+    let expanded = quote! {
+        #function
+
+        #[doc(hidden)]
+        #[allow(non_snake_case)]
+        #vis mod #name {
+            //modules and functions don't share a namespace, so the route is found under the path
+            //of its handler
+            pub static EXEMPT_ROUTE: ::rocket_csrf::ExemptRoute = ::rocket_csrf::ExemptRoute {
+                method: ::rocket::http::Method::#method,
+                path: #path,
+            };
+        }
+    };
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::{self, ItemFn};
+
+    use {exempt, route_info, route_method};
+
+    fn info(function: &str) -> Option<(String, String)> {
+        route_info(&syn::parse_str::<ItemFn>(function).unwrap())
+    }
+
+    #[test]
+    fn test_route_method() {
+        assert_eq!(route_method("post"), Some("Post"));
+        assert_eq!(route_method("PATCH"), Some("Patch"));
+        assert_eq!(route_method("catch"), None);
+    }
+
+    #[test]
+    fn test_route_info() {
+        let webhook = info(r#"#[post("/webhook", data = "<payload>")] fn webhook() {}"#);
+        assert_eq!(webhook, Some(("Post".to_owned(), "/webhook".to_owned())));
+        let route = info(r#"#[route(PUT, path = "/item/<id>")] fn item(id: u32) {}"#);
+        assert_eq!(route, Some(("Put".to_owned(), "/item/<id>".to_owned())));
+        let documented = info(r#"#[doc = "x"] #[delete("/item")] fn delete() {}"#);
+        assert_eq!(documented, Some(("Delete".to_owned(), "/item".to_owned())));
+        assert_eq!(info(r#"#[catch(404)] fn not_found() {}"#), None);
+        assert_eq!(info("fn helper() {}"), None);
+    }
+
+    #[test]
+    fn test_exempt() {
+        let function = r#"#[post("/webhook")] pub fn webhook() {}"#;
+        let expanded = exempt(quote!(), function.parse().unwrap()).to_string();
+        assert!(expanded.contains("pub mod webhook"));
+        assert!(expanded.contains("EXEMPT_ROUTE"));
+        assert!(expanded.contains("Method :: Post"));
+        assert!(expanded.contains(r#"path : "/webhook""#));
+        assert!(!expanded.contains("compile_error"));
+
+        let expanded = exempt(quote!(), "fn helper() {}".parse().unwrap()).to_string();
+        assert!(expanded.contains("compile_error"));
+        let expanded = exempt(quote!(always), function.parse().unwrap()).to_string();
+        assert!(expanded.contains("compile_error"));
+    }
+}
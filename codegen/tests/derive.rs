@@ -0,0 +1,39 @@
+extern crate rocket;
+extern crate rocket_csrf;
+
+use rocket::request::{FormItems, FromForm};
+use rocket_csrf::{CsrfError, CsrfProtectedForm};
+
+#[derive(CsrfProtectedForm)]
+struct Comment {
+    content: String,
+    rating: u8,
+}
+
+fn parse(form: &str, strict: bool) -> Result<Comment, CsrfError> {
+    Comment::from_form(&mut FormItems::from(form), strict)
+}
+
+#[test]
+fn test_token_required() {
+    match parse("content=hi&rating=3", true) {
+        Err(CsrfError::InvalidToken) => (),
+        _ => panic!("form without token accepted"),
+    }
+    match parse("content=hi&rating=3&csrf-token=abc", true) {
+        Err(CsrfError::MissingCookie) => (), //verified against the cookie of the request
+        _ => panic!("token accepted without cookie"),
+    }
+}
+
+#[test]
+fn test_fields() {
+    match parse("content=hi&rating=many&csrf-token=abc", true) {
+        Err(CsrfError::Parse(ref key)) if key == "rating" => (),
+        _ => panic!("invalid field accepted"),
+    }
+    match parse("content=hi&rating=3&other=1", true) {
+        Err(CsrfError::Parse(ref key)) if key == "other" => (),
+        _ => panic!("unexpected field accepted in strict mode"),
+    }
+}
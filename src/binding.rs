@@ -0,0 +1,107 @@
+use rocket::Request;
+use std::ops::BitOr;
+
+use csrf_state::SessionFn;
+
+/// Request attributes tokens are bound to, combined with `|`.
+///
+/// A token bound to an attribute is only valid on requests with the same value for it, so a
+/// leaked token is useless from another client. Attributes can change during a legitimate
+/// session, for example the IP address of a mobile client, which then has to reload the page to
+/// get a new token, so bind only to what is stable enough for your users.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// CsrfFairingBuilder::new()
+///     .set_token_binding(TokenBinding::CLIENT_IP | TokenBinding::USER_AGENT)
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenBinding(u8);
+
+impl TokenBinding {
+    /// Bind to nothing. This is the default.
+    pub const NONE: TokenBinding = TokenBinding(0);
+    /// Bind to the IP address of the client, as seen by Rocket. Behind a proxy, this is the
+    /// address of the proxy.
+    pub const CLIENT_IP: TokenBinding = TokenBinding(1);
+    /// Bind to the `User-Agent` header.
+    pub const USER_AGENT: TokenBinding = TokenBinding(1 << 1);
+
+    /// Whether every attribute of `other` is in this binding.
+    pub fn contains(self, other: TokenBinding) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for TokenBinding {
+    fn default() -> Self {
+        TokenBinding::NONE
+    }
+}
+
+impl BitOr for TokenBinding {
+    type Output = TokenBinding;
+
+    fn bitor(self, other: TokenBinding) -> TokenBinding {
+        TokenBinding(self.0 | other.0)
+    }
+}
+
+fn push_part(binding: &mut Vec<u8>, label: u8, value: &[u8]) {
+    //label and length prefixed, so distinct attributes never give the same binding
+    binding.push(label);
+    let len = value.len() as u32;
+    binding.extend_from_slice(&[
+        (len >> 24) as u8,
+        (len >> 16) as u8,
+        (len >> 8) as u8,
+        len as u8,
+    ]);
+    binding.extend_from_slice(value);
+}
+
+pub fn request_binding(
+    request: &Request,
+    session: Option<&SessionFn>,
+    attributes: TokenBinding,
+) -> Option<Vec<u8>> {
+    //what tokens of a request must be bound to, none if nothing is configured
+    if session.is_none() && attributes == TokenBinding::NONE {
+        return None;
+    }
+    let mut binding = Vec::new();
+    if let Some(session) = session.and_then(|session| session(request)) {
+        push_part(&mut binding, b's', &session);
+    }
+    if attributes.contains(TokenBinding::CLIENT_IP) {
+        let ip = request
+            .remote()
+            .map(|remote| remote.ip().to_string())
+            .unwrap_or_default();
+        push_part(&mut binding, b'i', ip.as_bytes());
+    }
+    if attributes.contains(TokenBinding::USER_AGENT) {
+        let user_agent = request.headers().get_one("User-Agent").unwrap_or("");
+        push_part(&mut binding, b'u', user_agent.as_bytes());
+    }
+    if binding.is_empty() {
+        None //only bound to a session, and there is none
+    } else {
+        Some(binding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use binding::TokenBinding;
+
+    #[test]
+    fn test_flags() {
+        let binding = TokenBinding::CLIENT_IP | TokenBinding::USER_AGENT;
+        assert!(binding.contains(TokenBinding::CLIENT_IP));
+        assert!(binding.contains(TokenBinding::USER_AGENT));
+        assert!(!TokenBinding::CLIENT_IP.contains(TokenBinding::USER_AGENT));
+        assert!(TokenBinding::NONE.contains(TokenBinding::NONE));
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::Mutex;
+
+const MAX_POOLED: usize = 64; //buffers kept for later responses, the others are freed
+const MAX_CAPACITY: usize = 64 * 1024; //larger buffers are freed, not to keep memory of a huge page
+
+#[derive(Default)]
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>, //buffers used to rewrite bodies, kept from a response to the next
+}
+
+impl BufferPool {
+    pub fn take(&self) -> Vec<u8> {
+        //an empty buffer, which may already have some capacity
+        self.buffers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_default()
+    }
+
+    pub fn give(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > MAX_CAPACITY {
+            return;
+        }
+        buffer.clear();
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        if buffers.len() < MAX_POOLED {
+            buffers.push(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer_pool::{BufferPool, MAX_CAPACITY};
+
+    #[test]
+    fn test_reuse() {
+        let pool = BufferPool::default();
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(b"<form>");
+        let capacity = buffer.capacity();
+        pool.give(buffer);
+        let buffer = pool.take();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.capacity(), capacity);
+
+        pool.give(Vec::with_capacity(MAX_CAPACITY + 1));
+        assert_eq!(pool.take().capacity(), 0); //too large to be kept
+    }
+}
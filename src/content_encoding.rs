@@ -0,0 +1,91 @@
+use std::io::Read;
+
+#[cfg(feature = "brotli")]
+const BUFFER_SIZE: usize = 4096; //size of the buffers of brotli readers
+#[cfg(feature = "brotli")]
+const BROTLI_QUALITY: u32 = 5; //fast enough to compress on every response
+#[cfg(feature = "brotli")]
+const BROTLI_WINDOW: u32 = 22;
+
+pub fn normalize(encoding: Option<&str>) -> Option<String> {
+    //the Content-Encoding of a response, none if its body is not encoded
+    encoding
+        .map(|encoding| encoding.trim().to_ascii_lowercase())
+        .filter(|encoding| !encoding.is_empty() && encoding != "identity")
+}
+
+pub fn is_supported(encoding: &str) -> bool {
+    //if bodies with this encoding can be decoded and encoded again, depending on features
+    match encoding {
+        #[cfg(feature = "flate2")]
+        "gzip" | "x-gzip" | "deflate" => true,
+        #[cfg(feature = "brotli")]
+        "br" => true,
+        _ => false,
+    }
+}
+
+pub fn decoder<'a>(encoding: Option<&str>, body: Box<Read + 'a>) -> Box<Read + 'a> {
+    match encoding {
+        #[cfg(feature = "flate2")]
+        Some("gzip") | Some("x-gzip") => Box::new(::flate2::read::GzDecoder::new(body)),
+        #[cfg(feature = "flate2")]
+        Some("deflate") => Box::new(::flate2::read::ZlibDecoder::new(body)), //zlib, despite its name
+        #[cfg(feature = "brotli")]
+        Some("br") => Box::new(::brotli::Decompressor::new(body, BUFFER_SIZE)),
+        _ => body,
+    }
+}
+
+pub fn encoder<'a>(encoding: Option<&str>, body: Box<Read + 'a>) -> Box<Read + 'a> {
+    match encoding {
+        #[cfg(feature = "flate2")]
+        Some("gzip") | Some("x-gzip") => Box::new(::flate2::read::GzEncoder::new(
+            body,
+            ::flate2::Compression::default(),
+        )),
+        #[cfg(feature = "flate2")]
+        Some("deflate") => Box::new(::flate2::read::ZlibEncoder::new(
+            body,
+            ::flate2::Compression::default(),
+        )),
+        #[cfg(feature = "brotli")]
+        Some("br") => Box::new(::brotli::CompressorReader::new(
+            body,
+            BUFFER_SIZE,
+            BROTLI_QUALITY,
+            BROTLI_WINDOW,
+        )),
+        _ => body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use content_encoding::normalize;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(Some(" GZIP ")).unwrap(), "gzip");
+        assert_eq!(normalize(Some("identity")), None);
+        assert_eq!(normalize(None), None);
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn test_round_trip() {
+        use content_encoding::{decoder, encoder};
+        use std::io::Read;
+
+        let page = b"<form method=post></form>";
+        let mut encoded = Vec::new();
+        encoder(Some("gzip"), Box::new(&page[..]))
+            .read_to_end(&mut encoded)
+            .unwrap();
+        let mut decoded = Vec::new();
+        decoder(Some("gzip"), Box::new(&encoded[..]))
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(&decoded[..], &page[..]);
+    }
+}
@@ -0,0 +1,151 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Method;
+use rocket::{Data, Request, Response, Rocket};
+
+use csrf_fairing::CsrfFairing;
+
+/// Fairing running a CORS fairing (such as rocket_cors) and a [CsrfFairing] in the right order.
+///
+/// Rocket run fairings in the order they were attached, which is easy to get wrong. This type
+/// enforce the following contract, whatever the order of other fairings:
+///
+/// - requests are seen by the CORS fairing first, so CORS preflight requests are validated before
+///   anything else, and are never touched by the csrf protection (no cookie is issued either);
+/// - responses are seen by the CORS fairing last, so CORS headers are also applied to responses of
+///   the csrf violation route, and to responses with inserted tokens.
+///
+/// It is obtained via [`CsrfFairing::with_cors`], and should be attached instead of both fairings.
+///
+/// [CsrfFairing]: struct.CsrfFairing.html
+/// [`CsrfFairing::with_cors`]: struct.CsrfFairing.html#method.with_cors
+pub struct CorsAware<F: Fairing> {
+    cors: F,
+    csrf: CsrfFairing,
+}
+
+impl<F: Fairing> CorsAware<F> {
+    pub(crate) fn new(cors: F, csrf: CsrfFairing) -> Self {
+        CorsAware { cors, csrf }
+    }
+}
+
+pub fn is_preflight(request: &Request) -> bool {
+    //a CORS preflight is an OPTIONS request announcing the method of the actual request
+    request.method() == Method::Options
+        && request
+            .headers()
+            .contains("Access-Control-Request-Method")
+}
+
+impl<F: Fairing> Fairing for CorsAware<F> {
+    fn info(&self) -> Info {
+        Info {
+            name: "CSRF protection with CORS",
+            kind: Kind::Attach | Kind::Launch | Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_attach(&self, rocket: Rocket) -> Result<Rocket, Rocket> {
+        let rocket = if self.cors.info().kind.is(Kind::Attach) {
+            self.cors.on_attach(rocket)?
+        } else {
+            rocket
+        };
+        self.csrf.on_attach(rocket)
+    }
+
+    fn on_launch(&self, rocket: &Rocket) {
+        if self.cors.info().kind.is(Kind::Launch) {
+            self.cors.on_launch(rocket);
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, data: &Data) {
+        if self.cors.info().kind.is(Kind::Request) {
+            self.cors.on_request(request, data);
+        }
+        if !is_preflight(request) {
+            self.csrf.on_request(request, data);
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if self.csrf.info().kind.is(Kind::Response) {
+            self.csrf.on_response(request, response);
+        }
+        if self.cors.info().kind.is(Kind::Response) {
+            self.cors.on_response(request, response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::fairing::{Fairing, Info, Kind};
+    use rocket::http::{Header, Method, Status};
+    use rocket::local::Client;
+    use rocket::{handler, Data, Request, Response, Route};
+
+    use csrf_fairing::CsrfFairingBuilder;
+
+    struct FakeCors;
+
+    impl Fairing for FakeCors {
+        fn info(&self) -> Info {
+            Info {
+                name: "Fake CORS",
+                kind: Kind::Response,
+            }
+        }
+
+        fn on_response(&self, _: &Request, response: &mut Response) {
+            response.set_raw_header("Access-Control-Allow-Origin", "https://example.com");
+        }
+    }
+
+    fn ok<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+        handler::Outcome::from(request, "ok")
+    }
+
+    fn client() -> Client {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_default_target("/violation".to_owned(), Method::Get)
+                    .set_secret([0; 32])
+                    .finalize()
+                    .unwrap()
+                    .with_cors(FakeCors),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Post, "/form", ok),
+                    Route::new(Method::Options, "/form", ok),
+                    Route::new(Method::Get, "/violation", ok),
+                ],
+            );
+        Client::new(rocket).unwrap()
+    }
+
+    #[test]
+    fn test_cors_headers_on_violation() {
+        let client = client();
+        let response = client.post("/form").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.headers().get_one("Access-Control-Allow-Origin"),
+            Some("https://example.com")
+        );
+    }
+
+    #[test]
+    fn test_preflight_untouched() {
+        let client = client();
+        let response = client
+            .options("/form")
+            .header(Header::new("Access-Control-Request-Method", "POST"))
+            .dispatch();
+        assert!(response.headers().get_one("Set-Cookie").is_none());
+    }
+}
@@ -0,0 +1,238 @@
+use data_encoding::BASE64;
+use rocket::response::{self, Responder, Response};
+use rocket::Request;
+use sha2::{Digest, Sha256};
+
+pub const CSP_NONCE_HEADER: &str = "X-Rocket-Csrf-Csp-Nonce";
+
+/// Responder giving the Content Security Policy nonce of a response to the fairing.
+///
+/// When the fairing insert scripts into a page, they must carry the nonce allowed by the
+/// `script-src` directive of the page's policy, or browsers will refuse to run them. If the
+/// response already has a `Content-Security-Policy` header containing a nonce, it is used
+/// automatically. Otherwise, for example when the header is added later by another fairing,
+/// wrap your response with the nonce you gave to that fairing.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[get("/")]
+/// fn index(nonce: MyCspNonce) -> WithCspNonce<Template> {
+///     WithCspNonce(nonce.to_string(), Template::render("index", ()))
+/// }
+/// ```
+#[derive(Debug)]
+pub struct WithCspNonce<R>(pub String, pub R);
+
+impl<'r, R: Responder<'r>> Responder<'r> for WithCspNonce<R> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        Response::build_from(self.1.respond_to(request)?)
+            .raw_header(CSP_NONCE_HEADER, self.0)
+            .ok()
+    }
+}
+
+/// How the token is exposed to javascript, when a global variable is requested.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsGlobalMode {
+    /// Insert an inline script setting the global variable. It carry the nonce of the page's
+    /// policy if there is one, and is left out if the policy forbid inline scripts otherwise.
+    InlineScript,
+    /// Insert a non executable `<script type="application/json" id="csrf-token">` block holding
+    /// the token, which is allowed by any policy. Your own scripts then read it with:
+    ///
+    /// ```js
+    /// window.csrfToken = JSON.parse(document.getElementById('csrf-token').textContent);
+    /// ```
+    JsonBlock,
+    /// Insert an inline script setting the global variable, and add its `'sha256-...'` hash to
+    /// the `script-src` directive of the page's policies which would block it, so it runs even
+    /// when the page has no nonce. Policies allowing inline scripts are left untouched, as a hash
+    /// would make browsers ignore their `'unsafe-inline'`.
+    HashedInlineScript,
+}
+
+pub struct CspInfo {
+    //what the fairing needs to know about the policy of a response
+    pub nonce: Option<String>,
+    pub inline_scripts_allowed: bool,
+}
+
+impl CspInfo {
+    pub fn from(response: &mut Response) -> Self {
+        //consume the private nonce header, and read the policy of a response
+        let given_nonce = response
+            .headers()
+            .get_one(CSP_NONCE_HEADER)
+            .map(|nonce| nonce.to_owned());
+        response.remove_header(CSP_NONCE_HEADER);
+        let given_nonce = given_nonce.filter(|nonce| is_nonce(nonce));
+        let policies: Vec<String> = response
+            .headers()
+            .get("Content-Security-Policy")
+            .map(|policy| policy.to_owned())
+            .collect();
+        let nonce = given_nonce.or_else(|| policies.iter().filter_map(|p| script_nonce(p)).next());
+        let nonce = nonce.filter(|nonce| is_nonce(nonce)); //inserted in attributes, never escape them
+        CspInfo {
+            inline_scripts_allowed: nonce.is_some()
+                || policies.iter().all(|policy| allow_inline_scripts(policy)),
+            nonce,
+        }
+    }
+
+    pub fn nonce_attribute(&self) -> String {
+        //attribute to add to inserted <script> tags
+        match self.nonce {
+            Some(ref nonce) => format!(" nonce=\"{}\"", nonce),
+            None => String::new(),
+        }
+    }
+}
+
+pub fn allow_script(response: &mut Response, script: &str) {
+    //add the hash of an inline script to the policies of a response which would block it
+    let hash = format!("'sha256-{}'", BASE64.encode(&Sha256::digest(script.as_bytes())));
+    let policies: Vec<String> = response
+        .headers()
+        .get("Content-Security-Policy")
+        .map(|policy| policy.to_owned())
+        .collect();
+    if policies.iter().all(|policy| allow_inline_scripts(policy)) {
+        return;
+    }
+    response.remove_header("Content-Security-Policy");
+    for policy in policies {
+        let policy = if allow_inline_scripts(&policy) {
+            policy
+        } else {
+            with_script_source(&policy, &hash)
+        };
+        response.adjoin_raw_header("Content-Security-Policy", policy);
+    }
+}
+
+fn is_nonce(nonce: &str) -> bool {
+    //nonces are base64 or base64url values
+    !nonce.is_empty()
+        && nonce
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"+/-_=".contains(&c))
+}
+
+pub fn is_js_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+fn governing_directive(directives: &[Vec<&str>]) -> Option<usize> {
+    //position of the directive governing inline scripts, if the policy have one
+    ["script-src-elem", "script-src", "default-src"]
+        .iter()
+        .filter_map(|name| {
+            directives.iter().position(|directive| {
+                directive.first().map(|d| d.eq_ignore_ascii_case(name)) == Some(true)
+            })
+        })
+        .next()
+}
+
+fn with_script_source(policy: &str, source: &str) -> String {
+    //add a source to the directive governing inline scripts
+    let mut directives: Vec<Vec<&str>> = policy
+        .split(';')
+        .map(|directive| directive.split_whitespace().collect())
+        .collect();
+    if let Some(position) = governing_directive(&directives) {
+        directives[position].push(source);
+    }
+    directives
+        .iter()
+        .filter(|directive| !directive.is_empty())
+        .map(|directive| directive.join(" "))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn script_sources(policy: &str) -> Option<Vec<&str>> {
+    //sources of the directive governing inline scripts, if the policy have one
+    let directives: Vec<Vec<&str>> = policy
+        .split(';')
+        .map(|directive| directive.split_whitespace().collect())
+        .collect();
+    governing_directive(&directives).map(|position| directives[position][1..].to_vec())
+}
+
+fn script_nonce(policy: &str) -> Option<String> {
+    script_sources(policy)?
+        .iter()
+        .find(|source| source.starts_with("'nonce-") && source.ends_with('\''))
+        .map(|source| source[7..source.len() - 1].to_owned())
+}
+
+fn allow_inline_scripts(policy: &str) -> bool {
+    //'unsafe-inline' is ignored by browsers when a nonce or a hash is present
+    match script_sources(policy) {
+        Some(sources) => {
+            sources.contains(&"'unsafe-inline'")
+                && !sources
+                    .iter()
+                    .any(|source| source.starts_with("'nonce-") || source.starts_with("'sha"))
+        }
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csp::{allow_inline_scripts, is_js_identifier, is_nonce, script_nonce, with_script_source};
+
+    #[test]
+    fn test_script_nonce() {
+        assert_eq!(
+            script_nonce("default-src 'self'; script-src 'self' 'nonce-abc123'").unwrap(),
+            "abc123"
+        );
+        assert_eq!(script_nonce("default-src 'nonce-xyz'").unwrap(), "xyz");
+        assert!(script_nonce("default-src 'nonce-xyz'; script-src 'self'").is_none());
+        assert!(script_nonce("img-src *").is_none());
+    }
+
+    #[test]
+    fn test_allow_inline_scripts() {
+        assert!(allow_inline_scripts("img-src *"));
+        assert!(allow_inline_scripts("script-src 'self' 'unsafe-inline'"));
+        assert!(!allow_inline_scripts("script-src 'self'"));
+        assert!(!allow_inline_scripts("default-src 'self'"));
+        assert!(!allow_inline_scripts("script-src 'unsafe-inline' 'nonce-abc'"));
+    }
+
+    #[test]
+    fn test_with_script_source() {
+        assert_eq!(
+            with_script_source("default-src 'self'; script-src 'self'", "'sha256-x'"),
+            "default-src 'self'; script-src 'self' 'sha256-x'"
+        );
+        assert_eq!(
+            with_script_source("default-src 'none'", "'sha256-x'"),
+            "default-src 'none' 'sha256-x'"
+        );
+        assert_eq!(with_script_source("img-src *", "'sha256-x'"), "img-src *");
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(is_nonce("aB3+/_-="));
+        assert!(!is_nonce("abc\" onload=\"alert(1)"));
+        assert!(!is_nonce(""));
+        assert!(is_js_identifier("csrfToken"));
+        assert!(is_js_identifier("_$token1"));
+        assert!(!is_js_identifier("1token"));
+        assert!(!is_js_identifier("a=alert(1);b"));
+        assert!(!is_js_identifier(""));
+    }
+}
@@ -0,0 +1,66 @@
+use rocket::data::{self, DataStream, FromData};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::{Data, Request};
+use std::io::{self, Chain, Cursor, Read};
+
+use csrf_form::body_token_valid;
+use csrf_state::CsrfState;
+use error::CsrfError;
+use stripped_form::DEFAULT_FORM_LIMIT;
+
+/// Data guard verifying the csrf token of an urlencoded body, then giving the whole body to the
+/// route.
+///
+/// The `CsrfBody` type read the begining of the body, up to the limit set with
+/// [`set_max_body_scan`] (Rocket's `forms` limit by default), and look for the token field in it.
+/// Requests without a valid token there fail with `403 Forbidden`. Otherwise, the route reads
+/// the body from the guard as it would from `Data`: what was scanned, then the rest of the
+/// stream, so bodies larger than the limit are never held in memory whole.
+///
+/// Contrary to [CsrfForm](struct.CsrfForm.html), it doesn't parse the form, so it suits routes
+/// parsing large bodies themselves. Like it, it is meant for routes on which the fairing defer
+/// body verification, see [`set_deferred_verification_prefix`], as the fairing only see the
+/// first 4Kio of a body.
+///
+/// [`set_max_body_scan`]: struct.CsrfFairingBuilder.html#method.set_max_body_scan
+/// [`set_deferred_verification_prefix`]: struct.CsrfFairingBuilder.html#method.set_deferred_verification_prefix
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[post("/import", data = "<body>")]
+/// fn import(body: CsrfBody) -> io::Result<String> {
+///     let mut records = String::new();
+///     body.take(1 << 20).read_to_string(&mut records)?;
+///     //...
+/// }
+/// ```
+pub struct CsrfBody(Chain<Cursor<Vec<u8>>, DataStream>);
+
+impl Read for CsrfBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl FromData for CsrfBody {
+    type Error = CsrfError;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, CsrfError> {
+        let limit = CsrfState::from(request)
+            .ok()
+            .and_then(|state| state.max_body_scan)
+            .or_else(|| request.limits().get("forms"))
+            .unwrap_or(DEFAULT_FORM_LIMIT);
+        let mut stream = data.open();
+        let mut scanned = Vec::new();
+        if let Err(e) = (&mut stream).take(limit).read_to_end(&mut scanned) {
+            return Outcome::Failure((Status::BadRequest, CsrfError::Io(e)));
+        }
+        if !body_token_valid(request, &String::from_utf8_lossy(&scanned)) {
+            return Outcome::Failure((Status::Forbidden, CsrfError::InvalidToken));
+        } //a token cut by the limit is not valid either
+        Outcome::Success(CsrfBody(Cursor::new(scanned).chain(stream)))
+    }
+}
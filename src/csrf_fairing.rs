@@ -1,23 +1,60 @@
-use csrf::{AesGcmCsrfProtection, CsrfProtection,
-        CSRF_COOKIE_NAME, CSRF_FORM_FIELD};
-use data_encoding::{BASE64, BASE64URL_NOPAD};
 use rand::prelude::thread_rng;
 use rand::Rng;
-use rocket::fairing::{Fairing, Info, Kind};
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
 use rocket::http::uri::URI as Uri;
 use rocket::http::Method::{self, *};
+use rocket::http::{ContentType, SameSite, Status};
 use rocket::outcome::Outcome;
-use rocket::response::Body::Sized;
-use rocket::{Data, Request, Response, Rocket, State};
+use rocket::response::Body::{self, Sized};
+use rocket::config::{Config, Environment};
+use rocket::{Data, Request, Response, Rocket};
 use std::collections::HashMap;
 use std::env;
 use std::io::{Cursor, Read};
+use std::path::PathBuf;
 use std::str::from_utf8;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use zeroize::Zeroizing;
 
-use csrf_proxy::CsrfProxy;
+use binding::TokenBinding;
+use buffer_pool::BufferPool;
+use cors::{is_preflight, CorsAware};
+use csrf_json::{JSON_TOKEN_FIELD, TOKEN_HEADER};
+use csp::{allow_script, is_js_identifier, CspInfo, JsGlobalMode};
+use content_encoding::{decoder, encoder, is_supported, normalize};
+use csrf_proxy::{is_ascii_compatible, AmpMode, CsrfProxy, OPT_OUT_ATTRIBUTE};
+use csrf_state::{CookieOptions, CookiePrefix, CsrfState, SessionFn, SlidingExpiration};
+use csrf_field::{set_current_cookie, set_current_field};
+use error::{CsrfConfigError, CsrfError};
+use exempt::{mounted_paths, ExemptRoute};
+use keyring::CsrfKeyring;
+use csrf_token::{cookie_changed, parse_cookie, raw_cookie, renew_cookie};
+use csrf_token::{set_cookie_changed, set_current_token};
 use csrf_token::CsrfToken;
+use legacy::LegacyFormat;
+use metrics::CsrfMetrics;
+use no_auto_insert::NO_AUTO_INSERT_HEADER;
+use nonce::NonceStore;
+use origin::{FetchMetadataPolicy, OriginCheck};
 use path::Path;
-use utils::parse_args;
+use pattern::Pattern;
+use settings::{default_target, exception, prefixes, Settings, CONFIG_TABLE};
+use report::{log_report, report_route, ReportHandler, ReportState, ViolationReport};
+use secret::{decode_secret, derive_secret, rocket_secret, FileSecretProvider, SecretProvider};
+use policy::{
+    content_length, is_parsed, BodyTokenPolicy, CsrfPolicy, CustomHeaderPolicy, DeferredPolicy,
+    HeaderTokenPolicy, LegacyPolicy, OriginPolicy, PolicyContext, PolicyOutcome,
+};
+use token::{CookieSecret, CsrfBackend, TokenScheme, FORM_FIELD};
+use violation::{
+    set_current_violation, violation_route, Enforcement, ViolationAction, ViolationCallback,
+    ViolationReason, VIOLATION_ROUTE,
+};
+use utils::{now, sample_ratio};
+
+const CONFIG_SECRET_KEY: &str = "csrf_secret_key";
+const METHOD_OVERRIDE_HEADER: &str = "X-HTTP-Method-Override";
 
 
 /// Builder for [CsrfFairing](struct.CsrfFairing.html)
@@ -53,15 +90,112 @@ use utils::parse_args;
 ///         .launch();
 /// # }
 /// ```
-
+///
+/// ## Configuration
+///
+/// When the fairing is attached, settings from a `[csrf]` table of Rocket's configuration
+/// override the ones of the builder, so they can change from an environment to another without
+/// recompiling. Unknown keys and invalid values make attaching fail.
+///
+/// ```toml
+/// [production.csrf]
+/// timeout = 3600
+/// default_target = "/csrf-violation"
+/// default_target_method = "GET"
+/// exceptions = [{ source = "/legacy/<id>", target = "/error?id=<id>", method = "GET" }]
+/// auto_insert = true
+/// auto_insert_disable_prefix = ["/api"]
+/// auto_insert_max_chunk_size = 16384
+///
+/// [production.csrf.cookie]
+/// name = "csrf"
+/// same_site = "strict"
+/// secure = true
+/// http_only = true
+/// path = "/"
+/// domain = "example.com"
+/// prefix = "secure" # or "host", or "none"
+/// ```
+///
+/// ## Environments
+///
+/// Some defaults follow the environment Rocket runs in. In development, violations are logged as
+/// warnings rather than debug events, and random secrets and insecure cookies are fine. In staging
+/// and production, attaching fails unless a persistent secret is set and the cookie is `Secure`.
+/// Each of these can be forced with [`set_require_secret`], [`set_require_secure_cookie`] and
+/// [`set_verbose`].
+///
+/// [`set_require_secret`]: #method.set_require_secret
+/// [`set_require_secure_cookie`]: #method.set_require_secure_cookie
+/// [`set_verbose`]: #method.set_verbose
 pub struct CsrfFairingBuilder {
     duration: i64,
+    grace_period: i64,
+    prefix_timeouts: Vec<(String, i64)>,
     default_target: (String, Method),
     exceptions: Vec<(String, String, Method)>,
-    secret: Option<[u8; 32]>,
+    secrets: Vec<[u8; 32]>,
+    secret_provider: Option<Arc<SecretProvider>>,
     auto_insert: bool,
     auto_insert_disable_prefix: Vec<String>,
     auto_insert_max_size: u64,
+    preserve_content_length: bool,
+    legacy_formats: Vec<LegacyFormat>,
+    legacy_deadline: Option<i64>,
+    report_only_prefix: Vec<String>,
+    enforcement_sample_rate: f32,
+    amp_mode: AmpMode,
+    auto_insert_status: Vec<Status>,
+    max_body_size: Option<u64>,
+    max_body_scan: Option<u64>,
+    unique_token_per_form: bool,
+    action_bound_tokens: bool,
+    form_origins: Vec<String>,
+    opt_out_attribute: String,
+    field_template: Option<String>,
+    sliding_expiration: SlidingExpiration,
+    js_global: Option<String>,
+    js_global_mode: JsGlobalMode,
+    meta_tag: bool,
+    param_meta_tag: bool,
+    htmx: bool,
+    custom_header: Option<(String, Option<String>)>,
+    custom_header_prefix: Vec<String>,
+    static_prefix: Vec<String>,
+    deferred_verification_prefix: Vec<String>,
+    locales: Vec<String>,
+    token_header: String,
+    json_token_field: Option<String>,
+    json_max_depth: usize,
+    body_content_types: Vec<ContentType>,
+    violation_action: ViolationAction,
+    cookie_options: CookieOptions,
+    field_name: String,
+    token_cookie: Option<String>,
+    origin_check: OriginCheck,
+    trusted_origins: Vec<String>,
+    fetch_metadata_policy: FetchMetadataPolicy,
+    policies: Vec<Box<CsrfPolicy>>,
+    exempt_routes: Vec<&'static ExemptRoute>,
+    exempt_methods: Vec<Method>,
+    exempt_mounts: Vec<String>,
+    safe_methods: Vec<Method>,
+    method_override: bool,
+    enforcement: Enforcement,
+    violation_callback: Option<ViolationCallback>,
+    report_uri: Option<String>,
+    report_handler: Option<ReportHandler>,
+    report_limit: u32,
+    metrics: Option<Arc<CsrfMetrics>>,
+    keyring: Option<CsrfKeyring>,
+    backend: CsrfBackend,
+    token_scheme: TokenScheme,
+    nonce_store: Option<Arc<NonceStore>>,
+    session: Option<SessionFn>,
+    token_binding: TokenBinding,
+    require_secret: Option<bool>,
+    require_secure_cookie: Option<bool>,
+    verbose: Option<bool>,
 }
 
 impl CsrfFairingBuilder {
@@ -69,12 +203,72 @@ impl CsrfFairingBuilder {
     pub fn new() -> Self {
         CsrfFairingBuilder {
             duration: 60 * 60,
+            grace_period: 0,
+            prefix_timeouts: Vec::new(),
             default_target: (String::from("/"), Get),
             exceptions: Vec::new(),
-            secret: None,
+            secrets: Vec::new(),
+            secret_provider: None,
             auto_insert: true,
             auto_insert_disable_prefix: Vec::new(),
             auto_insert_max_size: 16 * 1024,
+            preserve_content_length: false,
+            legacy_formats: Vec::new(),
+            legacy_deadline: None,
+            report_only_prefix: Vec::new(),
+            enforcement_sample_rate: 1.0,
+            amp_mode: AmpMode::Inject,
+            auto_insert_status: vec![Status::Ok, Status::NonAuthoritativeInformation],
+            max_body_size: None,
+            max_body_scan: None,
+            unique_token_per_form: false,
+            action_bound_tokens: false,
+            form_origins: Vec::new(),
+            opt_out_attribute: OPT_OUT_ATTRIBUTE.to_owned(),
+            field_template: None,
+            sliding_expiration: SlidingExpiration::OnEveryRequest,
+            js_global: None,
+            js_global_mode: JsGlobalMode::InlineScript,
+            meta_tag: false,
+            param_meta_tag: false,
+            htmx: false,
+            custom_header: None,
+            custom_header_prefix: Vec::new(),
+            static_prefix: Vec::new(),
+            deferred_verification_prefix: Vec::new(),
+            locales: Vec::new(),
+            token_header: TOKEN_HEADER.to_owned(),
+            json_token_field: None,
+            json_max_depth: 0,
+            body_content_types: Vec::new(),
+            violation_action: ViolationAction::Reroute,
+            cookie_options: CookieOptions::default(),
+            field_name: FORM_FIELD.to_owned(),
+            token_cookie: None,
+            origin_check: OriginCheck::Off,
+            trusted_origins: Vec::new(),
+            fetch_metadata_policy: FetchMetadataPolicy::Off,
+            policies: Vec::new(),
+            exempt_routes: Vec::new(),
+            exempt_methods: Vec::new(),
+            exempt_mounts: Vec::new(),
+            safe_methods: vec![Get, Head, Connect, Options],
+            method_override: false,
+            enforcement: Enforcement::Enforce,
+            violation_callback: None,
+            report_uri: None,
+            report_handler: None,
+            report_limit: 60,
+            metrics: None,
+            keyring: None,
+            backend: CsrfBackend::default(),
+            token_scheme: TokenScheme::default(),
+            nonce_store: None,
+            session: None,
+            token_binding: TokenBinding::default(),
+            require_secret: None,
+            require_secure_cookie: None,
+            verbose: None,
         }
     }
 
@@ -85,6 +279,35 @@ impl CsrfFairingBuilder {
         self
     }
 
+    /// Set timeouts (in seconds) for tokens of pages under some prefixs, instead of the one set
+    /// with [`set_timeout`](#method.set_timeout), like a shorter one for an admin panel. The first
+    /// matching prefix is used. Globs and regexs are accepted, as for the sources of
+    /// [`set_exceptions`](#method.set_exceptions).
+    ///
+    /// The csrf cookie is shared by every path, tokens of those pages carry their own expiry,
+    /// which is only enforced as long as the cookie is valid. A page with a longer timeout renews
+    /// the cookie with this timeout, but visiting another page afterward may shorten it again.
+    pub fn set_prefix_timeouts(mut self, prefix_timeouts: Vec<(String, i64)>) -> Self {
+        self.prefix_timeouts = prefix_timeouts;
+        self
+    }
+
+    /// Set for how long (in seconds) tokens are still accepted after the timeout of their cookie.
+    /// A request accepted during this grace period renews the cookie, so a user coming back to a
+    /// form left open overnight can submit it once, and then gets tokens with a fresh timeout.
+    /// The cookie is longer when this is set. Default value is 0, without any grace period.
+    pub fn set_grace_period(mut self, grace_period: i64) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Set when the timeout of the csrf cookie is extended. By default it is extended on every
+    /// request, see [SlidingExpiration](enum.SlidingExpiration.html) for alternatives.
+    pub fn set_sliding_expiration(mut self, sliding_expiration: SlidingExpiration) -> Self {
+        self.sliding_expiration = sliding_expiration;
+        self
+    }
+
     /// Set the default route when an invalide request is catched, you may add a <uri> as a segment
     /// or a param to get the percent-encoded original target. You can also set the method of the
     /// route to which you choosed to redirect.
@@ -111,6 +334,32 @@ impl CsrfFairingBuilder {
         self
     }
 
+    /// Set what is done with requests violating csrf protection which match no exception. By
+    /// default they are rerouted to the default target, see
+    /// [ViolationAction](enum.ViolationAction.html) for alternatives.
+    ///
+    /// # Example
+    ///
+    ///  ```rust,no_run
+    /// # extern crate rocket;
+    /// # extern crate rocket_csrf;
+    /// use rocket_csrf::{CsrfFairingBuilder, ViolationAction};
+    /// # use rocket::Rocket;
+    ///
+    /// fn main() {
+    ///     rocket::ignite()
+    ///         .attach(rocket_csrf::CsrfFairingBuilder::new()
+    ///                 .set_violation_action(ViolationAction::Respond(rocket::http::Status::Forbidden))
+    ///                 .finalize().unwrap())
+    ///         //add your routes, other fairings...
+    ///         .launch();
+    /// }
+    /// ```
+    pub fn set_violation_action(mut self, violation_action: ViolationAction) -> Self {
+        self.violation_action = violation_action;
+        self
+    }
+
     /// Set the list of exceptions which will not be redirected to the default route, removing any
     /// previously added exceptions, to juste add exceptions use [`add_exceptions`] instead. A route may
     /// contain dynamic parts noted as <name>, which will be replaced in the target route.
@@ -119,7 +368,13 @@ impl CsrfFairingBuilder {
     /// circunvence this issue, you can add a (not so) exception matching the static route before
     /// the dynamic one, and redirect it to the default target manually.
     ///
+    /// A source may also be a glob, where `*` match a single segment (or part of it) and `**` any
+    /// number of segments, like `/api/**`. With the `regex` feature, sources starting with `^` are
+    /// regexs, matched against the whole uri, whose named groups are given to the target like
+    /// dynamic parts. Patterns are compiled by [`finalize`], which fails on invalid ones.
+    ///
     /// [`add_exceptions`]: #method.add_exceptions
+    /// [`finalize`]: #method.finalize
     ///
     /// # Example
     ///
@@ -154,11 +409,61 @@ impl CsrfFairingBuilder {
         self
     }
 
+    /// Add routes marked with `#[csrf_exempt]`, which are never verified. Get them with the
+    /// [csrf_exempt_routes!](macro.csrf_exempt_routes.html) macro. Contrary to exceptions, no
+    /// path is given: the fairing find where each route is mounted when it is attached, so routes
+    /// must be mounted before the fairing is attached.
+    pub fn add_exempt_routes(mut self, exempt_routes: Vec<&'static ExemptRoute>) -> Self {
+        self.exempt_routes.extend(exempt_routes);
+        self
+    }
+
+    /// Never verify requests sent with this method, for example `Method::Delete` if all your
+    /// `DELETE` routes are called by scripts authenticated by other means. A `POST` tunneling
+    /// this method, through a method override header or a `_method` form field, is still
+    /// verified.
+    pub fn exempt_method(mut self, method: Method) -> Self {
+        self.exempt_methods.push(method);
+        self
+    }
+
+    /// Set the methods which are not verified, and on which the csrf cookie is issued. Requests
+    /// with any other method require a token. Default is `GET`, `HEAD`, `CONNECT` and `OPTIONS`.
+    /// Remove `GET` to protect routes with side effects, but pages would then need a token to be
+    /// displayed, as would the [refresh_routes](fn.refresh_routes.html).
+    pub fn set_safe_methods(mut self, safe_methods: Vec<Method>) -> Self {
+        self.safe_methods = safe_methods;
+        self
+    }
+
+    /// Set if the method tunneled through a `POST` with an `X-HTTP-Method-Override` header is used
+    /// to decide whether a request is verified, instead of the method it was sent with. An
+    /// overridden method only make a request stricter: a `POST` overridden to a safe method is
+    /// still verified, and methods exempted with `exempt_method` are always checked against the
+    /// method the request was sent with. Default is false.
+    pub fn set_method_override(mut self, method_override: bool) -> Self {
+        self.method_override = method_override;
+        self
+    }
+
+    /// Never verify requests on this mount point or below it, for example `/api` for an API
+    /// authenticated by bearer tokens. Contrary to a prefix, `/api` doesn't match `/apis`.
+    pub fn exempt_mount(mut self, mount: String) -> Self {
+        self.exempt_mounts.push(mount);
+        self
+    }
+
     /// Set the secret key used to generate secure cryptographic tokens. If not set, rocket_csrf
-    /// will attempt to get the secret used by Rocket for it's own private cookies via the
-    /// ROCKET_SECRET_KEY environment variable, or will generate a new one at each restart.
-    /// Having the secret key set (via this or Rocket environment variable) allow tokens to keep
-    /// their validity in case of an application restart.
+    /// will look, when the fairing is attached, for a base64 `csrf_secret_key` in Rocket's
+    /// configuration (in `Rocket.toml`, or the `ROCKET_CSRF_SECRET_KEY` environment variable),
+    /// then for the `secret_key` used by Rocket for it's own private cookies, in the
+    /// `ROCKET_SECRET_KEY` environment variable or in `Rocket.toml`, from which a distinct key is
+    /// derived with HKDF, whatever its length, or will generate a new one at each restart, which
+    /// is refused outside of development (see [`set_require_secret`](#method.set_require_secret)).
+    /// Having the secret key set (via this or Rocket configuration) allow tokens to keep
+    /// their validity in case of an application restart. Copies of the secret held by this crate
+    /// are wiped from memory once dropped, but not the one the csrf crate keeps in its ciphers,
+    /// nor the cookie secrets it decrypts.
     ///
     /// # Example
     ///
@@ -178,228 +483,3843 @@ impl CsrfFairingBuilder {
     /// }
     /// ```
     pub fn set_secret(mut self, secret: [u8; 32]) -> Self {
-        self.secret = Some(secret);
+        self.secrets = vec![secret];
         self
     }
 
-    /// Set if this should modify response to insert tokens automatically in all forms. If true,
-    /// this will insert tokens in all forms it encounter, if false, you will have to add them via
-    /// [CsrfFairing](struct.CsrfFairing.html), which you may obtain via request guards.
+    /// Set several secret keys, to rotate them without invalidating every token at once. The
+    /// first one is the primary key, used for new cookies and tokens, while the others are only
+    /// used to verify tokens made before the rotation. Tokens are prefixed by an identifier of
+    /// the key which made them, so verifying doesn't try every key. The same can be done in
+    /// Rocket's configuration, by setting `csrf_secret_key` to an array of base64 keys.
     ///
-    pub fn set_auto_insert(mut self, auto_insert: bool) -> Self {
-        self.auto_insert = auto_insert;
+    /// A rotation is done by adding the new key at the front, then removing the old one once the
+    /// cookies it encrypted have expired, which is the timeout set with
+    /// [`set_timeout`](#method.set_timeout) after the rotation at most. An empty list has the
+    /// same effect as not setting any secret.
+    pub fn set_secrets(mut self, secrets: Vec<[u8; 32]>) -> Self {
+        self.secrets = secrets;
         self
     }
 
-    /// Set prefixs for which this will not try to add tokens in forms. This has no effect if
-    /// auto_insert is set to false. Not having to parse response on paths witch don't need it may
-    /// improve performances, but not that only html documents are parsed, so it's not usefull to
-    /// use it on routes containing only images or stillsheets.
-    pub fn set_auto_insert_disable_prefix(mut self, auto_insert_prefix: Vec<String>) -> Self {
-        self.auto_insert_disable_prefix = auto_insert_prefix;
+    /// Get the secret keys from a provider, like Vault or a KMS, each time the fairing is
+    /// attached. It takes precedence over secrets from Rocket's configuration and the
+    /// environment, but not over [`set_secret`](#method.set_secret) and
+    /// [`set_secrets`](#method.set_secrets). Attaching fails if the provider fails. See
+    /// [SecretProvider](trait.SecretProvider.html).
+    pub fn set_secret_provider<P: SecretProvider + 'static>(mut self, provider: P) -> Self {
+        self.secret_provider = Some(Arc::new(provider));
         self
     }
 
-    /// Set the maximum size of a request before it get send chunked. A request will need at most
-    /// this additional memory for the buffer used to parse and tokens into forms. This have no
-    /// effect if auto_insert is set to false. Default value is 16Kio
-    pub fn set_auto_insert_max_chunk_size(mut self, chunk_size: u64) -> Self {
-        self.auto_insert_max_size = chunk_size;
+    /// Read the secret keys from a file each time the fairing is attached, like a secret mounted
+    /// by Docker or Kubernetes. The file holds base64 keys, one per line, the primary one first,
+    /// as [`set_secrets`](#method.set_secrets) would take them. This replaces any
+    /// [`set_secret_provider`](#method.set_secret_provider).
+    pub fn set_secret_from_file(self, path: PathBuf) -> Self {
+        self.set_secret_provider(FileSecretProvider { path })
+    }
+
+    /// Use a keyring created beforehand, to change secrets while the server is running. It
+    /// takes precedence over any other secret. See [CsrfKeyring](struct.CsrfKeyring.html).
+    pub fn set_keyring(mut self, keyring: CsrfKeyring) -> Self {
+        self.keyring = Some(keyring);
         self
     }
 
-    /// Get the fairing from the builder.
-    pub fn finalize(self) -> Result<CsrfFairing, ()> {
-        let secret = self.secret.unwrap_or_else(|| {
-            //use provided secret if one is
-            env::vars()
-                .find(|(key, _)| key == "ROCKET_SECRET_KEY")
-                .and_then(|(_, value)| {
-                    let b64 = BASE64.decode(value.as_bytes());
-                    if let Ok(b64) = b64 {
-                        if b64.len() == 32 {
-                            let mut array = [0; 32];
-                            array.copy_from_slice(&b64);
-                            Some(array)
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                })//else get secret environment variable
-                .unwrap_or_else(|| {
-                    eprintln!("[rocket_csrf] No secret key was found, you should consider set one to keep token validity across application restart");
-                    thread_rng().gen()
-                }) //if environment variable is not set, generate a random secret and print a warning
-        });
-
-        let default_target = Path::from(&self.default_target.0);
-        let mut hashmap = HashMap::new();
-        hashmap.insert("uri", "".to_owned());
-        if default_target.map(&hashmap).is_none() {
-            return Err(());
-        } //verify if this path is valid as default path, i.e. it have at most one dynamic part which is <uri>
-        Ok(CsrfFairing {
-            duration: self.duration,
-            default_target: (default_target, self.default_target.1),
-            exceptions: self
-                .exceptions
-                .iter()
-                .map(|(a, b, m)| (Path::from(&a), Path::from(&b), *m))//TODO verify if source and target are compatible
-                .collect(),
-            secret,
-            auto_insert: self.auto_insert,
-            auto_insert_disable_prefix: self.auto_insert_disable_prefix,
-            auto_insert_max_size: self.auto_insert_max_size,
-        })
+    /// Set the cryptographic primitive protecting cookies and tokens, see
+    /// [CsrfBackend](enum.CsrfBackend.html). Default is AES-GCM.
+    pub fn set_backend(mut self, backend: CsrfBackend) -> Self {
+        self.backend = backend;
+        self
     }
-}
 
-impl Default for CsrfFairingBuilder {
-    fn default() -> Self {
-        Self::new()
+    /// Set how cookies and tokens are made, see [TokenScheme](enum.TokenScheme.html). Default is
+    /// an encrypted pair.
+    pub fn set_token_scheme(mut self, token_scheme: TokenScheme) -> Self {
+        self.token_scheme = token_scheme;
+        self
     }
-}
 
-/// Fairing to protect against Csrf attacks.
-///
-/// The `CsrfFairing` type protect a Rocket instance against Csrf attack by requesting mendatory
-/// token on any POST, PUT, DELETE or PATCH request.
-/// This is created via a [CsrfFairingBuilder](struct.CsrfFairingBuilder.html), and implement nothing else than the `Fairing` trait.
-///
-/// [`CsrfFairingBuilder`]: /rocket_csrf/struct.CsrfFairing.html
-pub struct CsrfFairing {
-    duration: i64,
-    default_target: (Path, Method),
-    exceptions: Vec<(Path, Path, Method)>,
-    secret: [u8; 32],
-    auto_insert: bool,
-    auto_insert_disable_prefix: Vec<String>,
-    auto_insert_max_size: u64,
-}
+    /// Make tokens valid only once, remembering used ones in this store. A replayed token is a
+    /// violation, so each form needs a token of its own, see
+    /// [`set_unique_token_per_form`](#method.set_unique_token_per_form), and a page must be
+    /// reloaded to submit its form again. Only tokens verified by the fairing are consumed, not
+    /// the ones verified by guards like `CsrfForm`. See [NonceStore](trait.NonceStore.html).
+    pub fn set_nonce_store<S: NonceStore + 'static>(mut self, store: S) -> Self {
+        self.nonce_store = Some(Arc::new(store));
+        self
+    }
 
-impl Fairing for CsrfFairing {
-    fn info(&self) -> Info {
-        if self.auto_insert {
-            Info {
-                name: "CSRF protection",
-                kind: Kind::Attach | Kind::Request | Kind::Response,
-            }
-        } else {
-            Info {
-                name: "CSRF protection",
-                kind: Kind::Attach | Kind::Request,
-            }
-        }
+    /// Bind tokens to the session of the application. The function returns an identifier of the
+    /// session of a request, like a session id from a private cookie, or `None` when there is no
+    /// session. Tokens are only valid with the session they were made for, so a token leaked
+    /// from a session can't be used in another one, and tokens made before logging in are not
+    /// valid afterward.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// CsrfFairingBuilder::new()
+    ///     .set_session_binding(|request| {
+    ///         request
+    ///             .cookies()
+    ///             .get_private("session")
+    ///             .map(|cookie| cookie.value().as_bytes().to_vec())
+    ///     })
+    /// ```
+    pub fn set_session_binding<F>(mut self, session: F) -> Self
+    where
+        F: Fn(&Request) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.session = Some(Arc::new(session));
+        self
     }
 
-    fn on_attach(&self, rocket: Rocket) -> Result<Rocket, Rocket> {
-        Ok(rocket.manage((AesGcmCsrfProtection::from_key(self.secret), self.duration))) //add the Csrf engine to Rocket's managed state
+    /// Bind tokens to attributes of the request, like the IP address of the client, in addition
+    /// to the session if [`set_session_binding`](#method.set_session_binding) is used. See
+    /// [TokenBinding](struct.TokenBinding.html). Default is to bind to nothing.
+    pub fn set_token_binding(mut self, token_binding: TokenBinding) -> Self {
+        self.token_binding = token_binding;
+        self
     }
 
-    fn on_request(&self, request: &mut Request, data: &Data) {
-        match request.method() {
-            Get | Head | Connect | Options => {
-                let _ = request.guard::<CsrfToken>(); //force regeneration of csrf cookies
-                return;
-            }
-            _ => {}
-        };
+    /// Set the maximum size of the body of a protected request. Requests announcing a larger
+    /// `Content-Length` are treated as violations right away, without looking at their body. By
+    /// default there is no limit, other than Rocket's `forms` data limit for urlencoded forms.
+    /// Violations caused by a limit are logged with a distinct reason, so they can be told apart
+    /// from forged requests.
+    pub fn set_max_body_size(mut self, max_body_size: u64) -> Self {
+        self.max_body_size = Some(max_body_size);
+        self
+    }
 
-        let (csrf_engine, _) = request
-            .guard::<State<(AesGcmCsrfProtection, i64)>>()
-            .unwrap()
-            .inner();
+    /// Set how many bytes of an urlencoded body the [CsrfBody](struct.CsrfBody.html) data guard
+    /// reads looking for the token, before giving the whole body to the route. The fairing only
+    /// sees the first 4Kio of a body, so use this guard on paths set with
+    /// [`set_deferred_verification_prefix`](#method.set_deferred_verification_prefix) for forms
+    /// whose token may come later. Default is Rocket's `forms` data limit.
+    pub fn set_max_body_scan(mut self, max_body_scan: u64) -> Self {
+        self.max_body_scan = Some(max_body_scan);
+        self
+    }
 
-        let cookie = request
-            .cookies()
-            .get(CSRF_COOKIE_NAME)
-            .and_then(|cookie| BASE64.decode(cookie.value().as_bytes()).ok())
-            .and_then(|cookie| csrf_engine.parse_cookie(&cookie).ok()); //get and parse Csrf cookie
+    /// Accept requests carrying a custom header as safe, without requiring a token. Browsers don't
+    /// allow cross origin requests to set custom headers unless a CORS preflight authorized it, so
+    /// for pure JSON APIs this is enough protection, as long as your CORS policy is strict. If a
+    /// value is given, the header must have exactly this value, otherwise any value is accepted.
+    /// Use [`set_custom_header_prefix`] to restrict this to your API.
+    ///
+    /// [`set_custom_header_prefix`]: #method.set_custom_header_prefix
+    ///
+    /// # Example
+    ///
+    ///  ```rust,no_run
+    /// # extern crate rocket;
+    /// # extern crate rocket_csrf;
+    /// use rocket_csrf::CsrfFairingBuilder;
+    /// # use rocket::Rocket;
+    ///
+    /// fn main() {
+    ///     rocket::ignite()
+    ///         .attach(rocket_csrf::CsrfFairingBuilder::new()
+    ///                 .set_custom_header("X-Requested-With".to_owned(), Some("XMLHttpRequest".to_owned()))
+    ///                 .set_custom_header_prefix(vec!["/api/".to_owned()])
+    ///                 .finalize().unwrap())
+    ///         //add your routes, other fairings...
+    ///         .launch();
+    /// }
+    /// ```
+    pub fn set_custom_header(mut self, header: String, value: Option<String>) -> Self {
+        self.custom_header = Some((header, value));
+        self
+    }
 
-        let _ = request.guard::<CsrfToken>(); //force regeneration of csrf cookies
+    /// Set how the `Origin` and `Referer` headers of protected requests are checked, see
+    /// [OriginCheck](enum.OriginCheck.html). Default is `OriginCheck::Off`.
+    pub fn set_origin_check(mut self, origin_check: OriginCheck) -> Self {
+        self.origin_check = origin_check;
+        self
+    }
 
-        let token = parse_args(from_utf8(data.peek()).unwrap_or(""))
-            .filter(|(key, _)| key == &CSRF_FORM_FIELD)
-            .filter_map(|(_, token)| BASE64URL_NOPAD.decode(&token.as_bytes()).ok())
-            .filter_map(|token| csrf_engine.parse_token(&token).ok())
-            .next(); //get and parse Csrf token
+    /// Set the origins trusted by the origin check, such as `https://app.example.com`, in
+    /// addition to the origin of the application itself.
+    pub fn set_trusted_origins(mut self, trusted_origins: Vec<String>) -> Self {
+        self.trusted_origins = trusted_origins;
+        self
+    }
 
-        if let Some(token) = token {
-            if let Some(cookie) = cookie {
-                if csrf_engine.verify_token_pair(&token, &cookie) {
-                    return; //if we got both token and cookie, and they match each other, we do nothing
-                }
-            }
-        }
+    /// Set how the `Sec-Fetch-Site` header of protected requests is used, see
+    /// [FetchMetadataPolicy](enum.FetchMetadataPolicy.html). Default is
+    /// `FetchMetadataPolicy::Off`.
+    pub fn set_fetch_metadata_policy(mut self, fetch_metadata_policy: FetchMetadataPolicy) -> Self {
+        self.fetch_metadata_policy = fetch_metadata_policy;
+        self
+    }
 
-        //Request reaching here are violating Csrf protection
+    /// Add a policy to the chain run on protected requests, see
+    /// [CsrfPolicy](trait.CsrfPolicy.html). Policies added this way run in the order they were
+    /// added, after the checks of headers (custom header, `Sec-Fetch-Site`, origin, header
+    /// token) and deferred verification, and before the checks of the body.
+    pub fn add_policy<P: CsrfPolicy + 'static>(mut self, policy: P) -> Self {
+        self.policies.push(Box::new(policy));
+        self
+    }
 
-        for (src, dst, method) in &self.exceptions {
-            if let Some(param) = src.extract(&request.uri().to_string()) {
-                if let Some(destination) = dst.map(&param) {
-                    request.set_uri(destination);
-                    request.set_method(*method);
-                    return;
-                }
-            }
-        }
+    /// Configure the fairing for single page applications following the convention of Angular
+    /// and axios: the token is given to scripts in a `XSRF-TOKEN` cookie readable from
+    /// javascript, and must be sent back in the `X-XSRF-TOKEN` header. Auto-insert is disabled.
+    ///
+    /// The readable cookie only hold a token, the secret it is verified against stay in the
+    /// HttpOnly csrf cookie.
+    ///
+    /// # Example
+    ///
+    ///  ```rust,no_run
+    /// # extern crate rocket;
+    /// # extern crate rocket_csrf;
+    /// use rocket_csrf::CsrfFairingBuilder;
+    /// # use rocket::Rocket;
+    ///
+    /// fn main() {
+    ///     rocket::ignite()
+    ///         .attach(rocket_csrf::CsrfFairingBuilder::new()
+    ///                 .spa_mode()
+    ///                 .finalize().unwrap())
+    ///         //add your routes, other fairings...
+    ///         .launch();
+    /// }
+    /// ```
+    pub fn spa_mode(mut self) -> Self {
+        self.token_cookie = Some("XSRF-TOKEN".to_owned());
+        self.token_header = "X-XSRF-TOKEN".to_owned();
+        self.auto_insert = false;
+        self
+    }
 
-        //if request matched no exception, reroute it to default target
+    /// Configure the fairing for [htmx](https://htmx.org) applications: the token is given in a
+    /// `<meta name="csrf-token">` element and in an `hx-headers` attribute of `<body>`, so every
+    /// request made by htmx sends it back in the `X-CSRF-Token` header without custom javascript.
+    ///
+    /// Responses to htmx requests, which have a `HX-Request` header, are fragments swapped into
+    /// a page which already has the token: only forms they contain get a token field, nothing is
+    /// inserted in `<head>` or `<body>`.
+    ///
+    /// # Example
+    ///
+    ///  ```rust,no_run
+    /// # extern crate rocket;
+    /// # extern crate rocket_csrf;
+    /// use rocket_csrf::CsrfFairingBuilder;
+    /// # use rocket::Rocket;
+    ///
+    /// fn main() {
+    ///     rocket::ignite()
+    ///         .attach(rocket_csrf::CsrfFairingBuilder::new()
+    ///                 .htmx()
+    ///                 .finalize().unwrap())
+    ///         //add your routes, other fairings...
+    ///         .launch();
+    /// }
+    /// ```
+    pub fn htmx(mut self) -> Self {
+        self.meta_tag = true;
+        self.htmx = true;
+        self.token_header = TOKEN_HEADER.to_owned();
+        self
+    }
 
-        let uri = request.uri().to_string();
-        let uri = Uri::percent_encode(&uri);
-        let mut param: HashMap<&str, String> = HashMap::new();
-        param.insert("uri", uri.to_string());
-        request.set_uri(self.default_target.0.map(&param).unwrap());
-        request.set_method(self.default_target.1)
+    /// Configure the fairing for [Turbo](https://turbo.hotwired.dev) and other tools following
+    /// the conventions of Rails: the name of the form field and the token are given in
+    /// `<meta name="csrf-param">` and `<meta name="csrf-token">` elements, and the token is
+    /// accepted in the `X-CSRF-Token` header. Turbo Drive form submissions and `fetch` calls of
+    /// Stimulus controllers (with `@rails/request.js` for example) then work without extra code.
+    ///
+    /// # Example
+    ///
+    ///  ```rust,no_run
+    /// # extern crate rocket;
+    /// # extern crate rocket_csrf;
+    /// use rocket_csrf::CsrfFairingBuilder;
+    /// # use rocket::Rocket;
+    ///
+    /// fn main() {
+    ///     rocket::ignite()
+    ///         .attach(rocket_csrf::CsrfFairingBuilder::new()
+    ///                 .turbo()
+    ///                 .finalize().unwrap())
+    ///         //add your routes, other fairings...
+    ///         .launch();
+    /// }
+    /// ```
+    pub fn turbo(mut self) -> Self {
+        self.meta_tag = true;
+        self.param_meta_tag = true;
+        self.token_header = TOKEN_HEADER.to_owned();
+        self
     }
 
-    fn on_response<'a>(&self, request: &Request, response: &mut Response<'a>) {
-        if let Some(ct) = response.content_type() {
-            if !ct.is_html() {
-                return;
-            }
-        } //if content type is not html, we do nothing
+    /// Set the name of the csrf cookie. Default is `csrf`.
+    pub fn set_cookie_name(mut self, cookie_name: String) -> Self {
+        self.cookie_options.name = cookie_name;
+        self
+    }
 
-        let uri = request.uri().to_string();
-        if self
-            .auto_insert_disable_prefix
-            .iter()
-            .any(|prefix| uri.starts_with(prefix))
-        {
-            return;
-        } //if request is on an ignored prefix, ignore it
+    /// Set the name of the form field holding the token, in inserted fields and submitted forms.
+    /// Default is `csrf-token`.
+    pub fn set_field_name(mut self, field_name: String) -> Self {
+        self.field_name = field_name;
+        self
+    }
 
-        let token = match request.guard::<CsrfToken>() {
-            Outcome::Success(t) => t,
-            _ => return,
-        }; //if we can't get a token, leave request unchanged, we can't do anything anyway
+    /// Set the `SameSite` attribute of the csrf cookie. Default is `Lax`.
+    pub fn set_cookie_same_site(mut self, same_site: SameSite) -> Self {
+        self.cookie_options.same_site = same_site;
+        self
+    }
 
-        let body = response.take_body(); //take request body from Rocket
-        if body.is_none() {
-            return;
-        } //if there was no body, leave it that way
-        let body = body.unwrap();
-
-        if let Sized(body_reader, len) = body {
-            if len <= self.auto_insert_max_size {
-                //if this is a small enought body, process the full body
-                let mut res = Vec::with_capacity(len as usize);
-                CsrfProxy::from(body_reader, &token)
-                    .read_to_end(&mut res)
-                    .unwrap();
-                response.set_sized_body(Cursor::new(res));
-            } else {
-                //if body is of known but long size, change it to a stream to preserve memory, by encapsulating it into our "proxy" struct
-                let body = body_reader;
-                response.set_streamed_body(Box::new(CsrfProxy::from(body, &token)));
-            }
-        } else {
-            //if body is of unknown size, encapsulate it into our "proxy" struct
-            let body = body.into_inner();
-            response.set_streamed_body(Box::new(CsrfProxy::from(body, &token)));
-        }
+    /// Set if the csrf cookie has the `Secure` attribute, so it's only sent over https. Enable it
+    /// when the application is only served over https, including behind a TLS terminating proxy.
+    /// Default is false.
+    pub fn set_cookie_secure(mut self, secure: bool) -> Self {
+        self.cookie_options.secure = secure;
+        self
+    }
+
+    /// Require a persistent secret key, set with [`set_secret`](#method.set_secret) or in Rocket's
+    /// configuration, as `csrf_secret_key` or `secret_key`, instead of generating one at each
+    /// restart.
+    /// Attaching fails without one. Default is to require it in staging and production.
+    pub fn set_require_secret(mut self, require: bool) -> Self {
+        self.require_secret = Some(require);
+        self
+    }
+
+    /// Require the csrf cookie to have the `Secure` attribute, else attaching fails. Default is to
+    /// require it in staging and production.
+    pub fn set_require_secure_cookie(mut self, require: bool) -> Self {
+        self.require_secure_cookie = Some(require);
+        self
+    }
+
+    /// Log enforced violations as warnings instead of debug events. Default is to do so in
+    /// development only.
+    pub fn set_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = Some(verbose);
+        self
+    }
+
+    /// Set if the csrf cookie has the `HttpOnly` attribute. Scripts never need to read the cookie,
+    /// only the token, so this default to true.
+    pub fn set_cookie_http_only(mut self, http_only: bool) -> Self {
+        self.cookie_options.http_only = http_only;
+        self
+    }
+
+    /// Set the `Path` attribute of the csrf cookie. Default is `/`.
+    pub fn set_cookie_path(mut self, path: String) -> Self {
+        self.cookie_options.path = path;
+        self
+    }
+
+    /// Set the `Domain` attribute of the csrf cookie, to share it with subdomains. By default it
+    /// has none, and is only sent to the host which set it.
+    pub fn set_cookie_domain(mut self, domain: String) -> Self {
+        self.cookie_options.domain = Some(domain);
+        self
+    }
+
+    /// Set the prefix of the name of the csrf cookie, `__Secure-` or `__Host-`, and the
+    /// attributes it requires, whatever the other cookie options: `Secure`, and with `__Host-`
+    /// the `/` path and no domain. Default is no prefix. See
+    /// [CookiePrefix](enum.CookiePrefix.html).
+    pub fn set_cookie_prefix(mut self, prefix: CookiePrefix) -> Self {
+        self.cookie_options.prefix = prefix;
+        self
+    }
+
+    /// Set the name of the header in which clients may send the token instead of the body, for
+    /// javascript and JSON clients. Default is `X-CSRF-Token`. The same header is used by the
+    /// [CsrfJson](struct.CsrfJson.html) data guard.
+    pub fn set_token_header_name(mut self, token_header: String) -> Self {
+        self.token_header = token_header;
+        self
+    }
+
+    /// Set the field holding the token in JSON bodies. The body of `application/json` requests is
+    /// parsed and the token searched in this field instead of urlencoded form fields, as the
+    /// [CsrfJson](struct.CsrfJson.html) data guard does. Default is `csrf_token`.
+    /// Only the part of the body peeked by Rocket (4Kio) is parsed, so the token is not found in
+    /// larger bodies; send it with [`set_token_header_name`] in that case.
+    ///
+    /// [`set_token_header_name`]: #method.set_token_header_name
+    pub fn set_json_token_field(mut self, json_token_field: String) -> Self {
+        self.json_token_field = Some(json_token_field);
+        self
+    }
+
+    /// Set other content types whose body is searched for the token like urlencoded forms, for
+    /// example `text/plain` for old clients. Tokens are only searched in the body of urlencoded
+    /// forms, of JSON requests (see [`set_json_token_field`]), and of these types. Protected
+    /// requests with a body of another type are violations with the
+    /// `UnsupportedContentType` reason, unless another check accepts them, like a token header.
+    ///
+    /// [`set_json_token_field`]: #method.set_json_token_field
+    pub fn set_body_content_types(mut self, body_content_types: Vec<ContentType>) -> Self {
+        self.body_content_types = body_content_types;
+        self
+    }
+
+    /// Set how deep in nested objects and arrays the JSON token field is searched. Default is 0,
+    /// only looking at the field of the top level object.
+    pub fn set_json_max_depth(mut self, json_max_depth: usize) -> Self {
+        self.json_max_depth = json_max_depth;
+        self
+    }
+
+    /// Set prefixs on which [`set_custom_header`] apply. If empty, which is the default, it apply
+    /// everywhere.
+    ///
+    /// [`set_custom_header`]: #method.set_custom_header
+    pub fn set_custom_header_prefix(mut self, custom_header_prefix: Vec<String>) -> Self {
+        self.custom_header_prefix = custom_header_prefix;
+        self
+    }
+
+    /// Set if this should modify response to insert tokens automatically in all forms. If true,
+    /// this will insert tokens in all forms it encounter, if false, you will have to add them via
+    /// [CsrfFairing](struct.CsrfFairing.html), which you may obtain via request guards.
+    ///
+    pub fn set_auto_insert(mut self, auto_insert: bool) -> Self {
+        self.auto_insert = auto_insert;
+        self
+    }
+
+    /// Set the locales allowed as first segment of paths, for applications with localized urls
+    /// such as `/en/admin` and `/fr/admin`. Once set:
+    ///
+    /// - exceptions may use a `<locale>` dynamic part, which only match these values;
+    /// - `<locale>` in the default target or in exception targets is replaced by the locale of the
+    ///   request, even if the source of the exception didn't have one. Requests without locale
+    ///   use the first one of the list;
+    /// - [`set_auto_insert_disable_prefix`] prefixs also match after the locale, so `/admin`
+    ///   disable auto-insert on `/en/admin` too.
+    ///
+    /// By default there is no locale.
+    ///
+    /// [`set_auto_insert_disable_prefix`]: #method.set_auto_insert_disable_prefix
+    ///
+    /// # Example
+    ///
+    ///  ```rust,no_run
+    /// # extern crate rocket;
+    /// # extern crate rocket_csrf;
+    /// use rocket_csrf::CsrfFairingBuilder;
+    /// # use rocket::Rocket;
+    ///
+    /// fn main() {
+    ///     rocket::ignite()
+    ///         .attach(rocket_csrf::CsrfFairingBuilder::new()
+    ///                 .set_locales(vec!["en".to_owned(), "fr".to_owned()])
+    ///                 .set_default_target("/<locale>/csrf-violation".to_owned(), rocket::http::Method::Get)
+    ///                 .set_exceptions(vec![
+    ///                     ("/<locale>/hook".to_owned(), "/<locale>/hook".to_owned(), rocket::http::Method::Post),
+    ///                 ])
+    ///                 .finalize().unwrap())
+    ///         //add your routes, other fairings...
+    ///         .launch();
+    /// }
+    /// ```
+    pub fn set_locales(mut self, locales: Vec<String>) -> Self {
+        self.locales = locales;
+        self
+    }
+
+    /// Set prefixs on which the token in the body is verified by the route instead of the fairing.
+    /// On those paths, the fairing only check the request has a valid csrf cookie and, if the
+    /// browser sent one, that the `Origin` header match the `Host` header. Every protected route
+    /// under those prefixs **must** then use the [CsrfForm](struct.CsrfForm.html) data guard,
+    /// which read the full body, or the [CsrfBody](struct.CsrfBody.html) one, which read it up to
+    /// [`set_max_body_scan`](#method.set_max_body_scan), so tokens are found even in bodies too
+    /// large for the fairing.
+    pub fn set_deferred_verification_prefix(mut self, deferred_verification_prefix: Vec<String>) -> Self {
+        self.deferred_verification_prefix = deferred_verification_prefix;
+        self
+    }
+
+    /// Set prefixs on which static files are served. Safe requests on those paths don't get a
+    /// csrf cookie, and their responses are never parsed to insert tokens, so serving assets
+    /// doesn't cost any cryptographic operation nor add a `Set-Cookie` header to them.
+    pub fn set_static_prefix(mut self, static_prefix: Vec<String>) -> Self {
+        self.static_prefix = static_prefix;
+        self
+    }
+
+    /// Set if each form should get its own token when inserting tokens. Every token is valid for
+    /// the same cookie, but leaking the html of one form doesn't reveal tokens of other forms, and
+    /// identical tokens in two submissions mean the same form was submitted twice. This has no
+    /// effect if auto_insert is set to false. Default value is false.
+    pub fn set_unique_token_per_form(mut self, unique_token_per_form: bool) -> Self {
+        self.unique_token_per_form = unique_token_per_form;
+        self
+    }
+
+    /// Set if the token inserted in a form should only be valid when submitted to the `action` of
+    /// this form, resolved like a browser does against the url of the page. A token leaked from
+    /// one form, or stolen by markup injected in the page, can't be used to submit another form.
+    /// Tokens given to handlers with the `CsrfToken` guard are not scoped, use
+    /// [`CsrfToken::for_action`](struct.CsrfToken.html#method.for_action) to scope them too. This
+    /// has no effect if auto_insert is set to false. Default value is false.
+    pub fn set_action_bound_tokens(mut self, action_bound_tokens: bool) -> Self {
+        self.action_bound_tokens = action_bound_tokens;
+        self
+    }
+
+    /// Set the origins, such as `https://pay.example.com`, to which forms may be submitted with
+    /// a token. Forms whose `action` is an absolute url pointing to another origin than the
+    /// application itself, according to the `Host` header, never get a token, as it would be
+    /// given to a third party. This has no effect if auto_insert is set to false. Default is an
+    /// empty list.
+    pub fn set_form_origins(mut self, form_origins: Vec<String>) -> Self {
+        self.form_origins = form_origins;
+        self
+    }
+
+    /// Set the name of the attribute marking forms which must not get a token, when its value is
+    /// `off`, such as `<form data-csrf="off">` for a search form or a form posting to an exempt
+    /// path. This has no effect if auto_insert is set to false. Default is `data-csrf`.
+    pub fn set_opt_out_attribute(mut self, opt_out_attribute: String) -> Self {
+        self.opt_out_attribute = opt_out_attribute;
+        self
+    }
+
+    /// Set the markup inserted in forms, in place of
+    /// `<input type="hidden" name="{name}" value="{token}">`. The `{name}` placeholder is
+    /// replaced by the name of the field, `{token}` by the token and `{nonce}` by the CSP nonce
+    /// of the response, if any. [`finalize`] fails if there is no `{token}` placeholder. This has
+    /// no effect if auto_insert is set to false.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// CsrfFairingBuilder::new().set_field_template(
+    ///     "<input type=\"hidden\" class=\"csrf\" autocomplete=\"off\" name=\"{name}\" \
+    ///      value=\"{token}\">".to_owned(),
+    /// )
+    /// ```
+    ///
+    /// [`finalize`]: #method.finalize
+    pub fn set_field_template(mut self, field_template: String) -> Self {
+        self.field_template = Some(field_template);
+        self
+    }
+
+    /// Set prefixs for which this will not try to add tokens in forms. This has no effect if
+    /// auto_insert is set to false. To opt out a single route, wrap its response in a
+    /// [NoAutoInsert](struct.NoAutoInsert.html) instead. Not having to parse response on paths witch don't need it may
+    /// improve performances, but not that only html documents are parsed, so it's not usefull to
+    /// use it on routes containing only images or stillsheets. Globs and regexs are accepted, as
+    /// for the sources of [`set_exceptions`].
+    ///
+    /// [`set_exceptions`]: #method.set_exceptions
+    pub fn set_auto_insert_disable_prefix(mut self, auto_insert_prefix: Vec<String>) -> Self {
+        self.auto_insert_disable_prefix = auto_insert_prefix;
+        self
+    }
+
+    /// Set prefixs on which violations are only reported, not enforced. Requests on those paths
+    /// failing verification are logged, but reach their original route unchanged. This allow to
+    /// roll out protection gradually on newly added parts of an application.
+    pub fn set_report_only_prefix(mut self, report_only_prefix: Vec<String>) -> Self {
+        self.report_only_prefix = report_only_prefix;
+        self
+    }
+
+    /// Set the proportion of clients for which violations are enforced, between 0.0 and 1.0. Other
+    /// clients are in report only mode, as with [`set_report_only_prefix`]. The choice is made
+    /// from a hash of the client's csrf cookie, so a given client keep the same treatment as long
+    /// as its cookie is valid. Clients without a valid cookie are always enforced. Default value
+    /// is 1.0, protecting every client. Other values, or NaN, make
+    /// [`finalize`](#method.finalize) fail.
+    ///
+    /// [`set_report_only_prefix`]: #method.set_report_only_prefix
+    pub fn set_enforcement_sample_rate(mut self, rate: f32) -> Self {
+        self.enforcement_sample_rate = rate;
+        self
+    }
+
+    /// Set whether violations are enforced or only reported, see
+    /// [Enforcement](enum.Enforcement.html). Default is `Enforcement::Enforce`.
+    pub fn set_enforcement(mut self, enforcement: Enforcement) -> Self {
+        self.enforcement = enforcement;
+        self
+    }
+
+    /// Set a function called on every violation, enforced or not, before the request is
+    /// rerouted or failed. Use it to feed your own logs or metrics, for example while in
+    /// `Enforcement::ReportOnly`.
+    pub fn set_violation_callback<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Request, ViolationReason) + Send + Sync + 'static,
+    {
+        self.violation_callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Set an uri on which clients may send JSON [violation reports], like the `report-uri` of
+    /// CSP, for example from scripts getting their requests rejected. A handler for it is mounted
+    /// when the fairing is attached, and is never verified itself. Reports are given to the
+    /// [`set_report_handler`] function, or logged if there is none. Anyone can send them, so they
+    /// are marked `from_client`, never reach the violation callback or metrics, and are limited
+    /// by [`set_report_limit`]. By default there is no report uri.
+    ///
+    /// [violation reports]: struct.ViolationReport.html
+    /// [`set_report_handler`]: #method.set_report_handler
+    /// [`set_report_limit`]: #method.set_report_limit
+    pub fn set_report_uri(mut self, report_uri: String) -> Self {
+        self.report_uri = Some(report_uri);
+        self
+    }
+
+    /// Set how many reports are accepted on the report uri each minute, for all clients. Reports
+    /// beyond this are answered with `429 Too Many Requests` and dropped. Default is 60.
+    pub fn set_report_limit(mut self, report_limit: u32) -> Self {
+        self.report_limit = report_limit;
+        self
+    }
+
+    /// Install counters fed with tokens issued, verifications, insertions and cookie parse
+    /// errors, see [CsrfMetrics](trait.CsrfMetrics.html).
+    pub fn set_metrics<M: CsrfMetrics + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = Some(Arc::new(metrics));
+        self
+    }
+
+    /// Set a function receiving a structured [report](struct.ViolationReport.html) for each
+    /// violation seen by the fairing, and each report received on the report uri. Use
+    /// `from_client` to tell them apart.
+    pub fn set_report_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&ViolationReport) + Send + Sync + 'static,
+    {
+        self.report_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Set response status for which this will try to add tokens in forms. Responses with any
+    /// other status are sent untouched, without even looking at their body. This has no effect if
+    /// auto_insert is set to false. Default value is `200 OK` and `203 Non-Authoritative
+    /// Information`. Range responses (`206 Partial Content`, or any response with a
+    /// `Content-Range` header) are never modified, even if their status is listed here.
+    pub fn set_auto_insert_status(mut self, auto_insert_status: Vec<Status>) -> Self {
+        self.auto_insert_status = auto_insert_status;
+        self
+    }
+
+    /// Set if a `<meta name="csrf-token" content="...">` element holding the token is inserted at
+    /// the begining of `<head>`, so scripts (fetch, axios, htmx...) can read the token without a
+    /// form in the page. It is also updated by the script of
+    /// [refresh_routes](fn.refresh_routes.html). This has no effect if auto_insert is set to
+    /// false. Default value is false.
+    pub fn set_meta_tag(mut self, meta_tag: bool) -> Self {
+        self.meta_tag = meta_tag;
+        self
+    }
+
+    /// Set if a `<meta name="csrf-param" content="...">` element holding the name of the form
+    /// field is inserted at the begining of `<head>`, as Rails does. This has no effect if
+    /// auto_insert is set to false. Default value is false.
+    pub fn set_param_meta_tag(mut self, param_meta_tag: bool) -> Self {
+        self.param_meta_tag = param_meta_tag;
+        self
+    }
+
+    /// Set the name of a javascript global variable which will hold the token, via a script
+    /// inserted at the begining of `<head>`. The script carry the nonce of the page's Content
+    /// Security Policy (see [WithCspNonce](struct.WithCspNonce.html)), and is not inserted if the
+    /// policy would block it. The name must be a javascript identifier, or
+    /// [`finalize`](#method.finalize) fails. This has no effect if auto_insert is set to false.
+    /// Default value is None, inserting no script.
+    pub fn set_js_global(mut self, js_global: Option<String>) -> Self {
+        self.js_global = js_global;
+        self
+    }
+
+    /// Set how the token is exposed to javascript when [`set_js_global`] is used. By default an
+    /// inline script set the variable, see [JsGlobalMode](enum.JsGlobalMode.html) for a way to
+    /// satisfy policies forbidding inline scripts.
+    ///
+    /// [`set_js_global`]: #method.set_js_global
+    pub fn set_js_global_mode(mut self, js_global_mode: JsGlobalMode) -> Self {
+        self.js_global_mode = js_global_mode;
+        self
+    }
+
+    /// Set how AMP documents are handled when inserting tokens. By default they are treated like
+    /// any other document, see [AmpMode](enum.AmpMode.html) for alternatives. This has no effect if
+    /// auto_insert is set to false.
+    pub fn set_amp_mode(mut self, amp_mode: AmpMode) -> Self {
+        self.amp_mode = amp_mode;
+        self
+    }
+
+    /// Set the maximum size of a request before it get send chunked. A request will need at most
+    /// this additional memory for the buffer used to parse and tokens into forms. This have no
+    /// effect if auto_insert is set to false. Default value is 16Kio
+    pub fn set_auto_insert_max_chunk_size(mut self, chunk_size: u64) -> Self {
+        self.auto_insert_max_size = chunk_size;
+        self
+    }
+
+    /// Set if responses with a known size larger than the max chunk size keep a
+    /// `Content-Length` header, instead of being sent chunked, for clients and CDNs which need
+    /// it. Their body is then rewritten in memory like smaller ones, so the original and the
+    /// rewritten body are both held until it is sent. This has no effect if auto_insert is set to
+    /// false. Default value is false.
+    pub fn set_preserve_content_length(mut self, preserve_content_length: bool) -> Self {
+        self.preserve_content_length = preserve_content_length;
+        self
+    }
+
+    /// Set token formats of other frameworks which will still be accepted, to ease migration of an
+    /// existing application to Rocket. Only native tokens are issued, so once every page rendered
+    /// by the previous application has expired, this should be removed.
+    ///
+    /// # Example
+    ///
+    ///  ```rust,no_run
+    /// # extern crate rocket;
+    /// # extern crate rocket_csrf;
+    /// use rocket_csrf::{CsrfFairingBuilder, LegacyFormat};
+    /// # use rocket::Rocket;
+    ///
+    /// fn main() {
+    ///     rocket::ignite()
+    ///         .attach(rocket_csrf::CsrfFairingBuilder::new()
+    ///                 .set_legacy_formats(vec![LegacyFormat::django()])
+    ///                 .set_legacy_deadline(1_546_300_800)//stop accepting them in 2019
+    ///                 .finalize().unwrap())
+    ///         //add your routes, other fairings...
+    ///         .launch();
+    /// }
+    /// ```
+    pub fn set_legacy_formats(mut self, legacy_formats: Vec<LegacyFormat>) -> Self {
+        self.legacy_formats = legacy_formats;
+        self
+    }
+
+    /// Set the date (as a unix timestamp, in seconds) after which tokens in legacy formats are no
+    /// longer accepted. By default they are accepted as long as formats are configured.
+    pub fn set_legacy_deadline(mut self, deadline: i64) -> Self {
+        self.legacy_deadline = Some(deadline);
+        self
+    }
+
+    /// Configure Rocket for per-route protection, without attaching the fairing. Request and data
+    /// guards ([CsrfToken](struct.CsrfToken.html), [CsrfForm](struct.CsrfForm.html),
+    /// [CsrfJson](struct.CsrfJson.html), [CsrfVerified](struct.CsrfVerified.html)) then work
+    /// with this configuration, but nothing is verified or inserted into pages automatically:
+    /// every protected route must use one of these guards, and tokens must be added to forms by
+    /// hand. Only a fairing forgetting the token of the previous request, kept by guards of the
+    /// same thread, is attached.
+    ///
+    /// # Example
+    ///
+    ///  ```rust,no_run
+    /// # extern crate rocket;
+    /// # extern crate rocket_csrf;
+    /// use rocket_csrf::CsrfFairingBuilder;
+    /// # use rocket::Rocket;
+    ///
+    /// fn main() {
+    ///     let rocket = rocket::ignite();
+    ///     CsrfFairingBuilder::new()
+    ///         .manage(rocket).unwrap()
+    ///         //add your routes, other fairings...
+    ///         .launch();
+    /// }
+    /// ```
+    pub fn manage(self, rocket: Rocket) -> Result<Rocket, CsrfError> {
+        let fairing = self.finalize()?;
+        fairing.apply_config(rocket.config())?;
+        fairing.check_environment(rocket.config())?;
+        let keyring = fairing.resolve_keyring(rocket.config())?;
+        let field_name = fairing.field_name.clone();
+        Ok(rocket
+            .manage(keyring.clone())
+            .manage(fairing.state(keyring))
+            .attach(AdHoc::on_request(move |_, _| begin_request(&field_name))))
+    }
+
+    /// Get the fairing from the builder. Fail with a [CsrfConfigError](enum.CsrfConfigError.html)
+    /// telling what is wrong if the configuration is invalid.
+    pub fn finalize(self) -> Result<CsrfFairing, CsrfConfigError> {
+        if let Some(ref template) = self.field_template {
+            if !template.contains("{token}") {
+                return Err(CsrfConfigError::InvalidFieldTemplate(template.clone()));
+            }
+        }
+        if let Some(ref js_global) = self.js_global {
+            if !is_js_identifier(js_global) {
+                return Err(CsrfConfigError::InvalidJsGlobal(js_global.clone()));
+            }
+        } //the name is inserted into a script as is
+        if !(self.enforcement_sample_rate >= 0.0 && self.enforcement_sample_rate <= 1.0) {
+            return Err(CsrfConfigError::InvalidSampleRate(self.enforcement_sample_rate));
+        } //also false for NaN
+        let env_secret = match env::var("ROCKET_SECRET_KEY") {
+            Ok(value) => Some(Zeroizing::new(derive_secret(&value)?)),
+            Err(_) => None,
+        }; //validated now, but only used if Rocket's configuration has no secret for us
+
+        let settings = Settings {
+            duration: self.duration,
+            default_target: default_target(&self.default_target.0, self.default_target.1)?,
+            exceptions: self
+                .exceptions
+                .iter()
+                .map(|(a, b, m)| exception(a, b, *m))
+                .collect::<Result<Vec<_>, CsrfConfigError>>()?,
+            auto_insert: self.auto_insert,
+            auto_insert_disable_prefix: prefixes(&self.auto_insert_disable_prefix)?,
+            auto_insert_max_size: self.auto_insert_max_size,
+            cookie_options: self.cookie_options,
+        };
+        let json_token_field = self
+            .json_token_field
+            .unwrap_or_else(|| JSON_TOKEN_FIELD.to_owned());
+        let mut body_content_types = vec![ContentType::Form, ContentType::JSON];
+        body_content_types.extend(self.body_content_types); //types the token is searched in
+        let mut policies: Vec<Box<CsrfPolicy>> = Vec::new();
+        if let Some((header, value)) = self.custom_header {
+            policies.push(Box::new(CustomHeaderPolicy {
+                header,
+                value,
+                prefixes: self.custom_header_prefix,
+            }));
+        }
+        if self.fetch_metadata_policy != FetchMetadataPolicy::Off {
+            policies.push(Box::new(self.fetch_metadata_policy));
+        }
+        if self.origin_check != OriginCheck::Off {
+            policies.push(Box::new(OriginPolicy {
+                check: self.origin_check,
+                trusted: self.trusted_origins,
+            }));
+        }
+        policies.push(Box::new(HeaderTokenPolicy {
+            header: self.token_header.clone(),
+        }));
+        if !self.deferred_verification_prefix.is_empty() {
+            policies.push(Box::new(DeferredPolicy {
+                prefixes: self.deferred_verification_prefix,
+            }));
+        }
+        policies.extend(self.policies);
+        policies.push(Box::new(BodyTokenPolicy {
+            field_name: self.field_name.clone(),
+            json_token_field: json_token_field.clone(),
+            json_max_depth: self.json_max_depth,
+            max_body_size: self.max_body_size,
+            content_types: body_content_types.clone(),
+        }));
+        if !self.legacy_formats.is_empty() {
+            policies.push(Box::new(LegacyPolicy {
+                formats: self.legacy_formats,
+                deadline: self.legacy_deadline,
+            }));
+        } //build the chain of policies from the configuration
+
+        Ok(CsrfFairing {
+            settings: RwLock::new(settings),
+            secrets: Zeroizing::new(self.secrets),
+            secret_provider: self.secret_provider,
+            env_secret,
+            grace_period: self.grace_period,
+            prefix_durations: Arc::new(
+                self.prefix_timeouts
+                    .iter()
+                    .map(|(prefix, timeout)| Pattern::prefix(prefix).map(|prefix| (prefix, *timeout)))
+                    .collect::<Result<_, CsrfConfigError>>()?,
+            ),
+            report_only_prefix: self.report_only_prefix,
+            enforcement_sample_rate: self.enforcement_sample_rate,
+            amp_mode: self.amp_mode,
+            preserve_content_length: self.preserve_content_length,
+            auto_insert_status: self.auto_insert_status,
+            unique_token_per_form: self.unique_token_per_form,
+            action_bound_tokens: self.action_bound_tokens,
+            form_origins: Arc::new(self.form_origins),
+            buffer_pool: Arc::new(BufferPool::default()),
+            opt_out_attribute: self.opt_out_attribute,
+            field_template: self.field_template,
+            sliding_expiration: self.sliding_expiration,
+            js_global: self.js_global,
+            js_global_mode: self.js_global_mode,
+            meta_tag: self.meta_tag,
+            param_meta_tag: self.param_meta_tag,
+            htmx: self.htmx,
+            static_prefix: self.static_prefix,
+            locales: self.locales,
+            token_header: self.token_header,
+            violation_action: self.violation_action,
+            field_name: self.field_name,
+            token_cookie: self.token_cookie,
+            policies,
+            json_token_field,
+            body_content_types,
+            exempt_routes: self.exempt_routes,
+            exempt_paths: RwLock::new(Vec::new()),
+            exempt_methods: self.exempt_methods,
+            exempt_mounts: self
+                .exempt_mounts
+                .into_iter()
+                .map(|mount| mount.trim_right_matches('/').to_owned())
+                .collect(),
+            safe_methods: self.safe_methods,
+            method_override: self.method_override,
+            enforcement: self.enforcement,
+            violation_callback: self.violation_callback,
+            report_uri: self.report_uri,
+            report_handler: self.report_handler,
+            report_limit: self.report_limit,
+            metrics: self.metrics,
+            keyring: self.keyring,
+            backend: self.backend,
+            token_scheme: self.token_scheme,
+            nonce_store: self.nonce_store,
+            session: self.session,
+            token_binding: self.token_binding,
+            max_body_scan: self.max_body_scan,
+            require_secret: self.require_secret,
+            require_secure_cookie: self.require_secure_cookie,
+            verbose: self.verbose,
+            warn_violations: AtomicBool::new(false),
+        })
+    }
+}
+
+fn is_development(config: &Config) -> bool {
+    config.environment == Environment::Development
+}
+
+impl Default for CsrfFairingBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fairing to protect against Csrf attacks.
+///
+/// The `CsrfFairing` type protect a Rocket instance against Csrf attack by requesting mendatory
+/// token on any POST, PUT, DELETE or PATCH request (or any method not set as safe with
+/// [`set_safe_methods`](struct.CsrfFairingBuilder.html#method.set_safe_methods)).
+/// This is created via a [CsrfFairingBuilder](struct.CsrfFairingBuilder.html), and implement nothing else than the `Fairing` trait.
+///
+/// [`CsrfFairingBuilder`]: /rocket_csrf/struct.CsrfFairing.html
+pub struct CsrfFairing {
+    settings: RwLock<Settings>,
+    secrets: Zeroizing<Vec<[u8; 32]>>, //wiped when the fairing is dropped
+    secret_provider: Option<Arc<SecretProvider>>,
+    env_secret: Option<Zeroizing<[u8; 32]>>,
+    grace_period: i64,
+    prefix_durations: Arc<Vec<(Pattern, i64)>>,
+    report_only_prefix: Vec<String>,
+    enforcement_sample_rate: f32,
+    amp_mode: AmpMode,
+    preserve_content_length: bool,
+    auto_insert_status: Vec<Status>,
+    unique_token_per_form: bool,
+    action_bound_tokens: bool,
+    form_origins: Arc<Vec<String>>,
+    buffer_pool: Arc<BufferPool>,
+    opt_out_attribute: String,
+    field_template: Option<String>,
+    sliding_expiration: SlidingExpiration,
+    js_global: Option<String>,
+    js_global_mode: JsGlobalMode,
+    meta_tag: bool,
+    param_meta_tag: bool,
+    htmx: bool,
+    static_prefix: Vec<String>,
+    locales: Vec<String>,
+    token_header: String,
+    violation_action: ViolationAction,
+    field_name: String,
+    token_cookie: Option<String>,
+    policies: Vec<Box<CsrfPolicy>>,
+    json_token_field: String,
+    body_content_types: Vec<ContentType>,
+    exempt_routes: Vec<&'static ExemptRoute>,
+    exempt_paths: RwLock<Vec<(Path, Method)>>,
+    exempt_methods: Vec<Method>,
+    exempt_mounts: Vec<String>,
+    safe_methods: Vec<Method>,
+    method_override: bool,
+    enforcement: Enforcement,
+    violation_callback: Option<ViolationCallback>,
+    report_uri: Option<String>,
+    report_handler: Option<ReportHandler>,
+    report_limit: u32,
+    metrics: Option<Arc<CsrfMetrics>>,
+    keyring: Option<CsrfKeyring>,
+    backend: CsrfBackend,
+    token_scheme: TokenScheme,
+    nonce_store: Option<Arc<NonceStore>>,
+    session: Option<SessionFn>,
+    token_binding: TokenBinding,
+    max_body_scan: Option<u64>,
+    require_secret: Option<bool>,
+    require_secure_cookie: Option<bool>,
+    verbose: Option<bool>,
+    warn_violations: AtomicBool,
+}
+
+impl CsrfFairing {
+    /// Combine this fairing with a CORS fairing, guarantying they run in the right order. See
+    /// [CorsAware](struct.CorsAware.html) for details.
+    pub fn with_cors<F: Fairing>(self, cors: F) -> CorsAware<F> {
+        CorsAware::new(cors, self)
+    }
+
+    fn settings(&self) -> RwLockReadGuard<Settings> {
+        self.settings.read().unwrap_or_else(|e| e.into_inner()) //settings are always left consistent
+    }
+
+    fn apply_config(&self, config: &Config) -> Result<(), CsrfConfigError> {
+        //override settings with the [csrf] table of Rocket's configuration, if any
+        match config.get_table(CONFIG_TABLE) {
+            Ok(table) => self
+                .settings
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .apply(table),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn resolve_secrets(&self, config: &Config) -> Result<Vec<[u8; 32]>, CsrfConfigError> {
+        //keys of the keyring, the primary one first
+        if !self.secrets.is_empty() {
+            return Ok(self.secrets.to_vec());
+        } //use provided secrets if there are some
+        if let Some(ref provider) = self.secret_provider {
+            return provider.secrets();
+        } //then ask the provider, so a changed secret is picked up when attaching again
+        if let Ok(value) = config.get_str(CONFIG_SECRET_KEY) {
+            return Ok(vec![decode_secret(value)?]);
+        } //else get them from Rocket's configuration, as one key
+        if let Ok(values) = config.get_slice(CONFIG_SECRET_KEY) {
+            let secrets = values
+                .iter()
+                .map(|value| {
+                    value
+                        .as_str()
+                        .ok_or(CsrfConfigError::InvalidSecretEncoding)
+                        .and_then(decode_secret)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if !secrets.is_empty() {
+                return Ok(secrets);
+            }
+        } //or as an array of keys
+        if let Some(ref secret) = self.env_secret {
+            return Ok(vec![**secret]);
+        } //else from the environment, which has precedence over Rocket.toml, as for Rocket
+        if let Some(secret) = rocket_secret(config)? {
+            return Ok(vec![secret]);
+        } //else from the secret_key of Rocket.toml
+        if self.require_secret.unwrap_or(!is_development(config)) {
+            return Err(CsrfConfigError::MissingSecret);
+        } //a random secret invalidate every token on restart, and differ between instances
+        csrf_warn!("No secret key was found, you should consider set one to keep token validity across application restart");
+        Ok(vec![thread_rng().gen()]) //else generate a random secret and print a warning
+    }
+
+    fn check_environment(&self, config: &Config) -> Result<(), CsrfConfigError> {
+        //apply defaults of the environment Rocket runs in, unless overridden on the builder
+        let development = is_development(config);
+        let require_secure_cookie = self.require_secure_cookie.unwrap_or(!development);
+        if require_secure_cookie && !self.settings().cookie_options.with_prefix().secure {
+            return Err(CsrfConfigError::InsecureCookie);
+        }
+        self.warn_violations
+            .store(self.verbose.unwrap_or(development), Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn resolve_keyring(&self, config: &Config) -> Result<CsrfKeyring, CsrfConfigError> {
+        let keyring = match self.keyring {
+            Some(ref keyring) => keyring.clone(),
+            None => CsrfKeyring::new(self.resolve_secrets(config)?)?,
+        };
+        keyring.configure(self.backend, self.token_scheme);
+        Ok(keyring)
+    }
+
+    fn state(&self, keyring: CsrfKeyring) -> CsrfState {
+        //configuration shared with request and data guards
+        let settings = self.settings();
+        CsrfState {
+            keyring,
+            duration: settings.duration,
+            grace_period: self.grace_period,
+            prefix_durations: self.prefix_durations.clone(),
+            sliding_expiration: self.sliding_expiration,
+            token_header: self.token_header.clone(),
+            cookie_options: settings.cookie_options.with_prefix(),
+            field_name: self.field_name.clone(),
+            token_cookie: self.token_cookie.clone(),
+            json_token_field: self.json_token_field.clone(),
+            metrics: self.metrics.clone(),
+            session: self.session.clone(),
+            token_binding: self.token_binding,
+            max_body_scan: self.max_body_scan,
+        }
+    }
+
+    fn consume_token(
+        &self,
+        state: &CsrfState,
+        context: &PolicyContext,
+        outcome: PolicyOutcome,
+    ) -> PolicyOutcome {
+        //with one-time tokens, a request accepted for its token is only accepted the first time
+        match (outcome, &self.nonce_store, context.verified_token()) {
+            (PolicyOutcome::Accept, Some(store), Some(token)) => {
+                let expires = now() + state.max_duration() + state.grace_period;
+                match state.engine().nonce(&token) {
+                    Some(ref nonce) if store.consume(nonce, expires) => outcome,
+                    _ => PolicyOutcome::Reject(ViolationReason::ReplayedToken),
+                }
+            }
+            _ => outcome,
+        }
+    }
+
+    fn on_violation(
+        &self,
+        request: &mut Request,
+        cookie: Option<&CookieSecret>,
+        reason: ViolationReason,
+    ) {
+        set_current_violation(Some(reason)); //for the CsrfViolation guard
+        if let Some(ref metrics) = self.metrics {
+            metrics.verification_failed(reason);
+        }
+        if let Some(ref callback) = self.violation_callback {
+            callback(request, reason);
+        }
+        if let Some(ref handler) = self.report_handler {
+            handler(&ViolationReport::new(request, reason));
+        }
+
+        let uri = request.uri().to_string();
+        if self.enforcement == Enforcement::ReportOnly
+            || self
+                .report_only_prefix
+                .iter()
+                .any(|prefix| uri.starts_with(prefix))
+            || !self.is_enforced_for(cookie)
+        {
+            csrf_warn!(
+                "Csrf violation on {} {}: {} (report only)",
+                request.method(),
+                uri,
+                reason
+            );
+            return;
+        } //if in report only mode, on a report only prefix or client is not sampled, let it through
+
+        let locale = self
+            .locale(&uri)
+            .or_else(|| self.locales.first().map(|locale| locale.as_str()))
+            .map(|locale| locale.to_owned()); //requests without locale get the first one
+        let settings = self.settings();
+        for (src, dst, method) in &settings.exceptions {
+            if let Some(mut param) = src.extract(&uri) {
+                if let Some(found) = param.get("locale") {
+                    if !self.locales.is_empty() && !self.locales.contains(found) {
+                        continue;
+                    }
+                } //<locale> only match allowed locales
+                if let Some(ref locale) = locale {
+                    param.entry("locale").or_insert_with(|| locale.clone());
+                } //give the locale of the request to the target
+                if let Some(destination) = dst.map(&param) {
+                    csrf_debug!("Csrf violation on {} matched exception to {}", uri, destination);
+                    request.set_uri(destination);
+                    request.set_method(*method);
+                    return;
+                }
+            }
+        }
+
+        //if request matched no exception, fail it or reroute it to default target
+
+        if self.warn_violations.load(Ordering::Relaxed) {
+            csrf_warn!("Csrf violation on {} {}: {}", request.method(), uri, reason);
+        } else {
+            csrf_debug!("Csrf violation on {} {}: {}", request.method(), uri, reason);
+        }
+        if let ViolationAction::Respond(status) = self.violation_action {
+            request.set_uri(format!("{}/{}", VIOLATION_ROUTE, status.code));
+            request.set_method(Get);
+            return;
+        }
+
+        let mut param: HashMap<&str, String> = HashMap::new();
+        param.insert("locale", locale.unwrap_or_default());
+        let uri = Uri::percent_encode(&uri);
+        param.insert("uri", uri.to_string());
+        request.set_uri(
+            settings
+                .default_target
+                .0
+                .map(&param)
+                .expect("default target is verified in finalize"),
+        );
+        request.set_method(settings.default_target.1)
+    }
+
+    fn proxy<'a>(
+        &self,
+        request: &Request,
+        body: Box<Read + 'a>,
+        token: &CsrfToken,
+        csp: &CspInfo,
+    ) -> CsrfProxy<'a> {
+        //encapsulate a body into our "proxy" struct, configured as requested in the builder
+        let document = if self.action_bound_tokens {
+            Some(request.uri().path().to_owned())
+        } else {
+            None
+        };
+        let host = request.headers().get_one("Host").map(|host| host.to_owned());
+        let proxy = CsrfProxy::from(body, token)
+            .with_amp_mode(self.amp_mode)
+            .with_unique_token_per_form(self.unique_token_per_form)
+            .with_action_scope(document)
+            .with_form_origins(host, self.form_origins.clone())
+            .with_opt_out_attribute(self.opt_out_attribute.clone())
+            .with_buffer_pool(self.buffer_pool.clone())
+            .with_field_template(self.field_template.as_ref().map(|template| {
+                template.replace("{nonce}", csp.nonce.as_ref().map(|n| n.as_str()).unwrap_or(""))
+            }));
+        if self.htmx && request.headers().contains("HX-Request") {
+            proxy //a fragment, the page it is swapped into already has the token
+        } else {
+            proxy
+                .with_head_content(self.head_content(token, csp))
+                .with_body_attributes(self.body_attributes(token))
+        }
+    }
+
+    fn body_attributes(&self, token: &CsrfToken) -> Option<Vec<u8>> {
+        //attributes to insert into <body>, so htmx sends the token with every request
+        if self.htmx {
+            Some(
+                format!(
+                    " hx-headers='{{\"{}\": \"{}\"}}'",
+                    self.token_header,
+                    from_utf8(token.value()).unwrap_or("")
+                ).into_bytes(),
+            )
+        } else {
+            None
+        }
+    }
+
+    fn head_content(&self, token: &CsrfToken, csp: &CspInfo) -> Option<Vec<u8>> {
+        //markup to insert at the begining of <head>, if any
+        let token = from_utf8(token.value()).unwrap_or("");
+        let mut head = String::new();
+        if self.param_meta_tag {
+            head.push_str(&format!("<meta name=\"csrf-param\" content=\"{}\">", self.field_name));
+        }
+        if self.meta_tag {
+            head.push_str(&format!("<meta name=\"csrf-token\" content=\"{}\">", token));
+        }
+        if let Some(script) = self.js_global_script(token) {
+            match self.js_global_mode {
+                JsGlobalMode::InlineScript => if csp.inline_scripts_allowed {
+                    head.push_str(&format!("<script{}>{}</script>", csp.nonce_attribute(), script));
+                }, //if the policy would block our script, don't insert it
+                JsGlobalMode::HashedInlineScript => {
+                    head.push_str(&format!("<script{}>{}</script>", csp.nonce_attribute(), script));
+                } //the policies blocking it were given its hash
+                JsGlobalMode::JsonBlock => head.push_str(&format!(
+                    "<script type=\"application/json\" id=\"csrf-token\">\"{}\"</script>",
+                    token
+                )), //data blocks are never executed, so they are not subject to the policy
+            }
+        }
+        if head.is_empty() {
+            None
+        } else {
+            Some(head.into_bytes())
+        }
+    }
+
+    fn js_global_script(&self, token: &str) -> Option<String> {
+        //content of the script setting the javascript global variable, if any
+        self.js_global
+            .as_ref()
+            .map(|js_global| format!("window.{} = \"{}\";", js_global, token))
+    }
+
+    fn drop_csrf_cookies(&self, request: &Request, response: &mut Response) {
+        //remove the csrf cookies issued when the request began, as no page will use their tokens
+        let state = match CsrfState::from(request) {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        let names: Vec<&String> = Some(&state.cookie_options.name)
+            .into_iter()
+            .chain(state.token_cookie.as_ref())
+            .collect();
+        let cookies: Vec<String> = response
+            .headers()
+            .get("Set-Cookie")
+            .map(|cookie| cookie.to_owned())
+            .collect();
+        response.remove_header("Set-Cookie");
+        for cookie in cookies {
+            let name = cookie.split('=').next().unwrap_or("").trim();
+            if !names.iter().any(|csrf_name| csrf_name.as_str() == name) {
+                response.adjoin_raw_header("Set-Cookie", cookie);
+            }
+        }
+    }
+
+    fn count_insertion(&self) {
+        if let Some(ref metrics) = self.metrics {
+            metrics.auto_insertion();
+        }
+    }
+
+    fn is_static(&self, request: &Request) -> bool {
+        let uri = request.uri().to_string();
+        self.static_prefix
+            .iter()
+            .any(|prefix| uri.starts_with(prefix))
+    }
+
+    fn sent_method(&self, request: &Request) -> Method {
+        //method the request was sent with. Rocket already replaced the method of a POST by the
+        //`_method` field of its form body, but browsers send form bodies with POST only, so any
+        //form body was sent with a cross-site capable POST
+        let is_form = request.content_type().map(|ct| ct.is_form()).unwrap_or(false);
+        if is_form {
+            Post
+        } else {
+            request.method()
+        }
+    }
+
+    fn method(&self, request: &Request) -> Method {
+        //method deciding whether a request is verified, once overrides are taken into account
+        let sent = self.sent_method(request);
+        if sent != Post {
+            return sent;
+        }
+        let overridden = if self.method_override {
+            request
+                .headers()
+                .get_one(METHOD_OVERRIDE_HEADER)
+                .and_then(|method| method.parse::<Method>().ok())
+        } else {
+            None
+        };
+        match overridden {
+            Some(method) if !self.safe_methods.contains(&method) => method,
+            _ => sent, //never let an override skip verification
+        }
+    }
+
+    fn is_exempt(&self, request: &Request) -> bool {
+        let uri = request.uri();
+        if self.exempt_methods.contains(&self.sent_method(request)) {
+            return true;
+        } //an overridden method only adds verification, it never exempts a request
+        if self.report_uri.as_ref().map(|report_uri| uri.path() == report_uri).unwrap_or(false) {
+            return true;
+        } //clients reporting a violation may not have a token
+        if self.exempt_mounts.iter().any(|mount| {
+            uri.path() == mount || uri.path().starts_with(&format!("{}/", mount))
+        }) {
+            return true;
+        }
+        match self.exempt_paths.read() {
+            Ok(exempt_paths) => exempt_paths.iter().any(|(path, method)| {
+                *method == request.method() && path.extract(uri.path()).is_some()
+            }),
+            Err(_) => false,
+        }
+    }
+
+    fn is_enforced_for(&self, cookie: Option<&CookieSecret>) -> bool {
+        if self.enforcement_sample_rate >= 1.0 {
+            return true;
+        }
+        match cookie {
+            Some(cookie) => sample_ratio(cookie.value()) < self.enforcement_sample_rate,
+            None => true,
+        } //the cookie value is kept across regenerations, so this is sticky per client
+    }
+
+    fn locale<'a>(&self, uri: &'a str) -> Option<&'a str> {
+        //locale of a path, if its first segment is one of the configured locales
+        let segment = uri.trim_left_matches('/').split(|c| c == '/' || c == '?').next()?;
+        if self.locales.iter().any(|locale| locale == segment) {
+            Some(segment)
+        } else {
+            None
+        }
+    }
+
+    fn unlocalized<'a>(&self, uri: &'a str) -> &'a str {
+        //path without its locale segment, if it has one
+        match self.locale(uri) {
+            Some(locale) => &uri.trim_left_matches('/')[locale.len()..],
+            None => uri,
+        }
+    }
+}
+
+impl Fairing for CsrfFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "CSRF protection",
+            kind: Kind::Attach | Kind::Request | Kind::Response,
+        } //responses are always seen, if only to strip the private headers of our responders
+    }
+
+    fn on_attach(&self, rocket: Rocket) -> Result<Rocket, Rocket> {
+        let rocket = match self.violation_action {
+            ViolationAction::Respond(_) => rocket.mount("/", vec![violation_route()]),
+            ViolationAction::Reroute => rocket,
+        };
+        let rocket = match self.report_uri {
+            Some(ref report_uri) => rocket
+                .mount("/", vec![report_route(report_uri)])
+                .manage(ReportState::new(
+                    self.report_handler.clone().unwrap_or_else(|| Arc::new(log_report)),
+                    self.report_limit,
+                )),
+            None => rocket,
+        };
+        if let Ok(mut exempt_paths) = self.exempt_paths.write() {
+            *exempt_paths = mounted_paths(&rocket, &self.exempt_routes)
+                .iter()
+                .map(|(path, method)| (Path::from(path), *method))
+                .collect();
+        } //resolve exempted routes against their mount point
+        if let Err(e) = self
+            .apply_config(rocket.config())
+            .and_then(|_| self.check_environment(rocket.config()))
+        {
+            csrf_warn!("Could not attach csrf fairing: {}", e);
+            return Err(rocket);
+        }
+        let keyring = match self.resolve_keyring(rocket.config()) {
+            Ok(keyring) => keyring,
+            Err(e) => {
+                csrf_warn!("Could not attach csrf fairing: {}", e);
+                return Err(rocket);
+            }
+        };
+        Ok(rocket
+            .manage(keyring.clone()) //so handlers can change secrets
+            .manage(self.state(keyring))) //add the Csrf engine to Rocket's managed state
+    }
+
+    fn on_request(&self, request: &mut Request, data: &Data) {
+        begin_request(&self.field_name);
+
+        if is_preflight(request) {
+            return;
+        } //CORS preflight are answered by the CORS fairing, leave them alone
+
+        let method = self.method(request);
+        if self.safe_methods.contains(&method) {
+            if self.is_static(request) {
+                return;
+            }
+            if self.sliding_expiration == SlidingExpiration::OnSafeRequest {
+                let _ = renew_cookie(request); //if this fails, the guard issue a new cookie later
+            } else {
+                let _ = request.guard::<CsrfToken>(); //force regeneration of csrf cookies
+            }
+            return;
+        }
+
+        if self.is_exempt(request) {
+            return;
+        } //method, mount point or route marked with #[csrf_exempt] not to be verified
+
+        let state = match CsrfState::from(request) {
+            Ok(state) => state,
+            Err(_) => return self.on_violation(request, None, ViolationReason::Misconfigured), //can't verify anything, fail closed
+        };
+        set_current_cookie(
+            raw_cookie(request, state)
+                .map(|cookie| (state.engine(), cookie, state.binding(request))),
+        ); //give the cookie to CsrfTokenField
+
+        let cookie = parse_cookie(request, state); //get and parse Csrf cookie
+        csrf_debug!(
+            "csrf cookie of {} {}: {}",
+            request.method(),
+            request.uri(),
+            if cookie.is_some() { "parsed" } else { "missing or unparsable" }
+        );
+
+        let _ = request.guard::<CsrfToken>(); //force regeneration of csrf cookies
+
+        let (outcome, token_verified) = {
+            let context = PolicyContext::new(state, cookie.as_ref(), data.peek());
+            let request: &Request = request;
+            let outcome = self
+                .policies
+                .iter()
+                .map(|policy| policy.check(request, &context))
+                .enumerate()
+                .find(|(_, outcome)| *outcome != PolicyOutcome::Continue)
+                .map(|(position, outcome)| {
+                    csrf_debug!("policy {} of the chain decided {:?}", position, outcome);
+                    outcome
+                });
+            let outcome = outcome.unwrap_or_else(|| {
+                //Request no policy accepted are violating Csrf protection
+                if !context.has_cookie() {
+                    PolicyOutcome::Reject(ViolationReason::MissingCookie)
+                } else if context.cookie_expired() {
+                    PolicyOutcome::Reject(ViolationReason::ExpiredCookie)
+                } else if context.body_len() > 0 && !is_parsed(request, &self.body_content_types) {
+                    PolicyOutcome::Reject(ViolationReason::UnsupportedContentType) //never searched
+                } else if content_length(request)
+                    .map(|length| length > context.body_len() as u64)
+                    .unwrap_or(false)
+                {
+                    PolicyOutcome::Reject(ViolationReason::TokenBeyondLimit) //the token may be after the part we could search
+                } else if !request.headers().contains(&self.token_header)
+                    && !context.body().contains(self.field_name.as_str())
+                    && !context.body().contains(self.json_token_field.as_str())
+                {
+                    PolicyOutcome::Reject(ViolationReason::MissingToken) //not even the name of a token field, so no token was sent
+                } else {
+                    PolicyOutcome::Reject(ViolationReason::InvalidToken)
+                }
+            });
+            let token_verified = context.verified_token().is_some();
+            (self.consume_token(state, &context, outcome), token_verified)
+        };
+
+        match outcome {
+            PolicyOutcome::Reject(reason) => self.on_violation(request, cookie.as_ref(), reason),
+            _ => {
+                if let Some(ref metrics) = self.metrics {
+                    metrics.verification_passed();
+                }
+                let sliding = self.sliding_expiration == SlidingExpiration::OnVerifiedRequest;
+                if sliding && token_verified {
+                    let _ = renew_cookie(request); //if this fails, the cookie just keep its expiry
+                } //a header, fetch metadata or origin alone doesn't show the user still has a page
+            }
+        }
+    }
+
+    fn on_response<'a>(&self, request: &Request, response: &mut Response<'a>) {
+        let csp = CspInfo::from(response); //always consume the private nonce header
+
+        let opted_out = response.headers().contains(NO_AUTO_INSERT_HEADER);
+        response.remove_header(NO_AUTO_INSERT_HEADER);
+
+        if response.status().code >= 300 && response.status().code < 400 {
+            if self.safe_methods.contains(&request.method()) && !cookie_changed() {
+                self.drop_csrf_cookies(request, response);
+            }
+            return;
+        } //304 have no body to fill, and redirection bodies are never displayed, don't even issue a token
+
+        if opted_out {
+            return;
+        } //if route opted out via NoAutoInsert, we do nothing
+
+        if !self.settings().auto_insert {
+            return;
+        } //the private headers are gone, there is nothing else to do without auto-insert
+
+        if request.method() == Head {
+            return;
+        } //Rocket strip the body of HEAD responses, the cookie was already issued in on_request like for GET
+
+        if self.is_static(request) {
+            return;
+        } //static files never contain forms to fill
+
+        if response.status() == Status::PartialContent
+            || response.headers().contains("Content-Range")
+        {
+            return;
+        } //a byte range must be sent exactly as requested, whatever the allowed status are
+
+        if !self.auto_insert_status.contains(&response.status()) {
+            return;
+        } //if status is not one we insert tokens for, we do nothing
+
+        if let Some(ct) = response.content_type() {
+            if !ct.is_html() {
+                return;
+            }
+            let charset = ct
+                .params()
+                .find(|&(name, _)| name.eq_ignore_ascii_case("charset"))
+                .map(|(_, charset)| charset.to_owned());
+            if let Some(charset) = charset {
+                if !is_ascii_compatible(&charset) {
+                    csrf_debug!("Not inserting csrf token in a {} document", charset);
+                    return;
+                }
+            }
+        } //if content type is not html, or its charset is not ascii compatible, we do nothing
+
+        let encoding = normalize(response.headers().get_one("Content-Encoding"));
+        if let Some(ref encoding) = encoding {
+            if !is_supported(encoding) {
+                csrf_warn!("Not inserting csrf token in a {} encoded response", encoding);
+                return;
+            }
+        } //compressed bodies are decoded and encoded again, if the feature for it is enabled
+        let encoding = encoding.as_ref().map(|encoding| encoding.as_str());
+
+        let uri = request.uri().to_string();
+        let unlocalized = self.unlocalized(&uri);
+        if self
+            .settings()
+            .auto_insert_disable_prefix
+            .iter()
+            .any(|prefix| prefix.matches(&uri) || prefix.matches(unlocalized))
+        {
+            return;
+        } //if request is on an ignored prefix, ignore it
+
+        let token = match request.guard::<CsrfToken>() {
+            Outcome::Success(t) => t, //the token generated when the request began, if any
+            _ => return,
+        }; //if we can't get a token, leave request unchanged, we can't do anything anyway
+
+        let body = match response.take_body() {
+            Some(body) => body,
+            None => return,
+        }; //take request body from Rocket, if there was no body, leave it that way
+
+        let cache_control = private_cache_control(response.headers().get_one("Cache-Control"));
+        response.set_raw_header("Cache-Control", cache_control);
+        response.adjoin_raw_header("Vary", "Cookie"); //the body now depends on the csrf cookie
+        response.remove_header("ETag");
+        response.remove_header("Content-MD5");
+        response.remove_header("Content-Length"); //these describe the original body, not ours
+
+        let fragment = self.htmx && request.headers().contains("HX-Request");
+        if self.js_global_mode == JsGlobalMode::HashedInlineScript && !fragment {
+            if let Some(script) = self.js_global_script(from_utf8(token.value()).unwrap_or("")) {
+                allow_script(response, &script);
+            }
+        } //the head of the page will hold this script
+
+        if let Sized(mut body_reader, len) = body {
+            if len <= self.settings().auto_insert_max_size || self.preserve_content_length {
+                //if this is a small enought body, or its size must be kept, process the full body
+                let mut original = Vec::with_capacity(len as usize);
+                if let Err(e) = body_reader.read_to_end(&mut original) {
+                    csrf_warn!("Could not read response body, sending it untouched: {}", e);
+                    response.set_sized_body(Cursor::new(original));
+                    return;
+                } //if the body can't be read, send what we got without inserting anything
+                let mut res = Vec::with_capacity(original.len());
+                let body = decoder(encoding, Box::new(&original[..]));
+                let proxy = Box::new(self.proxy(request, body, &token, &csp));
+                let inserted = encoder(encoding, proxy).read_to_end(&mut res);
+                match inserted {
+                    Ok(_) => {
+                        self.count_insertion();
+                        response.set_sized_body(Cursor::new(res))
+                    }
+                    Err(e) => {
+                        csrf_warn!("Could not insert csrf token, sending body untouched: {}", e);
+                        response.set_sized_body(Cursor::new(original));
+                    }
+                }
+            } else {
+                //if body is of known but long size, change it to a stream to preserve memory, by encapsulating it into our "proxy" struct
+                let body = decoder(encoding, body_reader);
+                let proxy = Box::new(self.proxy(request, body, &token, &csp));
+                self.count_insertion();
+                response.set_streamed_body(encoder(encoding, proxy));
+            }
+        } else {
+            //if body is of unknown size, encapsulate it into our "proxy" struct
+            let body = decoder(encoding, body.into_inner());
+            let proxy = Box::new(self.proxy(request, body, &token, &csp));
+            self.count_insertion();
+            response.set_streamed_body(encoder(encoding, proxy));
+        }
+    }
+}
+
+fn begin_request(field_name: &str) {
+    //reset the state kept by this thread for the request it handled before, so a token, cookie
+    //or violation never leaks from a client to the next
+    set_current_cookie(None);
+    set_current_token(None);
+    set_cookie_changed(false);
+    set_current_violation(None);
+    set_current_field(field_name);
+}
+
+fn private_cache_control(cache_control: Option<&str>) -> String {
+    //a Cache-Control header keeping a page with a token out of caches shared between users
+    let directives: Vec<&str> = cache_control
+        .unwrap_or("")
+        .split(',')
+        .map(|directive| directive.trim())
+        .filter(|directive| !directive.is_empty())
+        .collect();
+    let name = |directive: &str| directive.split('=').next().unwrap_or("").to_ascii_lowercase();
+    if directives.iter().any(|&directive| name(directive) == "no-store") {
+        return directives.join(", ");
+    }
+    let mut kept: Vec<&str> = directives
+        .into_iter()
+        .filter(|&directive| match name(directive).as_str() {
+            "public" | "private" | "s-maxage" => false,
+            _ => true,
+        })
+        .collect();
+    kept.insert(0, "private");
+    kept.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::config::{Config, Environment};
+    use rocket::data::FromData;
+    use rocket::http::{ContentType, Header, Method, SameSite, Status};
+    use rocket::local::Client;
+    use rocket::response::Body;
+    use rocket::{handler, Data, Request, Response, Route};
+    use std::io::{self, Cursor, Read};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use csp::{JsGlobalMode, WithCspNonce, CSP_NONCE_HEADER};
+    use csrf_body::CsrfBody;
+    use csrf_field::CsrfTokenField;
+    use csrf_fairing::{private_cache_control, CsrfFairingBuilder};
+    use csrf_json::CsrfJson;
+    use csrf_session::CsrfSession;
+    use csrf_state::{CookiePrefix, SlidingExpiration};
+    use csrf_token::CsrfToken;
+    use csrf_verified::{verify_token, CsrfVerified};
+    use error::{CsrfConfigError, CsrfError};
+    use metrics::CsrfMetrics;
+    use no_auto_insert::{NoAutoInsert, NO_AUTO_INSERT_HEADER};
+    use nonce::MemoryNonceStore;
+    use origin::FetchMetadataPolicy;
+    use policy::{CsrfPolicy, PolicyContext, PolicyOutcome};
+    use refresh::refresh_route;
+    use violation::{CsrfViolation, Enforcement, ViolationAction, ViolationReason};
+
+    const PAGE: &str = "<html><body><form method=\"post\"></form></body></html>";
+
+    const MALFORMED: &[(&str, &str)] = &[
+        //pages, and what they become with FIELD in place of the inserted field
+        (
+            "<FoRm method=post><input name=a></fOrM>",
+            "<FoRm method=post>FIELD<input name=a></fOrM>",
+        ),
+        (
+            "<form method=post data-x=\"a>b\"><p>x</p></form >",
+            "<form method=post data-x=\"a>b\"><p>x</p>FIELD</form >",
+        ),
+        (
+            "<!-- <form method=post></form> --><form method=post></form>",
+            "<!-- <form method=post></form> --><form method=post>FIELD</form>",
+        ),
+        (
+            "<!--><form method=post></form>",
+            "<!--><form method=post>FIELD</form>",
+        ),
+        (
+            "<form method=post><input type=hidden name=\"_method\" value=put><input></form>",
+            "<form method=post><input type=hidden name=\"_method\" value=put>FIELD<input></form>",
+        ),
+        (
+            "<a title='<form method=post>'>a < b</a><form method=post/>",
+            "<a title='<form method=post>'>a < b</a><form method=post/>",
+        ),
+        (
+            "<form method=post></form><div class=\"x",
+            "<form method=post>FIELD</form><div class=\"x",
+        ),
+        (
+            "<script>f = '<form method=post></form>';</script><form method=post></form>",
+            "<script>f = '<form method=post></form>';</script><form method=post>FIELD</form>",
+        ),
+        (
+            "<SCRIPT>a = '</scripts>' < b</script ><form method=post></form>",
+            "<SCRIPT>a = '</scripts>' < b</script ><form method=post>FIELD</form>",
+        ),
+        (
+            "<form method=post><textarea><input></textarea></form>",
+            "<form method=post><textarea><input></textarea>FIELD</form>",
+        ),
+        (
+            "<template><form method=post><input></form></template><form method=post></form>",
+            "<template><form method=post><input></form></template><form method=post>FIELD</form>",
+        ),
+        (
+            "<form method=post><template><input></template></form>",
+            "<form method=post><template><input></template>FIELD</form>",
+        ),
+    ];
+
+    struct Trickle(&'static [u8]); //a body read one byte at a time
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    fn malformed<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+        let page = request
+            .get_param::<usize>(0)
+            .ok()
+            .and_then(|page| MALFORMED.get(page))
+            .map(|page| page.0)
+            .unwrap_or("");
+        handler::Outcome::Success(
+            Response::build()
+                .header(ContentType::HTML)
+                .streamed_body(Trickle(page.as_bytes()))
+                .finalize(),
+        )
+    }
+
+    fn partial<'r>(_: &'r Request, _: Data) -> handler::Outcome<'r> {
+        handler::Outcome::Success(
+            Response::build()
+                .status(Status::PartialContent)
+                .header(ContentType::HTML)
+                .raw_header("Content-Range", "bytes 0-55/100")
+                .sized_body(Cursor::new(PAGE))
+                .finalize(),
+        )
+    }
+
+    fn redirect<'r>(_: &'r Request, _: Data) -> handler::Outcome<'r> {
+        handler::Outcome::Success(
+            Response::build()
+                .status(Status::SeeOther)
+                .header(ContentType::HTML)
+                .raw_header("Location", "/partial")
+                .sized_body(Cursor::new(PAGE))
+                .finalize(),
+        )
+    }
+
+    fn not_modified<'r>(_: &'r Request, _: Data) -> handler::Outcome<'r> {
+        handler::Outcome::Success(
+            Response::build()
+                .status(Status::NotModified)
+                .header(ContentType::HTML)
+                .finalize(),
+        )
+    }
+
+    fn page<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+        handler::Outcome::from(request, ::rocket::response::content::Html(PAGE))
+    }
+
+    fn form_token(body: &str) -> String {
+        //value of the first hidden field of a page
+        body.split("value=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap()
+            .to_owned()
+    }
+
+    fn form_encode(value: &str) -> String {
+        //a value encoded like browsers do in urlencoded form bodies
+        value
+            .bytes()
+            .map(|byte| match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'*' | b'-' | b'.' | b'_' => {
+                    (byte as char).to_string()
+                }
+                b' ' => "+".to_owned(),
+                _ => format!("%{:02X}", byte),
+            })
+            .collect()
+    }
+
+    fn opted_out<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+        handler::Outcome::from(request, NoAutoInsert(WithCspNonce("abc".to_owned(), PAGE)))
+    }
+
+    fn rotate<'r>(request: &'r Request, data: Data) -> handler::Outcome<'r> {
+        match request.guard::<CsrfSession>() {
+            ::rocket::outcome::Outcome::Success(session) => session.regenerate().unwrap(),
+            _ => panic!("CsrfSession never fails"),
+        };
+        redirect(request, data)
+    }
+
+    fn login<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+        match request.guard::<CsrfSession>() {
+            ::rocket::outcome::Outcome::Success(session) => session.regenerate().unwrap(),
+            _ => panic!("CsrfSession never fails"),
+        };
+        handler::Outcome::from(request, "logged in")
+    }
+
+    fn client() -> Client {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_auto_insert_status(vec![
+                        Status::Ok,
+                        Status::PartialContent,
+                        Status::SeeOther,
+                        Status::NotModified,
+                    ])
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Get, "/partial", partial),
+                    Route::new(Method::Get, "/redirect", redirect),
+                    Route::new(Method::Get, "/not_modified", not_modified),
+                    Route::new(Method::Get, "/rotate", rotate),
+                ],
+            );
+        Client::new(rocket).unwrap()
+    }
+
+    #[test]
+    fn test_partial_content_untouched() {
+        let client = client();
+        let mut response = client.get("/partial").dispatch();
+        assert_eq!(response.body_string().unwrap(), PAGE);
+    }
+
+    #[test]
+    fn test_redirection_untouched() {
+        let client = client();
+        let mut response = client.get("/redirect").dispatch();
+        assert!(response.headers().get_one("Set-Cookie").is_none()); //no page will use a token
+        assert_eq!(response.body_string().unwrap(), PAGE);
+    }
+
+    #[test]
+    fn test_redirection_keep_rotated_cookie() {
+        let client = client();
+        let response = client.get("/rotate").dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert!(response.headers().get_one("Set-Cookie").is_some()); //replaced on purpose
+    }
+
+    #[test]
+    fn test_not_modified_untouched() {
+        let client = client();
+        let mut response = client.get("/not_modified").dispatch();
+        assert_eq!(response.status(), Status::NotModified);
+        assert!(response.headers().get_one("Set-Cookie").is_none());
+        assert!(response.body_bytes().is_none());
+    }
+
+    #[test]
+    fn test_head_issue_cookie_without_body() {
+        let client = client();
+        let mut response = client.head("/page").dispatch();
+        assert!(response.headers().get_one("Set-Cookie").is_some());
+        assert!(response.body_bytes().map(|body| body.is_empty()).unwrap_or(true));
+    }
+
+    #[test]
+    fn test_private_headers_stripped() {
+        for auto_insert in &[true, false] {
+            let rocket = ::rocket::ignite()
+                .attach(
+                    CsrfFairingBuilder::new()
+                        .set_secret([0; 32])
+                        .set_auto_insert(*auto_insert)
+                        .finalize()
+                        .unwrap(),
+                )
+                .mount("/", vec![Route::new(Method::Get, "/opted_out", opted_out)]);
+            let client = Client::new(rocket).unwrap();
+            let mut response = client.get("/opted_out").dispatch();
+            assert!(!response.headers().contains(NO_AUTO_INSERT_HEADER));
+            assert!(!response.headers().contains(CSP_NONCE_HEADER));
+            assert_eq!(response.body_string().unwrap(), PAGE);
+        }
+    }
+
+    #[test]
+    fn test_violation_respond() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount("/", vec![Route::new(Method::Post, "/page", page)]);
+        let client = Client::new(rocket).unwrap();
+        let response = client.post("/page").dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_exempt_method_and_mount() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .exempt_method(Method::Delete)
+                    .exempt_mount("/api".to_owned())
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Post, "/api/page", page),
+                    Route::new(Method::Post, "/apis/page", page),
+                    Route::new(Method::Delete, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        assert_eq!(client.post("/api/page").dispatch().status(), Status::Ok);
+        assert_eq!(client.delete("/page").dispatch().status(), Status::Ok);
+        assert_eq!(client.post("/apis/page").dispatch().status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_method_override() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .exempt_method(Method::Delete)
+                    .set_method_override(true)
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Post, "/page", page),
+                    Route::new(Method::Delete, "/page", page),
+                    Route::new(Method::Get, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        assert_eq!(client.delete("/page").dispatch().status(), Status::Ok);
+        let response = client
+            .post("/page")
+            .header(Header::new("X-HTTP-Method-Override", "DELETE"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden); //overriding to an exempt method is not a bypass
+        let response = client
+            .post("/page")
+            .header(Header::new("X-HTTP-Method-Override", "GET"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden); //neither is overriding to a safe method
+        for method in &["DELETE", "GET"] {
+            let response = client
+                .post("/page")
+                .header(ContentType::Form)
+                .body(format!("_method={}", method))
+                .dispatch();
+            assert_eq!(response.status(), Status::Forbidden); //nor a _method form field
+        }
+    }
+
+    #[test]
+    fn test_report_only() {
+        let reported = Arc::new(AtomicUsize::new(0));
+        let counter = reported.clone();
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .set_enforcement(Enforcement::ReportOnly)
+                    .set_violation_callback(move |_, _| {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                    })
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount("/", vec![Route::new(Method::Post, "/page", page)]);
+        let client = Client::new(rocket).unwrap();
+        assert_eq!(client.post("/page").dispatch().status(), Status::Ok);
+        assert_eq!(reported.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_report_uri() {
+        use std::sync::Mutex;
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let received = reports.clone();
+        let violations = Arc::new(AtomicUsize::new(0));
+        let counter = violations.clone();
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .set_violation_callback(move |_, _| {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                    })
+                    .set_report_uri("/csrf-report".to_owned())
+                    .set_report_handler(move |report| {
+                        received.lock().unwrap().push(report.from_client);
+                    })
+                    .set_report_limit(2)
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount("/", vec![Route::new(Method::Post, "/page", page)]);
+        let client = Client::new(rocket).unwrap();
+        let report = "{\"uri\": \"/page\", \"method\": \"POST\", \"reason\": \"forged\", \
+                      \"from_client\": false}";
+        let send = || client.post("/csrf-report").body(report).dispatch().status();
+        assert_eq!(send(), Status::NoContent);
+        assert_eq!(send(), Status::NoContent);
+        assert_eq!(send(), Status::TooManyRequests); //reports from clients are limited
+        assert_eq!(client.post("/page").dispatch().status(), Status::Forbidden);
+        assert_eq!(*reports.lock().unwrap(), vec![true, true, false]);
+        assert_eq!(violations.load(Ordering::SeqCst), 1); //reports are not violations
+    }
+
+    #[test]
+    fn test_enforcement_sample_rate() {
+        let status = |rate: f32| {
+            let rocket = ::rocket::ignite()
+                .attach(
+                    CsrfFairingBuilder::new()
+                        .set_secret([0; 32])
+                        .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                        .set_enforcement_sample_rate(rate)
+                        .finalize()
+                        .unwrap(),
+                )
+                .mount(
+                    "/",
+                    vec![
+                        Route::new(Method::Get, "/page", page),
+                        Route::new(Method::Post, "/page", page),
+                    ],
+                );
+            let client = Client::new(rocket).unwrap();
+            let cookies = client.get("/page").dispatch().cookies();
+            let mut request = client.post("/page");
+            for cookie in cookies {
+                request = request.cookie(cookie);
+            }
+            request.dispatch().status() //a violation, as there is no token
+        };
+        assert_eq!(status(1.0), Status::Forbidden);
+        assert_eq!(status(0.0), Status::Ok); //the client is not sampled, only reported
+        for rate in &[-0.1, 1.5, ::std::f32::NAN] {
+            match CsrfFairingBuilder::new().set_enforcement_sample_rate(*rate).finalize() {
+                Err(CsrfConfigError::InvalidSampleRate(_)) => (),
+                _ => panic!("sample rate {} accepted", rate),
+            }
+        }
+    }
+
+    #[test]
+    fn test_one_time_tokens() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .set_nonce_store(MemoryNonceStore::new())
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Post, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/page").dispatch();
+        let cookies = response.cookies();
+        let token = form_token(&response.body_string().unwrap());
+        let submit = || {
+            let mut request = client
+                .post("/page")
+                .header(ContentType::Form)
+                .body(format!("csrf-token={}", token));
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            request.dispatch().status()
+        };
+        assert_eq!(submit(), Status::Ok);
+        assert_eq!(submit(), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_urlencoded_token() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Post, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/page").dispatch();
+        let cookies = response.cookies();
+        let token = form_token(&response.body_string().unwrap());
+        assert!(form_encode(&token).contains("%3A")); //the separator of the key id
+        let mut request = client
+            .post("/page")
+            .header(ContentType::Form)
+            .body(format!("comment=a+b&csrf-token={}", form_encode(&token)));
+        for cookie in cookies {
+            request = request.cookie(cookie);
+        }
+        assert_eq!(request.dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn test_huge_ttl() {
+        fn ttl_token<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+            let token = request
+                .guard::<CsrfToken>()
+                .unwrap()
+                .with_ttl(::std::time::Duration::from_secs(::std::u64::MAX));
+            let remaining = token.remaining().as_secs();
+            let value = String::from_utf8_lossy(token.value()).into_owned();
+            handler::Outcome::from(request, format!("{} {}", remaining, value))
+        }
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/token", ttl_token),
+                    Route::new(Method::Post, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/token").dispatch();
+        let cookies = response.cookies();
+        let body = response.body_string().unwrap();
+        let mut parts = body.split(' ');
+        let remaining: u64 = parts.next().unwrap().parse().unwrap();
+        assert!(remaining > 0 && remaining <= 60 * 60); //clamped to the cookie, not wrapped
+        let token = parts.next().unwrap().to_owned();
+        let mut request = client.post("/page").header(Header::new("X-CSRF-Token", token));
+        for cookie in cookies {
+            request = request.cookie(cookie);
+        }
+        assert_eq!(request.dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn test_non_utf8_body() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Post, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/page").dispatch();
+        let cookies = response.cookies();
+        let token = form_token(&response.body_string().unwrap());
+        let submit = |body: Vec<u8>| {
+            let mut request = client.post("/page").header(ContentType::Form).body(body);
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            request.dispatch().status()
+        };
+
+        let mut latin1 = b"comment=caf".to_vec();
+        latin1.push(0xe9); //"café" sent by a page in ISO-8859-1
+        latin1.extend_from_slice(format!("&csrf-token={}", form_encode(&token)).as_bytes());
+        assert_eq!(submit(latin1), Status::Ok);
+
+        let mut cut = format!("csrf-token={}&comment=", form_encode(&token)).into_bytes();
+        let fill = 4095 - cut.len();
+        cut.extend(::std::iter::repeat(b'a').take(fill));
+        cut.extend_from_slice("ééé".as_bytes()); //the 4Kio peeked end in the middle of a char
+        assert_eq!(submit(cut), Status::Ok);
+    }
+
+    #[test]
+    fn test_session_bound_urlencoded_token() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .set_session_binding(|request| {
+                        request
+                            .cookies()
+                            .get("session")
+                            .map(|cookie| cookie.value().as_bytes().to_vec())
+                    })
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Post, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let session = |name: &str| ::rocket::http::Cookie::new("session", name.to_owned());
+        let mut response = client.get("/page").cookie(session("alice")).dispatch();
+        let cookies = response.cookies();
+        let token = form_token(&response.body_string().unwrap());
+        assert!(form_encode(&token).contains("%7E")); //the separator of the binding
+        let submit = |name: &str| {
+            let mut request = client
+                .post("/page")
+                .header(ContentType::Form)
+                .cookie(session(name))
+                .body(format!("csrf-token={}", form_encode(&token)));
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            request.dispatch().status()
+        };
+        assert_eq!(submit("alice"), Status::Ok);
+        assert_eq!(submit("bob"), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_action_bound_tokens() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .set_action_bound_tokens(true)
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Post, "/page", page),
+                    Route::new(Method::Post, "/other", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/page").dispatch();
+        let cookies = response.cookies();
+        let token = form_token(&response.body_string().unwrap());
+        assert!(form_encode(&token).contains("%7E")); //the separator of the scope
+        let submit = |path: &str| {
+            let mut request = client
+                .post(path.to_owned())
+                .header(ContentType::Form)
+                .body(format!("csrf-token={}", form_encode(&token))); //as sent by browsers
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            request.dispatch().status()
+        };
+        assert_eq!(submit("/other"), Status::Forbidden);
+        assert_eq!(submit("/page"), Status::Ok); //the form has no action, so it is sent to the page
+    }
+
+    #[test]
+    fn test_session_regenerate() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Post, "/page", page),
+                    Route::new(Method::Post, "/login", login),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/page").dispatch();
+        let cookies = response.cookies();
+        let token = form_token(&response.body_string().unwrap());
+        let submit = |path: &str, cookies: &[::rocket::http::Cookie<'static>]| {
+            let mut request = client
+                .post(path.to_owned())
+                .header(ContentType::Form)
+                .body(format!("csrf-token={}", token));
+            for cookie in cookies {
+                request = request.cookie(cookie.clone());
+            }
+            request.dispatch()
+        };
+        let response = submit("/login", &cookies);
+        assert_eq!(response.status(), Status::Ok);
+        let rotated = response.cookies();
+        assert!(!rotated.is_empty());
+        assert_eq!(submit("/page", &rotated).status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_sliding_expiration() {
+        let renewed = |sliding_expiration| {
+            let rocket = ::rocket::ignite()
+                .attach(
+                    CsrfFairingBuilder::new()
+                        .set_secret([0; 32])
+                        .set_sliding_expiration(sliding_expiration)
+                        .finalize()
+                        .unwrap(),
+                )
+                .mount("/", vec![Route::new(Method::Get, "/page", page)]);
+            let client = Client::new(rocket).unwrap();
+            let cookies = client.get("/page").dispatch().cookies();
+            let mut request = client.get("/page");
+            for cookie in cookies {
+                request = request.cookie(cookie);
+            }
+            !request.dispatch().cookies().is_empty()
+        };
+        assert!(!renewed(SlidingExpiration::Never));
+        assert!(renewed(SlidingExpiration::OnSafeRequest));
+        assert!(renewed(SlidingExpiration::OnEveryRequest));
+    }
+
+    #[test]
+    fn test_expired_cookie_new_secret() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .set_sliding_expiration(SlidingExpiration::Never)
+                    .set_timeout(1)
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Post, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/page").dispatch();
+        let cookies = response.cookies();
+        let expired_token = form_token(&response.body_string().unwrap());
+        ::std::thread::sleep(::std::time::Duration::from_secs(2));
+        let mut request = client.get("/page");
+        for cookie in cookies {
+            request = request.cookie(cookie);
+        }
+        let mut response = request.dispatch();
+        let cookies = response.cookies();
+        assert!(!cookies.is_empty());
+        let token = form_token(&response.body_string().unwrap());
+        let submit = |token: &str| {
+            let mut request = client
+                .post("/page")
+                .header(ContentType::Form)
+                .body(format!("csrf-token={}", token));
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            request.dispatch().status()
+        };
+        assert_eq!(submit(&expired_token), Status::Forbidden); //not revived by the new cookie
+        assert_eq!(submit(&token), Status::Ok);
+    }
+
+    #[test]
+    fn test_prefix_timeouts() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .set_prefix_timeouts(vec![("/admin".to_owned(), 0)])
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Get, "/admin/page", page),
+                    Route::new(Method::Post, "/admin/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let token = form_token(&client.get("/page").dispatch().body_string().unwrap());
+        assert!(!token.contains('.'));
+        let mut response = client.get("/admin/page").dispatch();
+        let cookies = response.cookies();
+        let token = form_token(&response.body_string().unwrap());
+        assert!(token.contains('.')); //carries its own expiry
+        let mut request = client
+            .post("/admin/page")
+            .header(ContentType::Form)
+            .body(format!("csrf-token={}", token));
+        for cookie in cookies {
+            request = request.cookie(cookie);
+        }
+        assert_eq!(request.dispatch().status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_refresh_route() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_sliding_expiration(SlidingExpiration::Never)
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount("/", vec![refresh_route("/csrf/refresh")]);
+        let client = Client::new(rocket).unwrap();
+        let cookies = client.get("/csrf/refresh").dispatch().cookies();
+        let mut request = client.get("/csrf/refresh");
+        for cookie in cookies {
+            request = request.cookie(cookie);
+        }
+        let mut response = request.dispatch();
+        assert!(!response.cookies().is_empty()); //renewed even if sliding expiration is off
+        let body = response.body_string().unwrap();
+        assert!(body.starts_with("{\"token\":\"") && body.contains("\"expires_at\":"));
+    }
+
+    #[test]
+    fn test_meta_tag() {
+        fn document<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+            let page = "<html><head><title>t</title></head><body></body></html>";
+            handler::Outcome::from(request, ::rocket::response::content::Html(page))
+        }
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_meta_tag(true)
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount("/", vec![Route::new(Method::Get, "/document", document)]);
+        let client = Client::new(rocket).unwrap();
+        let body = client.get("/document").dispatch().body_string().unwrap();
+        assert!(body.starts_with("<html><head><meta name=\"csrf-token\" content=\""));
+        assert!(body.ends_with("\"><title>t</title></head><body></body></html>"));
+    }
+
+    #[test]
+    fn test_js_global() {
+        use data_encoding::BASE64;
+        use sha2::{Digest, Sha256};
+
+        fn document<'r>(_: &'r Request, _: Data) -> handler::Outcome<'r> {
+            handler::Outcome::Success(
+                Response::build()
+                    .header(ContentType::HTML)
+                    .raw_header("Content-Security-Policy", "default-src 'self'; img-src *")
+                    .sized_body(Cursor::new("<html><head></head><body></body></html>"))
+                    .finalize(),
+            )
+        }
+        let build = |mode| {
+            let rocket = ::rocket::ignite()
+                .attach(
+                    CsrfFairingBuilder::new()
+                        .set_secret([0; 32])
+                        .set_js_global(Some("csrfToken".to_owned()))
+                        .set_js_global_mode(mode)
+                        .finalize()
+                        .unwrap(),
+                )
+                .mount("/", vec![Route::new(Method::Get, "/document", document)]);
+            Client::new(rocket).unwrap()
+        };
+        let client = build(JsGlobalMode::HashedInlineScript);
+        let mut response = client.get("/document").dispatch();
+        let body = response.body_string().unwrap();
+        let script = body
+            .split("<script>")
+            .nth(1)
+            .and_then(|rest| rest.split("</script>").next())
+            .unwrap();
+        assert!(script.starts_with("window.csrfToken = \""));
+        let hash = BASE64.encode(&Sha256::digest(script.as_bytes()));
+        assert_eq!(
+            response.headers().get_one("Content-Security-Policy"),
+            Some(format!("default-src 'self' 'sha256-{}'; img-src *", hash).as_str())
+        );
+
+        let client = build(JsGlobalMode::InlineScript);
+        let mut response = client.get("/document").dispatch();
+        assert!(!response.body_string().unwrap().contains("<script"));
+
+        for name in &["1token", "a;alert(1)", "window.csrf"] {
+            let error = CsrfFairingBuilder::new()
+                .set_js_global(Some(name.to_string()))
+                .finalize()
+                .err();
+            assert_eq!(error, Some(CsrfConfigError::InvalidJsGlobal(name.to_string())));
+        }
+    }
+
+    #[test]
+    fn test_htmx() {
+        fn document<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+            let page = "<html><head></head><body class=\"app\"><form></form></body></html>";
+            handler::Outcome::from(request, ::rocket::response::content::Html(page))
+        }
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .htmx()
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount("/", vec![Route::new(Method::Get, "/document", document)]);
+        let client = Client::new(rocket).unwrap();
+        let body = client.get("/document").dispatch().body_string().unwrap();
+        assert!(body.contains("<meta name=\"csrf-token\""));
+        assert!(body.contains("<body hx-headers='{\"X-CSRF-Token\": \""));
+        assert!(body.contains("\"}' class=\"app\"><form><input type=\"hidden\""));
+
+        let body = client
+            .get("/document")
+            .header(Header::new("HX-Request", "true"))
+            .dispatch()
+            .body_string()
+            .unwrap();
+        assert!(body.starts_with("<html><head></head><body class=\"app\"><form><input"));
+    }
+
+    #[test]
+    fn test_turbo() {
+        fn document<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+            let page = "<html><head></head><body></body></html>";
+            handler::Outcome::from(request, ::rocket::response::content::Html(page))
+        }
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .turbo()
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/document", document),
+                    Route::new(Method::Post, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/document").dispatch();
+        let cookies = response.cookies();
+        let body = response.body_string().unwrap();
+        let start = "<html><head><meta name=\"csrf-param\" content=\"csrf-token\">";
+        assert!(body.starts_with(start));
+        let token = body
+            .split("<meta name=\"csrf-token\" content=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap()
+            .to_owned();
+
+        let mut request = client.post("/page").header(Header::new("X-CSRF-Token", token));
+        for cookie in cookies {
+            request = request.cookie(cookie);
+        }
+        assert_eq!(request.dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn test_forms_untouched() {
+        fn document<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+            let page = "<form method=GET><input name=q></form><form method=\"post\"></form>\
+                    <form method=post data-csrf=OFF></form>";
+            handler::Outcome::from(request, ::rocket::response::content::Html(page))
+        }
+        let rocket = ::rocket::ignite()
+            .attach(CsrfFairingBuilder::new().set_secret([0; 32]).finalize().unwrap())
+            .mount("/", vec![Route::new(Method::Get, "/document", document)]);
+        let client = Client::new(rocket).unwrap();
+        let body = client.get("/document").dispatch().body_string().unwrap();
+        let get_form = "<form method=GET><input name=q></form>";
+        assert!(body.starts_with(&format!("{}<form method=\"post\"><input", get_form)));
+        assert_eq!(body.matches("type=\"hidden\"").count(), 1);
+        assert!(body.ends_with("<form method=post data-csrf=OFF></form>"));
+    }
+
+    #[test]
+    fn test_opt_out_attribute() {
+        fn document<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+            let page = "<form method=post data-csrf=\"off\"></form>\
+                    <form method=post data-csrf=\"on\"></form>\
+                    <form method=post data-token=off></form>";
+            handler::Outcome::from(request, ::rocket::response::content::Html(page))
+        }
+        let body = |builder: CsrfFairingBuilder| {
+            let rocket = ::rocket::ignite()
+                .attach(builder.set_secret([0; 32]).finalize().unwrap())
+                .mount("/", vec![Route::new(Method::Get, "/document", document)]);
+            let client = Client::new(rocket).unwrap();
+            let mut response = client.get("/document").dispatch();
+            response.body_string().unwrap()
+        };
+
+        let default = body(CsrfFairingBuilder::new());
+        assert!(default.starts_with("<form method=post data-csrf=\"off\"></form>"));
+        assert!(default.contains("<form method=post data-csrf=\"on\"><input"));
+        assert!(default.contains("<form method=post data-token=off><input"));
+        assert_eq!(default.matches("type=\"hidden\"").count(), 2);
+
+        let custom = body(CsrfFairingBuilder::new().set_opt_out_attribute("data-token".to_owned()));
+        assert!(custom.starts_with("<form method=post data-csrf=\"off\"><input"));
+        assert!(custom.contains("<form method=post data-csrf=\"on\"><input"));
+        assert!(custom.ends_with("<form method=post data-token=off></form>"));
+        assert_eq!(custom.matches("type=\"hidden\"").count(), 2);
+    }
+
+    #[test]
+    fn test_field_template() {
+        let template = "<input id=csrf name=\"{name}\" value=\"{token}\">";
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_field_template(template.to_owned())
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount("/", vec![Route::new(Method::Get, "/page", page)]);
+        let client = Client::new(rocket).unwrap();
+        let body = client.get("/page").dispatch().body_string().unwrap();
+        let start = "<html><body><form method=\"post\"><input id=csrf name=\"csrf-token\" value=\"";
+        assert!(body.starts_with(start));
+        assert!(!form_token(&body).is_empty());
+    }
+
+    #[test]
+    fn test_malformed_html() {
+        let rocket = ::rocket::ignite()
+            .attach(CsrfFairingBuilder::new().set_secret([0; 32]).finalize().unwrap())
+            .mount("/", vec![Route::new(Method::Get, "/malformed/<page>", malformed)]);
+        let client = Client::new(rocket).unwrap();
+        for (page, &(_, expected)) in MALFORMED.iter().enumerate() {
+            let uri = format!("/malformed/{}", page);
+            let body = client.get(uri).dispatch().body_string().unwrap();
+            let token = body
+                .split("name=\"csrf-token\" value=\"")
+                .nth(1)
+                .and_then(|rest| rest.split('"').next())
+                .unwrap_or("");
+            let field = format!("<input type=\"hidden\" name=\"csrf-token\" value=\"{}\">", token);
+            assert_eq!(body, expected.replace("FIELD", &field));
+        }
+    }
+
+    #[test]
+    fn test_charset() {
+        fn document<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+            let charset = request.get_param::<String>(0).unwrap_or_default();
+            let (page, content_type) = if charset == "meta" {
+                let page = "<meta charset=\"iso-2022-jp\"><form method=post></form>";
+                (page, ContentType::HTML)
+            } else {
+                let page = "<form method=post></form>";
+                (page, ContentType::with_params("text", "html", ("charset", charset)))
+            };
+            handler::Outcome::Success(
+                Response::build()
+                    .header(content_type)
+                    .sized_body(Cursor::new(page))
+                    .finalize(),
+            )
+        }
+        let rocket = ::rocket::ignite()
+            .attach(CsrfFairingBuilder::new().set_secret([0; 32]).finalize().unwrap())
+            .mount("/", vec![Route::new(Method::Get, "/document/<charset>", document)]);
+        let client = Client::new(rocket).unwrap();
+        let body = |charset: &str| {
+            let uri = format!("/document/{}", charset);
+            client.get(uri).dispatch().body_string().unwrap()
+        };
+        assert!(body("windows-1252").contains("type=\"hidden\""));
+        assert_eq!(body("utf-16"), "<form method=post></form>");
+        assert_eq!(body("meta"), "<meta charset=\"iso-2022-jp\"><form method=post></form>");
+    }
+
+    fn encoded<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+        let encoding = request.get_param::<String>(0).unwrap_or_default();
+        let mut page = Vec::new();
+        ::content_encoding::encoder(Some(&encoding), Box::new(PAGE.as_bytes()))
+            .read_to_end(&mut page)
+            .unwrap();
+        handler::Outcome::Success(
+            Response::build()
+                .header(ContentType::HTML)
+                .raw_header("Content-Encoding", encoding)
+                .sized_body(Cursor::new(page))
+                .finalize(),
+        )
+    }
+
+    #[test]
+    fn test_encoded_body() {
+        let rocket = ::rocket::ignite()
+            .attach(CsrfFairingBuilder::new().set_secret([0; 32]).finalize().unwrap())
+            .mount("/", vec![Route::new(Method::Get, "/encoded/<encoding>", encoded)]);
+        let client = Client::new(rocket).unwrap();
+        let body = client.get("/encoded/compress").dispatch().body_string().unwrap();
+        assert_eq!(body, PAGE); //not supported, sent untouched
+
+        if cfg!(feature = "flate2") {
+            let body = client.get("/encoded/gzip").dispatch().body_bytes().unwrap();
+            let mut decoded = String::new();
+            ::content_encoding::decoder(Some("gzip"), Box::new(&body[..]))
+                .read_to_string(&mut decoded)
+                .unwrap();
+            assert!(decoded.contains("<form method=\"post\"><input type=\"hidden\""));
+        }
+    }
+
+    #[test]
+    fn test_cache_headers() {
+        assert_eq!(private_cache_control(None), "private");
+        assert_eq!(
+            private_cache_control(Some("public, max-age=600, s-maxage=3600")),
+            "private, max-age=600"
+        );
+        assert_eq!(private_cache_control(Some("no-store")), "no-store");
+
+        let client = client();
+        let response = client.get("/page").dispatch();
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("private"));
+        assert_eq!(response.headers().get_one("Vary"), Some("Cookie"));
+        let response = client.get("/not_modified").dispatch();
+        assert_eq!(response.headers().get_one("Vary"), None); //nothing inserted
+    }
+
+    #[test]
+    fn test_stale_headers_removed() {
+        fn tagged<'r>(_: &'r Request, _: Data) -> handler::Outcome<'r> {
+            handler::Outcome::Success(
+                Response::build()
+                    .header(ContentType::HTML)
+                    .raw_header("ETag", "\"v1\"")
+                    .raw_header("Content-Length", PAGE.len().to_string())
+                    .sized_body(Cursor::new(PAGE))
+                    .finalize(),
+            )
+        }
+        let rocket = ::rocket::ignite()
+            .attach(CsrfFairingBuilder::new().set_secret([0; 32]).finalize().unwrap())
+            .mount("/", vec![Route::new(Method::Get, "/tagged", tagged)]);
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/tagged").dispatch();
+        assert_eq!(response.headers().get_one("ETag"), None);
+        let length = response.headers().get_one("Content-Length").map(|l| l.to_owned());
+        let body = response.body_string().unwrap();
+        assert!(body.len() > PAGE.len());
+        assert!(length.map(|length| length == body.len().to_string()).unwrap_or(true));
+    }
+
+    #[test]
+    fn test_preserve_content_length() {
+        fn document<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+            let page = "<form method=post></form><form method=post></form>";
+            handler::Outcome::from(request, ::rocket::response::content::Html(page))
+        }
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_auto_insert_max_chunk_size(8)
+                    .set_preserve_content_length(true)
+                    .set_unique_token_per_form(true)
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount("/", vec![Route::new(Method::Get, "/document", document)]);
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/document").dispatch();
+        match response.body() {
+            Some(Body::Sized(reader, size)) => {
+                let mut body = String::new();
+                reader.read_to_string(&mut body).unwrap();
+                let tokens: Vec<&str> = body
+                    .split("value=\"")
+                    .skip(1)
+                    .filter_map(|rest| rest.split('"').next())
+                    .collect();
+                assert_eq!(tokens.len(), 2);
+                assert_ne!(tokens[0], tokens[1]); //each form has its own token
+                assert_eq!(size, body.len() as u64);
+            }
+            _ => panic!("body sent chunked"),
+        }
+    }
+
+    #[test]
+    fn test_token_generated_once() {
+        #[derive(Default)]
+        struct Issued(AtomicUsize);
+        impl CsrfMetrics for Arc<Issued> {
+            fn token_issued(&self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        let issued = Arc::new(Issued::default());
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_metrics(issued.clone())
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount("/", vec![Route::new(Method::Get, "/page", page)]);
+        let client = Client::new(rocket).unwrap();
+        let response = client.get("/page").dispatch();
+        assert_eq!(response.headers().get("Set-Cookie").count(), 1);
+        assert_eq!(issued.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_guards_share_token() {
+        fn guards<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+            let first = request.guard::<CsrfToken>().unwrap();
+            let second = request.guard::<CsrfToken>().unwrap();
+            let same = first.value() == second.value();
+            handler::Outcome::from(request, if same { "same" } else { "different" })
+        }
+        let rocket = ::rocket::ignite()
+            .attach(CsrfFairingBuilder::new().set_secret([0; 32]).finalize().unwrap())
+            .mount("/", vec![Route::new(Method::Get, "/guards", guards)]);
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/guards").dispatch();
+        assert_eq!(response.body_string(), Some("same".to_owned()));
+    }
+
+    #[test]
+    fn test_cookie_attributes() {
+        fn set_cookie(configure: fn(CsrfFairingBuilder) -> CsrfFairingBuilder) -> Vec<String> {
+            let builder = configure(CsrfFairingBuilder::new().set_secret([0; 32]));
+            let rocket = ::rocket::ignite()
+                .attach(builder.finalize().unwrap())
+                .mount("/", vec![Route::new(Method::Get, "/page", page)]);
+            let client = Client::new(rocket).unwrap();
+            let response = client.get("/page").dispatch();
+            let set_cookie = response.headers().get_one("Set-Cookie").unwrap();
+            set_cookie.split("; ").map(|part| part.to_owned()).collect()
+        }
+        let has = |cookie: &[String], attribute: &str| cookie.iter().any(|part| part == attribute);
+
+        let default = set_cookie(|builder| builder);
+        assert!(default[0].starts_with("csrf="));
+        assert!(has(&default, "HttpOnly"));
+        assert!(has(&default, "SameSite=Lax"));
+        assert!(has(&default, "Path=/"));
+        assert!(!has(&default, "Secure"));
+        assert!(!default.iter().any(|part| part.starts_with("Domain=")));
+
+        let named = set_cookie(|builder| builder.set_cookie_name("xsrf".to_owned()));
+        assert!(named[0].starts_with("xsrf="));
+        let strict = set_cookie(|builder| builder.set_cookie_same_site(SameSite::Strict));
+        assert!(has(&strict, "SameSite=Strict") && !has(&strict, "SameSite=Lax"));
+        let secure = set_cookie(|builder| builder.set_cookie_secure(true));
+        assert!(has(&secure, "Secure"));
+        let readable = set_cookie(|builder| builder.set_cookie_http_only(false));
+        assert!(!has(&readable, "HttpOnly"));
+        let path = set_cookie(|builder| builder.set_cookie_path("/app".to_owned()));
+        assert!(has(&path, "Path=/app") && !has(&path, "Path=/"));
+        let domain = set_cookie(|builder| builder.set_cookie_domain("example.com".to_owned()));
+        assert!(has(&domain, "Domain=example.com"));
+    }
+
+    #[test]
+    fn test_cookie_prefix() {
+        let set_cookie = |prefix: CookiePrefix| {
+            let rocket = ::rocket::ignite()
+                .attach(
+                    CsrfFairingBuilder::new()
+                        .set_secret([0; 32])
+                        .set_cookie_path("/app".to_owned())
+                        .set_cookie_domain("example.com".to_owned())
+                        .set_cookie_prefix(prefix)
+                        .finalize()
+                        .unwrap(),
+                )
+                .mount("/", vec![Route::new(Method::Get, "/page", page)]);
+            let client = Client::new(rocket).unwrap();
+            let response = client.get("/page").dispatch();
+            let set_cookie = response.headers().get_one("Set-Cookie").unwrap();
+            set_cookie.split("; ").map(|part| part.to_owned()).collect::<Vec<_>>()
+        };
+        let secure = set_cookie(CookiePrefix::Secure);
+        assert!(secure[0].starts_with("__Secure-csrf="));
+        assert!(secure.contains(&"Secure".to_owned()));
+        assert!(secure.contains(&"Path=/app".to_owned()));
+        assert!(secure.contains(&"Domain=example.com".to_owned()));
+        let host = set_cookie(CookiePrefix::Host);
+        assert!(host[0].starts_with("__Host-csrf="));
+        assert!(host.contains(&"Secure".to_owned()));
+        assert!(host.contains(&"Path=/".to_owned()));
+        assert!(!host.iter().any(|part| part.starts_with("Domain=")));
+    }
+
+    #[test]
+    fn test_environment_defaults() {
+        let production = || {
+            rocket::custom(
+                Config::build(Environment::Production).finalize().unwrap(),
+                false,
+            )
+        };
+        match CsrfFairingBuilder::new().manage(production()) {
+            Err(CsrfError::Config(CsrfConfigError::MissingSecret)) => (),
+            _ => panic!("random secret accepted in production"),
+        }
+        match CsrfFairingBuilder::new().set_secret([0; 32]).manage(production()) {
+            Err(CsrfError::Config(CsrfConfigError::InsecureCookie)) => (),
+            _ => panic!("insecure cookie accepted in production"),
+        }
+        assert!(
+            CsrfFairingBuilder::new()
+                .set_secret([0; 32])
+                .set_cookie_secure(true)
+                .manage(production())
+                .is_ok()
+        );
+        assert!(
+            CsrfFairingBuilder::new()
+                .set_require_secret(false)
+                .set_require_secure_cookie(false)
+                .manage(production())
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_spa_mode() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .spa_mode()
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Post, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/page").dispatch();
+        assert_eq!(response.body_string().unwrap(), PAGE); //no auto-insert
+        let readable = response
+            .headers()
+            .get("Set-Cookie")
+            .find(|cookie| cookie.starts_with("XSRF-TOKEN="))
+            .map(|cookie| cookie.to_owned())
+            .unwrap();
+        assert!(!readable.contains("HttpOnly"));
+        let cookies = response.cookies();
+        let token = cookies
+            .iter()
+            .find(|cookie| cookie.name() == "XSRF-TOKEN")
+            .map(|cookie| cookie.value().to_owned())
+            .unwrap();
+        let submit = |header: Option<&str>| {
+            let mut request = client.post("/page");
+            if let Some(header) = header {
+                request = request.header(Header::new(header, token.clone()));
+            }
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            request.dispatch().status()
+        };
+        assert_eq!(submit(Some("X-XSRF-TOKEN")), Status::Ok);
+        assert_eq!(submit(None), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_csrf_verified() {
+        fn verified<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+            match request.guard::<CsrfVerified>() {
+                ::rocket::outcome::Outcome::Success(_) => {
+                    handler::Outcome::from(request, "verified")
+                }
+                _ => handler::Outcome::Failure(Status::Forbidden),
+            }
+        }
+        fn by_hand<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+            let token = request.headers().get_one("X-Custom").unwrap_or("");
+            match verify_token(request, token) {
+                Ok(()) => handler::Outcome::from(request, "verified"),
+                Err(_) => handler::Outcome::Failure(Status::Forbidden),
+            }
+        }
+        let rocket = ::rocket::ignite()
+            .attach(CsrfFairingBuilder::new().set_secret([0; 32]).finalize().unwrap())
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Get, "/verified", verified), //never verified by the fairing
+                    Route::new(Method::Get, "/by_hand", by_hand),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/page").dispatch();
+        let cookies = response.cookies();
+        let token = form_token(&response.body_string().unwrap());
+        let status = |path: &str, header: &str, token: &str| {
+            let mut request = client
+                .get(path.to_owned())
+                .header(Header::new(header.to_owned(), token.to_owned()));
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            request.dispatch().status()
+        };
+        assert_eq!(status("/verified", "X-CSRF-Token", &token), Status::Ok);
+        assert_eq!(status("/verified", "X-CSRF-Token", "forged"), Status::Forbidden);
+        assert_eq!(status("/verified", "X-Custom", &token), Status::Forbidden); //not the header
+        assert_eq!(status("/by_hand", "X-Custom", &token), Status::Ok);
+        assert_eq!(status("/by_hand", "X-Custom", "forged"), Status::Forbidden);
+        assert_eq!(client.get("/verified").dispatch().status(), Status::Forbidden); //no cookie
+    }
+
+    fn token<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+        let token = request.guard::<CsrfToken>().unwrap();
+        handler::Outcome::from(request, String::from_utf8_lossy(token.value()).into_owned())
+    }
+
+    fn managed_client() -> Client {
+        fn verified<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+            match request.guard::<CsrfVerified>() {
+                ::rocket::outcome::Outcome::Success(_) => {
+                    handler::Outcome::from(request, "verified")
+                }
+                _ => handler::Outcome::Failure(Status::Forbidden),
+            }
+        }
+        let rocket = CsrfFairingBuilder::new()
+            .set_secret([0; 32])
+            .manage(::rocket::ignite())
+            .unwrap()
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Get, "/token", token),
+                    Route::new(Method::Post, "/page", page),
+                    Route::new(Method::Post, "/verified", verified),
+                ],
+            );
+        Client::new(rocket).unwrap()
+    }
+
+    #[test]
+    fn test_manage() {
+        let client = managed_client();
+        let mut response = client.get("/page").dispatch();
+        assert!(response.headers().get_one("Set-Cookie").is_none()); //no guard, no cookie
+        assert_eq!(response.body_string().unwrap(), PAGE); //nothing is inserted
+        assert_eq!(client.post("/page").dispatch().status(), Status::Ok); //nor verified
+
+        let mut response = client.get("/token").dispatch();
+        let cookies = response.cookies();
+        assert!(!cookies.is_empty());
+        let token = response.body_string().unwrap();
+        let submit = |token: &str| {
+            let mut request = client
+                .post("/verified")
+                .header(Header::new("X-CSRF-Token", token.to_owned()));
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            request.dispatch().status()
+        };
+        assert_eq!(submit(&token), Status::Ok);
+        assert_eq!(submit("forged"), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_exempt_after_protected() {
+        fn field<'r>(request: &'r Request, data: Data) -> handler::Outcome<'r> {
+            use rocket::http::RawStr;
+            use rocket::request::FromFormValue;
+
+            let mut body = String::new();
+            data.open().read_to_string(&mut body).unwrap();
+            let token = body.trim_left_matches("csrf-token=");
+            match CsrfTokenField::from_form_value(RawStr::from_str(token)) {
+                Ok(_) => handler::Outcome::from(request, "valid"),
+                Err(_) => handler::Outcome::Failure(Status::Forbidden),
+            }
+        }
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .exempt_mount("/api".to_owned())
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Post, "/page", page),
+                    Route::new(Method::Post, "/api/field", field),
+                    Route::new(Method::Post, "/api/token", token),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/page").dispatch();
+        let cookies = response.cookies();
+        let token = form_token(&response.body_string().unwrap());
+        let mut request = client
+            .post("/page")
+            .header(ContentType::Form)
+            .body(format!("csrf-token={}", form_encode(&token)));
+        for cookie in cookies {
+            request = request.cookie(cookie);
+        }
+        assert_eq!(request.dispatch().status(), Status::Ok); //protected, with its cookie
+
+        let response = client
+            .post("/api/field")
+            .header(ContentType::Form)
+            .body(format!("csrf-token={}", form_encode(&token)))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden); //no cookie here, not even the last one
+        let mut response = client.post("/api/token").dispatch();
+        assert!(!response.cookies().is_empty()); //a cookie of its own
+        assert_ne!(response.body_string().unwrap(), token);
+    }
+
+    #[test]
+    fn test_manage_token_per_request() {
+        let get_token = |client: Client| {
+            let mut response = client.get("/token").dispatch();
+            (response.cookies(), response.body_string().unwrap())
+        };
+        let (first_cookies, first_token) = get_token(managed_client());
+        let (second_cookies, second_token) = get_token(managed_client()); //same thread
+        assert!(!first_cookies.is_empty());
+        assert!(!second_cookies.is_empty()); //the second client got its own cookie
+        assert_ne!(first_token, second_token);
+    }
+
+    #[test]
+    fn test_safe_methods() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .set_safe_methods(vec![Method::Head, Method::Options, Method::Put])
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Put, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        assert_eq!(client.get("/page").dispatch().status(), Status::Forbidden); //GET is protected
+        let response = client.put("/page").dispatch();
+        assert_eq!(response.status(), Status::Ok); //not verified, and issue the cookie
+        assert!(response.headers().get_one("Set-Cookie").is_some());
+        let response = client.head("/page").dispatch();
+        assert!(response.headers().get_one("Set-Cookie").is_some());
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_violation_logging() {
+        use log::{self, Level, Log, Metadata, Record};
+        use std::sync::Mutex;
+
+        struct Capture(Arc<Mutex<Vec<(Level, String)>>>);
+        impl Log for Capture {
+            fn enabled(&self, metadata: &Metadata) -> bool {
+                metadata.target() == "rocket_csrf"
+            }
+            fn log(&self, record: &Record) {
+                if self.enabled(record.metadata()) {
+                    let event = (record.level(), record.args().to_string());
+                    self.0.lock().unwrap().push(event);
+                }
+            }
+            fn flush(&self) {}
+        }
+        let events = Arc::new(Mutex::new(Vec::new()));
+        log::set_logger(Box::leak(Box::new(Capture(events.clone())))).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let level = |verbose: bool, path: &'static str| {
+            let rocket = ::rocket::ignite()
+                .attach(
+                    CsrfFairingBuilder::new()
+                        .set_secret([0; 32])
+                        .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                        .set_verbose(verbose)
+                        .finalize()
+                        .unwrap(),
+                )
+                .mount("/", vec![Route::new(Method::Post, path, page)]);
+            let client = Client::new(rocket).unwrap();
+            assert_eq!(client.post(path).dispatch().status(), Status::Forbidden);
+            let message = format!("Csrf violation on POST {}: ", path);
+            events
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(_, event)| event.starts_with(&message))
+                .map(|(level, _)| *level)
+        };
+        assert_eq!(level(true, "/loud"), Some(Level::Warn));
+        assert_eq!(level(false, "/quiet"), Some(Level::Debug));
+    }
+
+    #[test]
+    fn test_metrics() {
+        #[derive(Default)]
+        struct Recorder(::std::sync::Mutex<Vec<String>>);
+        impl CsrfMetrics for Arc<Recorder> {
+            fn token_issued(&self) {
+                self.0.lock().unwrap().push("issued".to_owned());
+            }
+            fn verification_passed(&self) {
+                self.0.lock().unwrap().push("passed".to_owned());
+            }
+            fn verification_failed(&self, reason: ViolationReason) {
+                self.0.lock().unwrap().push(format!("failed: {}", reason));
+            }
+            fn auto_insertion(&self) {
+                self.0.lock().unwrap().push("inserted".to_owned());
+            }
+            fn parse_error(&self) {
+                self.0.lock().unwrap().push("parse error".to_owned());
+            }
+        }
+        let recorder = Arc::new(Recorder::default());
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .set_metrics(recorder.clone())
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/page", page),
+                    Route::new(Method::Post, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let events = || recorder.0.lock().unwrap().drain(..).collect::<Vec<_>>();
+
+        let mut response = client.get("/page").dispatch();
+        let cookies = response.cookies();
+        let token = form_token(&response.body_string().unwrap());
+        assert_eq!(events(), vec!["issued", "inserted"]);
+
+        let mut request = client
+            .post("/page")
+            .header(ContentType::Form)
+            .body(format!("csrf-token={}", token));
+        for cookie in cookies {
+            request = request.cookie(cookie);
+        }
+        assert_eq!(request.dispatch().status(), Status::Ok);
+        assert!(events().contains(&"passed".to_owned()));
+
+        let request = client
+            .post("/page")
+            .cookie(::rocket::http::Cookie::new("csrf", "Z2FyYmFnZQ=="));
+        assert_eq!(request.dispatch().status(), Status::Forbidden);
+        let failed = format!("failed: {}", ViolationReason::MissingCookie);
+        let recorded = events();
+        assert!(recorded.contains(&"parse error".to_owned()));
+        assert!(recorded.contains(&failed));
+        assert!(!recorded.contains(&"passed".to_owned()));
+    }
+
+    #[test]
+    fn test_json_body_token() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/token", token),
+                    Route::new(Method::Post, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/token").dispatch();
+        let cookies = response.cookies();
+        let token = response.body_string().unwrap();
+        let submit = |body: String| {
+            let mut request = client.post("/page").header(ContentType::JSON).body(body);
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            request.dispatch().status()
+        };
+        assert_eq!(submit(format!("{{\"csrf_token\": \"{}\", \"a\": 1}}", token)), Status::Ok);
+        assert_eq!(submit(format!("{{\"csrf-token\": \"{}\"}}", token)), Status::Forbidden);
+        assert_eq!(submit("{\"a\": 1}".to_owned()), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_csrf_json_guard() {
+        fn json<'r>(request: &'r Request, data: Data) -> handler::Outcome<'r> {
+            match CsrfJson::<::serde_json::Value>::from_data(request, data) {
+                ::rocket::outcome::Outcome::Success(value) => {
+                    handler::Outcome::from(request, value.to_string())
+                }
+                ::rocket::outcome::Outcome::Failure((status, _)) => {
+                    handler::Outcome::Failure(status)
+                }
+                ::rocket::outcome::Outcome::Forward(_) => panic!("CsrfJson never forwards"),
+            }
+        }
+        let rocket = CsrfFairingBuilder::new()
+            .set_secret([0; 32])
+            .manage(::rocket::ignite())
+            .unwrap()
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/token", token),
+                    Route::new(Method::Post, "/json", json),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.get("/token").dispatch();
+        let cookies = response.cookies();
+        let token = response.body_string().unwrap();
+        let submit = |header: Option<&str>, body: String| {
+            let mut request = client.post("/json").header(ContentType::JSON).body(body);
+            if let Some(header) = header {
+                request = request.header(Header::new("X-CSRF-Token", header.to_owned()));
+            }
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            let mut response = request.dispatch();
+            (response.status(), response.body_string())
+        };
+        let body = format!("{{\"csrf_token\": \"{}\", \"a\": 1}}", token);
+        assert_eq!(submit(None, body), (Status::Ok, Some("{\"a\":1}".to_owned()))); //field removed
+        assert_eq!(submit(None, "{\"a\": 1}".to_owned()).0, Status::Forbidden);
+        assert_eq!(submit(None, "{\"a\": ".to_owned()).0, Status::Forbidden); //token first
+        assert_eq!(submit(Some(&token), "{\"a\": ".to_owned()).0, Status::BadRequest);
+        assert_eq!(submit(Some(&token), "{\"a\": 1}".to_owned()).0, Status::Ok);
+    }
+
+    #[test]
+    fn test_max_body_scan() {
+        fn import<'r>(request: &'r Request, data: Data) -> handler::Outcome<'r> {
+            match CsrfBody::from_data(request, data) {
+                ::rocket::outcome::Outcome::Success(mut body) => {
+                    let mut read = Vec::new();
+                    body.read_to_end(&mut read).unwrap();
+                    handler::Outcome::from(request, read.len().to_string())
+                }
+                ::rocket::outcome::Outcome::Failure((status, _)) => {
+                    handler::Outcome::Failure(status)
+                }
+                ::rocket::outcome::Outcome::Forward(_) => panic!("CsrfBody never forwards"),
+            }
+        }
+        let submit = |max_body_scan: u64| {
+            let rocket = ::rocket::ignite()
+                .attach(
+                    CsrfFairingBuilder::new()
+                        .set_secret([0; 32])
+                        .set_violation_action(ViolationAction::Respond(Status::Forbidden))
+                        .set_deferred_verification_prefix(vec!["/import".to_owned()])
+                        .set_max_body_scan(max_body_scan)
+                        .finalize()
+                        .unwrap(),
+                )
+                .mount(
+                    "/",
+                    vec![
+                        Route::new(Method::Get, "/page", page),
+                        Route::new(Method::Post, "/import", import),
+                    ],
+                );
+            let client = Client::new(rocket).unwrap();
+            let mut response = client.get("/page").dispatch();
+            let cookies = response.cookies();
+            let token = form_token(&response.body_string().unwrap());
+            let body = format!(
+                "{}csrf-token={}&{}",
+                "record=abc&".repeat(1000),
+                form_encode(&token),
+                "record=def&".repeat(1000)
+            ); //the token is far beyond what the fairing sees
+            let mut request = client.post("/import").header(ContentType::Form).body(body.clone());
+            for cookie in cookies {
+                request = request.cookie(cookie);
+            }
+            let mut response = request.dispatch();
+            (response.status(), response.body_string(), body.len())
+        };
+        let (status, read, len) = submit(16 * 1024);
+        assert_eq!(status, Status::Ok);
+        assert_eq!(read, Some(len.to_string())); //the route got the whole body back
+        assert_eq!(submit(4 * 1024).0, Status::Forbidden); //the token is after the scanned part
+    }
+
+    #[test]
+    fn test_finalize_errors() {
+        let error = CsrfFairingBuilder::new()
+            .set_secret([0; 32])
+            .set_default_target("/violation/<id>".to_owned(), Method::Get)
+            .finalize()
+            .err();
+        assert_eq!(
+            error,
+            Some(CsrfConfigError::InvalidDefaultTarget("/violation/<id>".to_owned()))
+        );
+        let error = CsrfFairingBuilder::new()
+            .set_secret([0; 32])
+            .set_exceptions(vec![(
+                "/some/<other>/path".to_owned(),
+                "/error?where=<elsewhere>".to_owned(),
+                Method::Get,
+            )])
+            .finalize()
+            .err();
+        assert_eq!(
+            error,
+            Some(CsrfConfigError::IncompatibleExceptionParams {
+                source: "/some/<other>/path".to_owned(),
+                target: "/error?where=<elsewhere>".to_owned(),
+            })
+        );
+        let error = CsrfFairingBuilder::new()
+            .set_secret([0; 32])
+            .set_field_template("<input name=\"{name}\">".to_owned())
+            .finalize()
+            .err();
+        assert_eq!(
+            error,
+            Some(CsrfConfigError::InvalidFieldTemplate("<input name=\"{name}\">".to_owned()))
+        );
+        assert!(
+            CsrfFairingBuilder::new()
+                .set_secret([0; 32])
+                .set_exceptions(vec![(
+                    "/some/<other>/path".to_owned(),
+                    "/<locale>/error?where=<other>".to_owned(),
+                    Method::Get,
+                )])
+                .finalize()
+                .is_ok()
+        );
+    }
+
+    fn violation<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+        let reason = request
+            .guard::<CsrfViolation>()
+            .succeeded()
+            .map(|violation| violation.reason().to_string())
+            .unwrap_or_default();
+        handler::Outcome::from(request, reason)
+    }
+
+    #[test]
+    fn test_violation_reason_guard() {
+        let rocket = ::rocket::ignite()
+            .attach(
+                CsrfFairingBuilder::new()
+                    .set_secret([0; 32])
+                    .set_default_target("/violation".to_owned(), Method::Get)
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/violation", violation),
+                    Route::new(Method::Post, "/page", page),
+                ],
+            );
+        let client = Client::new(rocket).unwrap();
+        let mut response = client.post("/page").dispatch();
+        assert_eq!(response.body_string().unwrap(), "missing csrf cookie");
+        let mut response = client.get("/violation").dispatch();
+        assert_eq!(response.body_string().unwrap(), "");
+    }
+
+    #[test]
+    fn test_custom_policy() {
+        struct ApiKeyPolicy;
+        impl CsrfPolicy for ApiKeyPolicy {
+            fn check(&self, request: &Request, _: &PolicyContext) -> PolicyOutcome {
+                match request.headers().get_one("X-Api-Key") {
+                    Some("valid") => PolicyOutcome::Accept,
+                    Some(_) => PolicyOutcome::Reject(ViolationReason::UntrustedOrigin),
+                    None => PolicyOutcome::Continue,
+                }
+            }
+        }
+        let client = policy_client(CsrfFairingBuilder::new().add_policy(ApiKeyPolicy));
+        let (cookies, token) = policy_cookies(&client);
+        let submit = |api_key: Option<&'static str>, body: &str| {
+            let mut request = client
+                .post("/page")
+                .header(ContentType::Form)
+                .body(body.to_owned());
+            if let Some(api_key) = api_key {
+                request = request.header(Header::new("X-Api-Key", api_key));
+            }
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            request.dispatch().body_string().unwrap()
+        };
+        assert_eq!(submit(Some("valid"), ""), PAGE); //accepted without token
+        let rejected = ViolationReason::UntrustedOrigin.to_string();
+        let body = format!("csrf-token={}", token);
+        assert_eq!(submit(Some("stolen"), &body), rejected); //checked before the body token
+        assert_eq!(submit(None, &body), PAGE); //left to the next policies
+        assert_eq!(submit(None, ""), ViolationReason::MissingToken.to_string());
+    }
+
+    #[test]
+    fn test_policy_order() {
+        struct RejectAll;
+        impl CsrfPolicy for RejectAll {
+            fn check(&self, _: &Request, _: &PolicyContext) -> PolicyOutcome {
+                PolicyOutcome::Reject(ViolationReason::InvalidToken)
+            }
+        }
+        let client = policy_client(
+            CsrfFairingBuilder::new()
+                .set_custom_header("X-Requested-With".to_owned(), None)
+                .set_fetch_metadata_policy(FetchMetadataPolicy::Strict)
+                .add_policy(RejectAll),
+        );
+        let (cookies, token) = policy_cookies(&client);
+        let submit = |headers: &[(&'static str, &str)], body: &str| {
+            let mut request = client
+                .post("/page")
+                .header(ContentType::Form)
+                .body(body.to_owned());
+            for &(name, value) in headers {
+                request = request.header(Header::new(name, value.to_owned()));
+            }
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            request.dispatch().body_string().unwrap()
+        };
+        let body = format!("csrf-token={}", token);
+        let cross_site = ViolationReason::CrossSiteRequest.to_string();
+        let invalid = ViolationReason::InvalidToken.to_string();
+        //the custom header comes first, then fetch metadata, then the header token, then the
+        //policies added to the builder, and the body token last
+        let cross_site_header = ("Sec-Fetch-Site", "cross-site");
+        let header_token = ("X-CSRF-Token", token.as_str());
+        assert_eq!(submit(&[("X-Requested-With", "x"), cross_site_header], ""), PAGE);
+        assert_eq!(submit(&[cross_site_header, header_token], ""), cross_site);
+        assert_eq!(submit(&[("Sec-Fetch-Site", "same-origin")], ""), PAGE);
+        assert_eq!(submit(&[header_token], ""), PAGE);
+        assert_eq!(submit(&[], &body), invalid);
+    }
+
+    #[test]
+    fn test_renewal_only_on_token() {
+        let client = policy_client(
+            CsrfFairingBuilder::new()
+                .set_custom_header("X-Requested-With".to_owned(), None)
+                .set_sliding_expiration(SlidingExpiration::OnVerifiedRequest),
+        );
+        let (cookies, token) = policy_cookies(&client);
+        let renewed = |header: (&'static str, &str)| {
+            let mut request = client
+                .post("/page")
+                .header(Header::new(header.0, header.1.to_owned()));
+            for cookie in &cookies {
+                request = request.cookie(cookie.clone());
+            }
+            let response = request.dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            response.cookies().iter().any(|cookie| cookie.name() == "csrf")
+        };
+        assert!(renewed(("X-CSRF-Token", token.as_str())));
+        assert!(!renewed(("X-Requested-With", "x"))); //accepted, but not for a token
+    }
+
+    fn policy_client(builder: CsrfFairingBuilder) -> Client {
+        let rocket = ::rocket::ignite()
+            .attach(
+                builder
+                    .set_secret([0; 32])
+                    .set_default_target("/violation".to_owned(), Method::Get)
+                    .set_auto_insert(false)
+                    .finalize()
+                    .unwrap(),
+            )
+            .mount(
+                "/",
+                vec![
+                    Route::new(Method::Get, "/token", token),
+                    Route::new(Method::Get, "/violation", violation),
+                    Route::new(Method::Post, "/page", page),
+                ],
+            );
+        Client::new(rocket).unwrap()
+    }
+
+    fn policy_cookies(client: &Client) -> (Vec<::rocket::http::Cookie<'static>>, String) {
+        //csrf cookie, and a token for it
+        let mut response = client.get("/token").dispatch();
+        (response.cookies(), response.body_string().unwrap())
+    }
+
+    #[test]
+    fn test_body_content_types() {
+        let submit = |builder: CsrfFairingBuilder, content_type: ContentType| {
+            let rocket = ::rocket::ignite()
+                .attach(
+                    builder
+                        .set_secret([0; 32])
+                        .set_default_target("/violation".to_owned(), Method::Get)
+                        .finalize()
+                        .unwrap(),
+                )
+                .mount(
+                    "/",
+                    vec![
+                        Route::new(Method::Get, "/violation", violation),
+                        Route::new(Method::Get, "/page", page),
+                        Route::new(Method::Post, "/page", page),
+                    ],
+                );
+            let client = Client::new(rocket).unwrap();
+            let mut response = client.get("/page").dispatch();
+            let token = form_token(&response.body_string().unwrap());
+            let mut request = client
+                .post("/page")
+                .header(content_type)
+                .body(format!("csrf-token={}", token));
+            for cookie in response.cookies() {
+                request = request.cookie(cookie);
+            }
+            request.dispatch().body_string().unwrap()
+        };
+        assert_eq!(
+            submit(CsrfFairingBuilder::new(), ContentType::Plain),
+            "unsupported body content type"
+        );
+        assert!(submit(CsrfFairingBuilder::new(), ContentType::Form).starts_with("<html>"));
+        let plain = CsrfFairingBuilder::new().set_body_content_types(vec![ContentType::Plain]);
+        assert!(submit(plain, ContentType::Plain).starts_with("<html>"));
     }
 }
@@ -0,0 +1,83 @@
+use rocket::http::RawStr;
+use rocket::request::FromFormValue;
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use error::CsrfError;
+use token::{TokenEngine, FORM_FIELD};
+use utils::form_decode;
+
+thread_local! {
+    //engine, raw csrf cookie and token binding of the request being handled by this thread.
+    //Rocket handle a request from the fairing to the handler on a single thread, so this is set
+    //by the fairing and read while parsing forms.
+    static CURRENT_COOKIE: RefCell<Option<CurrentCookie>> = RefCell::new(None);
+}
+
+thread_local! {
+    //name of the token field for the request being handled by this thread, set by the fairing
+    static CURRENT_FIELD: RefCell<String> = RefCell::new(FORM_FIELD.to_owned());
+}
+
+pub fn set_current_field(field_name: &str) {
+    CURRENT_FIELD.with(|field| *field.borrow_mut() = field_name.to_owned());
+}
+
+pub fn current_field() -> String {
+    CURRENT_FIELD.with(|field| field.borrow().clone())
+}
+
+type CurrentCookie = (Arc<TokenEngine>, Vec<u8>, Option<Vec<u8>>);
+
+pub fn set_current_cookie(current: Option<CurrentCookie>) {
+    CURRENT_COOKIE.with(|cookie| *cookie.borrow_mut() = current);
+}
+
+/// Form field holding a verified csrf token.
+///
+/// Declare it in your `FromForm` structs, so they can be parsed strictly, and form parsing fails
+/// if the token doesn't match the csrf cookie of the request. This require the
+/// [CsrfFairing](struct.CsrfFairing.html) to be attached, as it's the fairing which give the
+/// cookie to the form parser.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[derive(FromForm)]
+/// struct Comment {
+///     #[form(field = "csrf-token")]
+///     csrf_token: CsrfTokenField,
+///     content: String,
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CsrfTokenField(String);
+
+impl CsrfTokenField {
+    /// Get the token as it was submitted.
+    pub fn value(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'v> FromFormValue<'v> for CsrfTokenField {
+    type Error = CsrfError;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Self, CsrfError> {
+        let token = form_decode(form_value.as_str()).ok_or(CsrfError::InvalidToken)?;
+        CURRENT_COOKIE.with(|current| match *current.borrow() {
+            Some((ref engine, ref cookie, ref binding)) => {
+                let cookie = engine
+                    .parse_cookie(cookie)
+                    .ok_or(CsrfError::MissingCookie)?
+                    .bind(binding.clone());
+                if engine.verify(&token, &cookie) {
+                    Ok(CsrfTokenField(token.clone()))
+                } else {
+                    Err(CsrfError::InvalidToken)
+                }
+            }
+            None => Err(CsrfError::MissingCookie),
+        })
+    }
+}
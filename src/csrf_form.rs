@@ -0,0 +1,95 @@
+use rocket::data::{self, FromData};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::{FormItems, FromForm};
+use rocket::{Data, Request};
+use std::ops::Deref;
+
+use csrf_state::CsrfState;
+use csrf_token::parse_cookie;
+use error::CsrfError;
+use stripped_form::{field_name, read_form, strip_token};
+use utils::{form_decode, parse_args};
+
+/// Form data guard verifying the csrf token found in the body.
+///
+/// The `CsrfForm` type read the whole form body (up to the `forms` limit of Rocket's
+/// configuration), verify the token field against the csrf cookie, then parse the form strictly
+/// without the token field. Contrary to the fairing, which only see the begining of the body, it
+/// find the token wherever it is. Requests without a valid token fail with `403 Forbidden`.
+///
+/// It is meant for routes on which the fairing defer body verification, see
+/// [`set_deferred_verification_prefix`], or to protect individual routes without the fairing,
+/// see [`manage`].
+///
+/// [`set_deferred_verification_prefix`]: struct.CsrfFairingBuilder.html#method.set_deferred_verification_prefix
+/// [`manage`]: struct.CsrfFairingBuilder.html#method.manage
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[post("/upload", data = "<upload>")]
+/// fn upload(upload: CsrfForm<Upload>) -> Redirect {
+///     //...
+/// }
+/// ```
+#[derive(Debug)]
+pub struct CsrfForm<T>(pub T);
+
+impl<T> CsrfForm<T> {
+    /// Consume the guard, returning the parsed form.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for CsrfForm<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+pub fn body_token_valid(request: &Request, form: &str) -> bool {
+    //verify the token found in a form body against the csrf cookie
+    let state = match CsrfState::from(request) {
+        Ok(state) => state,
+        Err(_) => return false,
+    };
+    let cookie = match parse_cookie(request, state) {
+        Some(cookie) => cookie,
+        None => return false,
+    };
+    parse_args(form)
+        .filter(|(key, _)| key == &state.field_name)
+        .filter_map(|(_, token)| form_decode(token))
+        .any(|token| state.engine().verify(&token, &cookie))
+}
+
+pub fn read_verified_form(request: &Request, data: Data) -> Result<String, (Status, CsrfError)> {
+    //read a form body, verify its token, and give it back without the token field
+    let form = read_form(request, data).map_err(|e| (Status::BadRequest, e))?;
+    if !body_token_valid(request, &form) {
+        return Err((Status::Forbidden, CsrfError::InvalidToken));
+    }
+    Ok(strip_token(&form, &field_name(request)))
+}
+
+impl<T> FromData for CsrfForm<T>
+where
+    T: for<'f> FromForm<'f>,
+{
+    type Error = CsrfError;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, CsrfError> {
+        let form = match read_verified_form(request, data) {
+            Ok(form) => form,
+            Err(failure) => return Outcome::Failure(failure),
+        };
+        match T::from_form(&mut FormItems::from(form.as_str()), true) {
+            Ok(value) => Outcome::Success(CsrfForm(value)),
+            Err(_) => Outcome::Failure((Status::UnprocessableEntity, CsrfError::Parse(form))),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use rocket::data::{self, FromData};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::{Data, Request};
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use std::io::Read;
+use std::ops::{Deref, DerefMut};
+
+use csrf_state::CsrfState;
+use csrf_token::parse_cookie;
+use csrf_verified::verify_token;
+use error::CsrfError;
+
+pub const TOKEN_HEADER: &str = "X-CSRF-Token";
+pub const JSON_TOKEN_FIELD: &str = "csrf_token";
+const DEFAULT_JSON_LIMIT: u64 = 1 << 20;
+
+/// Json data guard verifying the csrf token before deserializing the body.
+///
+/// The `CsrfJson` type protect a single JSON route, without relying on the fairing's body
+/// inspection. The token is searched:
+///
+/// - in the `X-CSRF-Token` header (or the one set with [`set_token_header_name`]), in which case
+///   it is verified before the body is even read;
+/// - else in the `csrf_token` field (or the one set with [`set_json_token_field`]) of the top
+///   level JSON object, which is removed before deserializing, so `T` doesn't have to declare it.
+///
+/// Requests without a valid token fail with `403 Forbidden`, even if their body is not valid
+/// JSON, and the others with `400 Bad Request` if their body can't be deserialized. The body size
+/// is limited by the `json` limit of Rocket's configuration, or 1Mio by default.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[post("/api/message", data = "<message>")]
+/// fn post_message(message: CsrfJson<Message>) -> String {
+///     message.into_inner().text
+/// }
+/// ```
+///
+/// [`set_token_header_name`]: struct.CsrfFairingBuilder.html#method.set_token_header_name
+/// [`set_json_token_field`]: struct.CsrfFairingBuilder.html#method.set_json_token_field
+#[derive(Debug)]
+pub struct CsrfJson<T>(pub T);
+
+impl<T> CsrfJson<T> {
+    /// Consume the guard, returning the deserialized value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for CsrfJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for CsrfJson<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+pub fn header_token_valid(request: &Request) -> bool {
+    //verify the token sent in the token header against the csrf cookie
+    let state = match CsrfState::from(request) {
+        Ok(state) => state,
+        Err(_) => return false,
+    };
+    let cookie = match parse_cookie(request, state) {
+        Some(cookie) => cookie,
+        None => return false,
+    };
+    request
+        .headers()
+        .get(&state.token_header)
+        .any(|token| state.engine().verify(token, &cookie))
+}
+
+pub fn json_tokens(body: &str, field: &str, max_depth: usize) -> Vec<String> {
+    //find string values of a field in a JSON body, in objects nested at most max_depth levels
+    fn search(value: &Value, field: &str, depth: usize, found: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(token)) = map.get(field) {
+                    found.push(token.clone());
+                }
+                if depth > 0 {
+                    for value in map.values() {
+                        search(value, field, depth - 1, found);
+                    }
+                }
+            }
+            Value::Array(values) if depth > 0 => for value in values {
+                search(value, field, depth - 1, found);
+            },
+            _ => {}
+        }
+    }
+    let mut found = Vec::new();
+    if let Ok(value) = serde_json::from_str::<Value>(body) {
+        search(&value, field, max_depth, &mut found);
+    }
+    found
+}
+
+impl<T: DeserializeOwned> FromData for CsrfJson<T> {
+    type Error = CsrfError;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, CsrfError> {
+        let header_valid = header_token_valid(request);
+        let field = match CsrfState::from(request) {
+            Ok(state) => state.json_token_field.clone(),
+            Err(e) => return Outcome::Failure((Status::InternalServerError, e)),
+        };
+
+        let limit = request.limits().get("json").unwrap_or(DEFAULT_JSON_LIMIT);
+        let mut body = Vec::new();
+        let read = data.open().take(limit).read_to_end(&mut body);
+        let mut value: Value = match (read, serde_json::from_slice(&body)) {
+            (Ok(_), Ok(value)) => value,
+            _ if !header_valid => {
+                return Outcome::Failure((Status::Forbidden, CsrfError::InvalidToken))
+            } //no token could be verified, which matters more than the body being invalid
+            (Err(e), _) => return Outcome::Failure((Status::BadRequest, CsrfError::Io(e))),
+            (_, Err(e)) => {
+                return Outcome::Failure((Status::BadRequest, CsrfError::Parse(e.to_string())))
+            }
+        };
+
+        let token = match value {
+            Value::Object(ref mut map) => map.remove(&field),
+            _ => None,
+        }; //never give the token field to T, which may deny unknown fields
+        let body_valid = match token {
+            Some(Value::String(ref token)) => verify_token(request, token).is_ok(),
+            _ => false,
+        };
+        if !header_valid && !body_valid {
+            return Outcome::Failure((Status::Forbidden, CsrfError::InvalidToken));
+        }
+
+        match serde_json::from_value(value) {
+            Ok(value) => Outcome::Success(CsrfJson(value)),
+            Err(e) => Outcome::Failure((Status::BadRequest, CsrfError::Parse(e.to_string()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csrf_json::json_tokens;
+
+    #[test]
+    fn test_json_tokens() {
+        assert_eq!(json_tokens(r#"{"csrf_token":"abc","a":1}"#, "csrf_token", 0), vec!["abc"]);
+        assert!(json_tokens(r#"{"data":{"csrf_token":"abc"}}"#, "csrf_token", 0).is_empty());
+        assert_eq!(json_tokens(r#"{"data":{"csrf_token":"abc"}}"#, "csrf_token", 1), vec!["abc"]);
+        assert_eq!(json_tokens(r#"[{"csrf_token":"abc"}]"#, "csrf_token", 1), vec!["abc"]);
+        assert!(json_tokens(r#"{"csrf_token":42}"#, "csrf_token", 0).is_empty());
+        assert!(json_tokens(r#"{"csrf_token":"abc""#, "csrf_token", 0).is_empty()); //truncated body
+    }
+}
@@ -1,180 +1,738 @@
-use std::io::{Read, Error};
 use std::cmp;
+use std::io::{Error, Read};
+use std::mem;
+use std::str::from_utf8;
+use std::sync::Arc;
+
 use super::CsrfToken;
+use buffer_pool::BufferPool;
 use csrf_proxy::ParseState::*;
 
-#[derive(Debug)]
+//the proxy only finds where tags begin and end, following the tokenizer of the HTML5 spec for
+//comments, raw text elements and quoted attribute values, and copies every byte of the document
+//as is. Rewriters built on a full tokenizer don't fit: html5ever emits decoded tokens, so the page
+//would have to be serialized again, changing its quoting, case and entities, and lol_html needs a
+//far more recent compiler than Rocket 0.3 runs on
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum ParseState {
-    Reset,                          //default state
-    PartialFormMatch(u8),           //when parsing "<form"
-    SearchInput,                    //like default state, but inside a form
-    PartialInputMatch(u8, usize),   //when parsing "<input"
-    PartialFormEndMatch(u8, usize), //when parsing "</form" ('<' is actally done via PartialInputMarch)
-    SearchMethod(usize),            //when inside the first <input>, search for begining of a param
-    PartialNameMatch(u8, usize),    //when parsing "name="_method""
-    CloseInputTag, //only if insert after, search for '>' of a "<input name=\"_method\">"
+    Data,        //default state, text and tags
+    Comment,     //inside "<!-- ... -->", search for its end
+    RawText(&'static str), //inside <script> or another element whose content is text
+    Passthrough, //when document must be left untouched
 }
 
+#[derive(Debug, PartialEq)]
+enum Markup {
+    Text,                   //a '<' which begins nothing, like in "a < b"
+    Incomplete,             //what follows '<' is not read yet
+    Comment,                //"<!--", the comment goes on until "-->"
+    Other(usize),           //doctype, bogus comment or ignored end tag, with its length
+    StartTag(usize, usize), //length of the tag, and where its name ends
+    EndTag(usize, usize),   //length of the tag, and where its name ends
+}
+
+/// How documents using AMP (Accelerated Mobile Pages) are treated when inserting tokens.
+///
+/// AMP validation forbids some markup, and AMP pages are often served from caches which never
+/// forward the csrf cookie to your application.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AmpMode {
+    /// Insert tokens in AMP documents like in any other document. The inserted field is a plain
+    /// hidden input, which is valid in `action-xhr` forms.
+    Inject,
+    /// Leave documents whose root element is `<html amp>` or `<html ⚡>` untouched.
+    Skip,
+}
+
+const READ_SIZE: usize = 8 * 1024; //how much is read at once from the underlying body
+const MAX_TAG: usize = 4096; //longer tags are left untouched, their content is parsed as text
+pub const OPT_OUT_ATTRIBUTE: &str = "data-csrf";
+const RAW_TEXT: &[&str] = &[
+    "script", "style", "textarea", "title", "xmp", "iframe", "noembed", "noframes",
+]; //elements whose content is never parsed as tags
+const ASCII_COMPATIBLE: &[&str] = &[
+    "utf-8", "utf8", "us-ascii", "ascii", "iso-8859-", "iso8859-", "latin", "l1", "windows-",
+    "cp125", "koi8-", "euc-", "shift_jis", "shift-jis", "sjis", "windows-31j", "gbk",
+    "gb2312", "gb18030", "big5", "macintosh", "tis-620",
+]; //prefixes of charsets in which '<', '>', quotes and letters are always the ascii bytes
 
 pub struct CsrfProxy<'a> {
     underlying: Box<Read + 'a>, //the underlying Reader from which we get data
-    token: Vec<u8>,             //a full input tag loaded with a valid token
-    buf: Vec<(Vec<u8>, usize)>, //a stack of buffers, with a position in case a buffer was not fully transmited
+    input: Vec<u8>,             //what was read but not parsed, as it ends with an incomplete tag
+    output: Vec<u8>,            //what was parsed and rewritten, but not returned yet
+    output_pos: usize,          //how much of output was returned
+    eof: bool,                  //if the underlying Reader is exhausted
     state: ParseState,          //state of the parser
-    insert_tag: Option<usize>, //if we have to insert tag here, and how fare are we in the tag (in case of very short read()s)
+    in_form: bool,              //if the token is still to be inserted in the current form
+    template_depth: usize,      //number of <template> we are in, their forms are left untouched
+    token: Vec<u8>,             //markup inserted in the next form, loaded with a valid token
+    amp_mode: AmpMode,          //what to do when the document is an AMP one
+    csrf_token: CsrfToken,      //the token from which tags are generated
+    unique_per_form: bool,      //if each form should get its own token
+    head_content: Option<Vec<u8>>, //content to insert at the begining of <head>, if any and not done yet
+    body_attributes: Option<Vec<u8>>, //attributes to insert into <body>, if any and not done yet
+    document: Option<String>,   //path of the document, if tokens must be scoped to form actions
+    host: Option<String>,       //host of the document, forms posting elsewhere get no token
+    form_origins: Arc<Vec<String>>, //other origins forms may post tokens to
+    opt_out_attribute: String,  //forms with this attribute set to "off" get no token
+    field_template: Option<String>, //markup inserted in forms instead of the plain hidden input
+    pool: Option<Arc<BufferPool>>, //where input and output buffers come from, and go back to
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    //value of an attribute in the attributes of a tag, up to the closing '>'
+    let mut rest = tag.trim_left();
+    while !rest.is_empty() && !rest.starts_with('>') {
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '>')
+            .unwrap_or_else(|| rest.len());
+        let attribute = &rest[..end];
+        rest = rest[end..].trim_left();
+        let value = if rest.starts_with('=') {
+            rest = rest[1..].trim_left();
+            let (value, next) = match rest.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => {
+                    let end = rest[1..]
+                        .find(quote)
+                        .map(|end| end + 1)
+                        .unwrap_or_else(|| rest.len());
+                    (&rest[1..end], cmp::min(end + 1, rest.len()))
+                }
+                _ => {
+                    let end = rest
+                        .find(|c: char| c.is_whitespace() || c == '>')
+                        .unwrap_or_else(|| rest.len());
+                    (&rest[..end], end)
+                }
+            };
+            rest = rest[next..].trim_left();
+            value
+        } else {
+            ""
+        };
+        if attribute.eq_ignore_ascii_case(name) {
+            return Some(value.replace("&amp;", "&"));
+        }
+    }
+    None
+}
+
+fn resolve_action(document: &str, action: &str) -> String {
+    //path a form is submitted to, as the browser resolves its action against the document
+    let action = action.trim();
+    let action = &action[..action.find(|c| c == '?' || c == '#').unwrap_or_else(|| action.len())];
+    let scheme = action.find("://").filter(|&colon| {
+        action[..colon]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+    });
+    let authority = match scheme {
+        Some(colon) => Some(&action[colon + 3..]),
+        None if action.starts_with("//") => Some(&action[2..]),
+        None => None,
+    };
+    let directory = &document[..document.rfind('/').map(|i| i + 1).unwrap_or(0)];
+    let path = match authority {
+        Some(authority) => authority
+            .find('/')
+            .map(|slash| &authority[slash..])
+            .unwrap_or("/")
+            .to_owned(),
+        None if action.is_empty() => document.to_owned(),
+        None if action.starts_with('/') => action.to_owned(),
+        None => format!("{}{}", directory, action),
+    };
+    let mut segments: Vec<&str> = Vec::new();
+    let mut parts = path.split('/').skip(1).peekable();
+    while let Some(segment) = parts.next() {
+        match segment {
+            "." => if parts.peek().is_none() {
+                segments.push("");
+            },
+            ".." => {
+                segments.pop();
+                if parts.peek().is_none() {
+                    segments.push("");
+                }
+            }
+            segment => segments.push(segment),
+        }
+    } //remove dot segments, as in RFC 3986
+    format!("/{}", segments.join("/"))
+}
+
+fn is_external(action: &str, host: Option<&str>, form_origins: &[String]) -> bool {
+    //if a form action points to another origin than the document and the allowed ones
+    let action = action.trim();
+    let scheme = action.find(':').filter(|&colon| {
+        action[..colon].starts_with(|c: char| c.is_ascii_alphabetic())
+            && action[..colon]
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+    });
+    let scheme_end = scheme.map(|colon| colon + 1).unwrap_or(0);
+    let rest = &action[scheme_end..];
+    if !rest.starts_with("//") {
+        return scheme.is_some(); //relative to the document, or without authority like mailto:
+    }
+    let authority = &rest[2..rest[2..]
+        .find(|c| c == '/' || c == '?' || c == '#')
+        .map(|end| end + 2)
+        .unwrap_or_else(|| rest.len())];
+    if host.map(|host| host.eq_ignore_ascii_case(authority)).unwrap_or(false) {
+        return false;
+    }
+    let origin = format!("{}//{}", &action[..scheme_end], authority);
+    !form_origins.iter().any(|allowed| {
+        let allowed = allowed.trim_right_matches('/');
+        match scheme {
+            Some(_) => allowed.eq_ignore_ascii_case(&origin),
+            None => allowed
+                .splitn(2, "://")
+                .nth(1)
+                .map(|allowed| allowed.eq_ignore_ascii_case(authority))
+                .unwrap_or(false), //scheme relative, same scheme as the document
+        }
+    })
+}
+
+pub fn hidden_field(token: &CsrfToken) -> Vec<u8> {
+    let tag_begin = b"<input type=\"hidden\" name=\"";
+    let tag_name = token.field_name().as_bytes();
+    let tag_value = b"\" value=\"";
+    let tag_middle = token.value();
+    let tag_end = b"\">";
+    let mut tag = Vec::new();
+    tag.extend_from_slice(tag_begin);
+    tag.extend_from_slice(tag_name);
+    tag.extend_from_slice(tag_value);
+    tag.extend_from_slice(tag_middle);
+    tag.extend_from_slice(tag_end);
+    tag
+}
+
+pub fn field_markup(template: &str, token: &CsrfToken) -> Vec<u8> {
+    //fill the {name} and {token} placeholders of a template
+    template
+        .replace("{name}", token.field_name())
+        .replace("{token}", from_utf8(token.value()).unwrap_or(""))
+        .into_bytes()
+}
+
+pub fn is_ascii_compatible(charset: &str) -> bool {
+    //if inserting ascii markup in a document with this charset can't corrupt it
+    let charset = charset.trim().trim_matches(|c| c == '"' || c == '\'').to_ascii_lowercase();
+    ASCII_COMPATIBLE
+        .iter()
+        .any(|compatible| charset.starts_with(compatible))
+}
+
+fn meta_charset(attributes: &str) -> Option<String> {
+    //charset declared by a <meta charset> or <meta http-equiv="Content-Type"> tag
+    if let Some(charset) = attribute(attributes, "charset") {
+        return Some(charset);
+    }
+    let content = attribute(attributes, "content")?;
+    let lowercase = content.to_ascii_lowercase();
+    let start = lowercase.find("charset=")? + "charset=".len();
+    let charset = content[start..].split(';').next().unwrap_or("");
+    Some(charset.to_owned())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn name_end(buf: &[u8], from: usize) -> usize {
+    //end of a tag name, which go on until a space, '/' or '>'
+    buf[from..]
+        .iter()
+        .position(|&c| c == b'/' || c == b'>' || (c as char).is_ascii_whitespace())
+        .map(|end| from + end)
+        .unwrap_or_else(|| buf.len())
+}
+
+fn raw_text_end(buf: &[u8], name: &str) -> Option<usize> {
+    //position of the end tag of a raw text element, like "</script>"
+    let len = name.len();
+    (0..buf.len()).find(|&i| {
+        buf[i..].starts_with(b"</")
+            && buf.len() > i + 2 + len
+            && buf[i + 2..i + 2 + len].eq_ignore_ascii_case(name.as_bytes())
+            && match buf[i + 2 + len] {
+                b'/' | b'>' => true,
+                c => (c as char).is_ascii_whitespace(),
+            }
+    })
+}
+
+fn tag_end(buf: &[u8], from: usize) -> Option<usize> {
+    //position of the '>' closing a tag, skipping those in quoted attribute values
+    let mut quote = None;
+    let mut after_equal = false; //quotes only begin a value just after '='
+    for (i, &c) in buf.iter().enumerate().skip(from) {
+        match quote {
+            Some(q) => if c == q {
+                quote = None;
+            },
+            None => match c {
+                b'>' => return Some(i),
+                b'"' | b'\'' if after_equal => quote = Some(c),
+                b'=' => {
+                    after_equal = true;
+                    continue;
+                }
+                c if (c as char).is_ascii_whitespace() => continue,
+                _ => (),
+            },
+        }
+        after_equal = false;
+    }
+    None
+}
+
+fn markup(buf: &[u8], eof: bool) -> Markup {
+    //what begins at this '<', as a HTML5 tokenizer would see it
+    let incomplete = if eof || buf.len() >= MAX_TAG {
+        Markup::Text //give up, what follows is parsed as text
+    } else {
+        Markup::Incomplete
+    };
+    let until_gt = |from: usize| match buf[from..].iter().position(|&c| c == b'>') {
+        Some(end) => Markup::Other(from + end + 1),
+        None => if eof || buf.len() >= MAX_TAG {
+            Markup::Text
+        } else {
+            Markup::Incomplete
+        },
+    };
+    match buf.get(1) {
+        None => incomplete,
+        Some(&b'!') => if buf.starts_with(b"<!--") {
+            Markup::Comment
+        } else if b"<!--".starts_with(buf) {
+            incomplete
+        } else {
+            until_gt(2) //doctype, or a bogus comment
+        },
+        Some(&b'?') => until_gt(2), //bogus comment
+        Some(&b'/') => match buf.get(2) {
+            None => incomplete,
+            Some(&b'>') => Markup::Other(3),
+            Some(c) if c.is_ascii_alphabetic() => {
+                let name = name_end(buf, 2);
+                match tag_end(buf, name) {
+                    Some(end) => Markup::EndTag(end + 1, name),
+                    None => incomplete,
+                }
+            }
+            Some(_) => until_gt(2),
+        },
+        Some(c) if c.is_ascii_alphabetic() => {
+            let name = name_end(buf, 1);
+            match tag_end(buf, name) {
+                Some(end) => Markup::StartTag(end + 1, name),
+                None => incomplete,
+            }
+        }
+        Some(_) => Markup::Text,
+    }
 }
 
 impl<'a> CsrfProxy<'a> {
     pub fn from(underlying: Box<Read + 'a>, token: &CsrfToken) -> Self {
-        let tag_begin = b"<input type=\"hidden\" name=\"csrf-token\" value=\"";
-        let tag_middle = token.value();
-        let tag_end = b"\">";
-        let mut token = Vec::new();
-        token.extend_from_slice(tag_begin);
-        token.extend_from_slice(tag_middle);
-        token.extend_from_slice(tag_end);
         CsrfProxy {
             underlying,
-            token,
-            buf: Vec::new(),
-            state: ParseState::Reset,
-            insert_tag: None,
+            input: Vec::new(),
+            output: Vec::new(),
+            output_pos: 0,
+            eof: false,
+            state: Data,
+            in_form: false,
+            template_depth: 0,
+            token: hidden_field(token),
+            amp_mode: AmpMode::Inject,
+            csrf_token: token.clone(),
+            unique_per_form: false,
+            head_content: None,
+            body_attributes: None,
+            document: None,
+            host: None,
+            form_origins: Arc::new(Vec::new()),
+            opt_out_attribute: OPT_OUT_ATTRIBUTE.to_owned(),
+            field_template: None,
+            pool: None,
         }
     }
-}
 
-impl<'a> Read for CsrfProxy<'a> {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
-        if let Some(pos) = self.insert_tag {
-            //if we should insert a tag
-            let size = buf.len();
-            let copy_size = cmp::min(size, self.token.len() - pos); //get max copy length
-            buf[0..copy_size].copy_from_slice(&self.token[pos..copy_size + pos]); //copy that mutch
-            if copy_size == self.token.len() - pos {
-                //if we copied the full tag, say we don't need to set it again
-                self.insert_tag = None;
-            } else {
-                //if we didn't copy the full tag, save where we were
-                self.insert_tag = Some(pos + copy_size);
-            }
-            return Ok(copy_size); //return the lenght of the copied data
+    pub fn with_buffer_pool(mut self, pool: Arc<BufferPool>) -> Self {
+        self.input = pool.take();
+        self.output = pool.take();
+        self.pool = Some(pool);
+        self
+    }
+
+    pub fn with_head_content(mut self, head_content: Option<Vec<u8>>) -> Self {
+        self.head_content = head_content;
+        self
+    }
+
+    pub fn with_body_attributes(mut self, body_attributes: Option<Vec<u8>>) -> Self {
+        self.body_attributes = body_attributes;
+        self
+    }
+
+    pub fn with_field_template(mut self, field_template: Option<String>) -> Self {
+        self.field_template = field_template;
+        self.token = self.field(&self.csrf_token);
+        self
+    }
+
+    fn field(&self, token: &CsrfToken) -> Vec<u8> {
+        //markup inserted in forms for this token
+        match self.field_template {
+            Some(ref template) => field_markup(template, token),
+            None => hidden_field(token),
+        }
+    }
+
+    fn insert_field(&mut self) {
+        //insert the token in the current form
+        self.output.extend_from_slice(&self.token);
+        self.in_form = false;
+        if self.unique_per_form && self.document.is_none() {
+            self.token = self.field(&self.csrf_token.regenerate()); //next form get a new token
         }
+    }
+
+    pub fn with_unique_token_per_form(mut self, unique_per_form: bool) -> Self {
+        self.unique_per_form = unique_per_form;
+        self
+    }
+
+    pub fn with_action_scope(mut self, document: Option<String>) -> Self {
+        //scope the token of each form to its action, resolved against the path of the document
+        self.document = document;
+        self
+    }
+
+    pub fn with_form_origins(
+        mut self,
+        host: Option<String>,
+        form_origins: Arc<Vec<String>>,
+    ) -> Self {
+        self.host = host;
+        self.form_origins = form_origins;
+        self
+    }
+
+    pub fn with_opt_out_attribute(mut self, opt_out_attribute: String) -> Self {
+        self.opt_out_attribute = opt_out_attribute;
+        self
+    }
+
+    fn form_tag(&mut self, attributes: &str) -> bool {
+        //return if a form needs a token, and mint it for the action of this form
+        let opt_out = attribute(attributes, &self.opt_out_attribute).unwrap_or_default();
+        if opt_out.trim().eq_ignore_ascii_case("off") {
+            return false; //the author of the template asked for this form to be left untouched
+        }
+        let method = attribute(attributes, "method").unwrap_or_default();
+        if method.trim().eq_ignore_ascii_case("get") {
+            return false; //the token would end up in the query string, and so in logs
+        }
+        let action = attribute(attributes, "action").unwrap_or_default();
+        if is_external(&action, self.host.as_ref().map(|host| host.as_str()), &self.form_origins) {
+            return false; //the token would be given to a third party
+        }
+        if let Some(ref document) = self.document {
+            let action = resolve_action(document, &action);
+            self.token = self.field(&self.csrf_token.for_action(&action));
+        }
+        true
+    }
+
+    pub fn with_amp_mode(mut self, amp_mode: AmpMode) -> Self {
+        self.amp_mode = amp_mode;
+        self
+    }
 
-        let len = if let Some((vec, pos)) = self.buf.pop() {
-            //if there is a buffer to add here
-            let size = buf.len();
-            if vec.len() - pos <= size {
-                //if the part left of the buffer is smaller than buf
-                buf[0..vec.len() - pos].copy_from_slice(&vec[pos..]);
-                vec.len() - pos
+    fn start_tag(&mut self, tag: &[u8], name_end: usize) {
+        //copy a start tag to the output, with what must be inserted around it
+        let name = &tag[1..name_end];
+        let attributes = String::from_utf8_lossy(&tag[name_end..]);
+        let raw_text = RAW_TEXT.iter().find(|raw| name.eq_ignore_ascii_case(raw.as_bytes()));
+        if let Some(raw_text) = raw_text {
+            self.state = RawText(*raw_text); //what follows is text until its end tag
+        }
+        if name.eq_ignore_ascii_case(b"template") {
+            self.template_depth += 1;
+        }
+        if self.template_depth > 0 {
+            self.output.extend_from_slice(tag); //content of templates is cloned by scripts
+        } else if self.in_form && name.eq_ignore_ascii_case(b"input") {
+            if attribute(&attributes, "name").map(|name| name == "_method").unwrap_or(false) {
+                self.output.extend_from_slice(tag); //Rocket wants "_method" to be the first field
+                self.insert_field();
             } else {
-                //else if the part left of the buffer is bigger than buf
-                buf.copy_from_slice(&vec[pos..pos + size]);
-                self.buf.push((vec, pos + size));
-                size
-            } //send the size of what was read as if it was a normal read on underlying struct
-        } else {
-            //if there is no buffer to add, read from underlying struct
-            let res = self.underlying.read(buf);
-            if res.is_err() {
-                return res;
+                self.insert_field();
+                self.output.extend_from_slice(tag);
             }
-            match res {
-                Ok(v) => v,
-                Err(_) => return res,
+        } else if name.eq_ignore_ascii_case(b"body") && self.body_attributes.is_some() {
+            self.output.extend_from_slice(&tag[..name_end]);
+            if let Some(body_attributes) = self.body_attributes.take() {
+                self.output.extend_from_slice(&body_attributes); //only once per document
             }
-        };
+            self.output.extend_from_slice(&tag[name_end..]);
+        } else {
+            self.output.extend_from_slice(tag);
+            if name.eq_ignore_ascii_case(b"html") {
+                let amp = attribute(&attributes, "amp").is_some()
+                    || attribute(&attributes, "⚡").is_some();
+                if amp && self.amp_mode == AmpMode::Skip {
+                    self.state = Passthrough;
+                }
+            } else if name.eq_ignore_ascii_case(b"meta") {
+                if let Some(charset) = meta_charset(&attributes) {
+                    if !is_ascii_compatible(&charset) {
+                        self.state = Passthrough; //what follows may not be ascii, don't corrupt it
+                    }
+                }
+            } else if name.eq_ignore_ascii_case(b"head") {
+                if let Some(head_content) = self.head_content.take() {
+                    self.output.extend_from_slice(&head_content); //only once per document
+                }
+            } else if name.eq_ignore_ascii_case(b"form") && !self.in_form {
+                self.in_form = self.form_tag(&attributes); //nested forms are ignored by browsers
+            }
+        }
+    }
 
-        for i in 0..len {
-            //for each byte
-            self.state = match self.state {
-                Reset => if buf[i] as char == '<' {
-                    //if we are in default state and we begin to match any tag
-                    PartialFormMatch(0)
-                } else {
-                    //if we don't match a tag
-                    Reset
-                },
-                PartialFormMatch(count) => match (buf[i] as char, count) {
-                    //progressively match "form"
-                    ('f', 0) | ('F', 0) => PartialFormMatch(1),
-                    ('o', 1) | ('O', 1) => PartialFormMatch(2),
-                    ('r', 2) | ('R', 2) => PartialFormMatch(3),
-                    ('m', 3) | ('M', 3) => SearchInput, //when we success, go to next state
-                    _ => Reset, //if this don't match, go back to defailt state
-                },
-                SearchInput => if buf[i] as char == '<' {
-                    //begin to match any tag
-                    PartialInputMatch(0, i)
-                } else {
-                    SearchInput
-                },
-                PartialInputMatch(count, pos) => match (buf[i] as char, count) {
-                    //progressively match "input"
-                    ('i', 0) | ('I', 0) => PartialInputMatch(1, pos),
-                    ('n', 1) | ('N', 1) => PartialInputMatch(2, pos),
-                    ('p', 2) | ('P', 2) => PartialInputMatch(3, pos),
-                    ('u', 3) | ('U', 3) => PartialInputMatch(4, pos),
-                    ('t', 4) | ('T', 4) => SearchMethod(pos), //when we success, go to next state
-                    ('/', 0) => PartialFormEndMatch(1, pos), //if first char is '/', it may mean we are matching end of form, go to that state
-                    _ => SearchInput, //not a input tag, go back to SearchInput
-                },
-                PartialFormEndMatch(count, pos) => match (buf[i] as char, count) {
-                    //progressively match "/form"
-                    ('/', 0) => PartialFormEndMatch(1, pos), //unreachable, here only for comprehension
-                    ('f', 1) | ('F', 1) => PartialFormEndMatch(2, pos),
-                    ('o', 2) | ('O', 2) => PartialFormEndMatch(3, pos),
-                    ('r', 3) | ('R', 3) => PartialFormEndMatch(4, pos),
-                    ('m', 4) | ('M', 4) => {
-                        //if we match end of form, save "</form>" and anything after to a buffer, and insert our token
-                        self.insert_tag = Some(0);
-                        self.buf.push((buf[pos..len].to_vec(), 0));
-                        self.state = Reset;
-                        return Ok(pos);
+    fn parse(&mut self) {
+        //rewrite what was read, but keep an incomplete tag at its end until the rest is read
+        let mut input = mem::replace(&mut self.input, Vec::new());
+        let mut pos = 0; //begining of what is not parsed yet
+        let mut copied = 0; //begining of what is parsed but not copied to output yet
+        while pos < input.len() {
+            match self.state {
+                Passthrough => pos = input.len(),
+                RawText(name) => match raw_text_end(&input[pos..], name) {
+                    Some(end) => {
+                        pos += end; //the end tag is parsed as any tag
+                        self.state = Data;
                     }
-                    _ => SearchInput,
-                },
-                SearchMethod(pos) => match buf[i] as char {
-                    //try to match params
-                    ' ' => PartialNameMatch(0, pos), //space, next char is a new param
-                    '>' => {
-                        //end of this <input> tag, it's not Rocket special one, so insert before, saving what comes next to buffer
-                        self.insert_tag = Some(0);
-                        self.buf.push((buf[pos..len].to_vec(), 0));
-                        self.state = Reset;
-                        return Ok(pos);
+                    None => {
+                        if self.eof {
+                            pos = input.len();
+                        } else {
+                            let end_tag = name.len() + 2; //what is kept may begin the end tag
+                            pos = cmp::max(pos, input.len().saturating_sub(end_tag));
+                        }
+                        break;
                     }
-                    _ => SearchMethod(pos),
                 },
-                PartialNameMatch(count, pos) => match (buf[i] as char, count) {
-                    //progressively match "name='_method'", which must be first to work
-                    ('n', 0) | ('N', 0) => PartialNameMatch(1, pos),
-                    ('a', 1) | ('A', 1) => PartialNameMatch(2, pos),
-                    ('m', 2) | ('M', 2) => PartialNameMatch(3, pos),
-                    ('e', 3) | ('E', 3) => PartialNameMatch(4, pos),
-                    ('=', 4) => PartialNameMatch(5, pos),
-                    ('"', 5) | ('\'', 5) => PartialNameMatch(6, pos),
-                    ('_', 6) | ('_', 5) => PartialNameMatch(7, pos),
-                    ('m', 7) | ('M', 7) => PartialNameMatch(8, pos),
-                    ('e', 8) | ('E', 8) => PartialNameMatch(9, pos),
-                    ('t', 9) | ('T', 9) => PartialNameMatch(10, pos),
-                    ('h', 10) | ('H', 10) => PartialNameMatch(11, pos),
-                    ('o', 11) | ('O', 11) => PartialNameMatch(12, pos),
-                    ('d', 12) | ('D', 12) => PartialNameMatch(13, pos),
-                    ('"', 13) | ('\'', 13) | (' ', 13) => CloseInputTag, //we matched, wait for end of this <input> and insert just after
-                    _ => SearchMethod(pos),     //we did not match, search next param
-                },
-                CloseInputTag => if buf[i] as char == '>' {
-                    //search for '>' at the end of an "<input name='_method'>", and insert token after
-                    self.insert_tag = Some(0);
-                    self.buf.push((buf[i + 1..len].to_vec(), 0));
-                    self.state = Reset;
-                    return Ok(i + 1);
-                } else {
-                    CloseInputTag
+                Comment => match find(&input[pos..], b"-->") {
+                    Some(end) => {
+                        pos += end + 3;
+                        self.state = Data;
+                    }
+                    None => {
+                        if self.eof {
+                            pos = input.len();
+                        } else {
+                            pos = cmp::max(pos, input.len().saturating_sub(2)); //may begin "-->"
+                        }
+                        break;
+                    }
                 },
+                Data => {
+                    pos = match input[pos..].iter().position(|&c| c == b'<') {
+                        Some(lt) => pos + lt,
+                        None => {
+                            pos = input.len();
+                            break;
+                        }
+                    };
+                    match markup(&input[pos..], self.eof) {
+                        Markup::Incomplete => break,
+                        Markup::Text => pos += 1,
+                        Markup::Comment => {
+                            pos += 2; //so "<!-->" is a complete comment, as for browsers
+                            self.state = Comment;
+                        }
+                        Markup::Other(len) => pos += len,
+                        Markup::StartTag(len, name_end) => {
+                            self.output.extend_from_slice(&input[copied..pos]);
+                            self.start_tag(&input[pos..pos + len], name_end);
+                            pos += len;
+                            copied = pos;
+                        }
+                        Markup::EndTag(len, name_end) => {
+                            let name = &input[pos + 2..pos + name_end];
+                            if name.eq_ignore_ascii_case(b"template") {
+                                self.template_depth = self.template_depth.saturating_sub(1);
+                            }
+                            let in_template = self.template_depth > 0;
+                            if self.in_form && !in_template && name.eq_ignore_ascii_case(b"form") {
+                                //no field in this form, insert our token at its end
+                                self.output.extend_from_slice(&input[copied..pos]);
+                                self.insert_field();
+                                copied = pos;
+                            }
+                            pos += len;
+                        }
+                    }
+                }
+            }
+        }
+        self.output.extend_from_slice(&input[copied..pos]);
+        input.drain(..pos);
+        self.input = input;
+    }
+}
+
+impl<'a> Drop for CsrfProxy<'a> {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.give(mem::replace(&mut self.input, Vec::new()));
+            pool.give(mem::replace(&mut self.output, Vec::new()));
+        }
+    }
+}
+
+impl<'a> Read for CsrfProxy<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let drained = self.output_pos == self.output.len() && self.input.is_empty();
+        if drained && self.state == Passthrough {
+            return self.underlying.read(buf); //nothing left to parse, no need to copy
+        }
+        while self.output_pos == self.output.len() {
+            //nothing left to return, read and parse more of the underlying body
+            if self.eof {
+                return Ok(0);
             }
+            self.output.clear();
+            self.output_pos = 0;
+            let len = self.input.len();
+            self.input.resize(len + READ_SIZE, 0);
+            match self.underlying.read(&mut self.input[len..]) {
+                Ok(read) => {
+                    self.input.truncate(len + read);
+                    self.eof = read == 0;
+                }
+                Err(e) => {
+                    self.input.truncate(len);
+                    return Err(e);
+                }
+            }
+            self.parse();
         }
-        Ok(len)
+        let size = cmp::min(buf.len(), self.output.len() - self.output_pos);
+        buf[..size].copy_from_slice(&self.output[self.output_pos..self.output_pos + size]);
+        self.output_pos += size;
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csrf_proxy::{attribute, is_external, markup, raw_text_end, resolve_action, tag_end};
+    use csrf_proxy::{is_ascii_compatible, meta_charset, Markup, MAX_TAG};
+
+    #[test]
+    fn test_attribute() {
+        let tag = " method=post data-x ACTION = '/a?b=1&amp;c=2' class=\"form\">";
+        assert_eq!(attribute(tag, "action").unwrap(), "/a?b=1&c=2");
+        assert_eq!(attribute(tag, "method").unwrap(), "post");
+        assert_eq!(attribute(tag, "data-x").unwrap(), "");
+        assert_eq!(attribute(tag, "id"), None);
+    }
+
+    #[test]
+    fn test_markup() {
+        assert_eq!(markup(b"<", false), Markup::Incomplete);
+        assert_eq!(markup(b"<", true), Markup::Text);
+        assert_eq!(markup(b"<fo", false), Markup::Incomplete);
+        assert_eq!(markup(b"<form method=post", false), Markup::Incomplete);
+        assert_eq!(markup(b"<form method=post", true), Markup::Text);
+        assert_eq!(markup(b"<FORM>", false), Markup::StartTag(6, 5));
+        assert_eq!(markup(b"<form title=\"a>b\">x", false), Markup::StartTag(18, 5));
+        assert_eq!(markup(b"<form title='a>b", false), Markup::Incomplete);
+        assert_eq!(markup(b"<br/>", false), Markup::StartTag(5, 3));
+        assert_eq!(markup(b"</form >", false), Markup::EndTag(8, 6));
+        assert_eq!(markup(b"</>", false), Markup::Other(3));
+        assert_eq!(markup(b"</ form>", false), Markup::Other(8));
+        assert_eq!(markup(b"<!-", false), Markup::Incomplete);
+        assert_eq!(markup(b"<!-- <form> -->", false), Markup::Comment);
+        assert_eq!(markup(b"<!DOCTYPE html>", false), Markup::Other(15));
+        assert_eq!(markup(b"<?xml version=\"1.0\"?>", false), Markup::Other(21));
+        assert_eq!(markup(b"< form>", false), Markup::Text);
+        assert_eq!(markup(b"<3", false), Markup::Text);
+
+        let mut long = b"<form title=\"".to_vec();
+        long.resize(MAX_TAG, b'a');
+        assert_eq!(markup(&long, false), Markup::Text); //too long, given up
+    }
+
+    #[test]
+    fn test_tag_end() {
+        assert_eq!(tag_end(b"<a b=x'y>", 2), Some(8)); //quotes in unquoted values
+        assert_eq!(tag_end(b"<a b'c>", 2), Some(6)); //and in names are not quotes
+        assert_eq!(tag_end(b"<a b = \"c>d\">", 2), Some(12));
+        assert_eq!(tag_end(b"<a b='c\">'>", 2), Some(10));
+        assert_eq!(tag_end(b"<a b=\"c>", 2), None);
+    }
+
+    #[test]
+    fn test_raw_text_end() {
+        assert_eq!(raw_text_end(b"a = '</scripts>'; b < c</SCRIPT >", "script"), Some(23));
+        assert_eq!(raw_text_end(b"x</script>", "script"), Some(1));
+        assert_eq!(raw_text_end(b"x</script/>", "script"), Some(1));
+        assert_eq!(raw_text_end(b"x</script", "script"), None); //may be "</scripts"
+        assert_eq!(raw_text_end(b"<form></form>", "title"), None);
+    }
+
+    #[test]
+    fn test_charset() {
+        assert!(is_ascii_compatible("UTF-8"));
+        assert!(is_ascii_compatible("\"iso-8859-1\""));
+        assert!(is_ascii_compatible("Shift_JIS"));
+        assert!(is_ascii_compatible("windows-1252"));
+        assert!(!is_ascii_compatible("utf-16le"));
+        assert!(!is_ascii_compatible("ISO-2022-JP"));
+        assert!(!is_ascii_compatible("utf-7"));
+        assert_eq!(meta_charset(" charset=\"utf-8\">").unwrap(), "utf-8");
+        let http_equiv = " http-equiv=\"Content-Type\" content=\"text/html; Charset=ISO-2022-JP\">";
+        assert_eq!(meta_charset(http_equiv).unwrap(), "ISO-2022-JP");
+        assert_eq!(meta_charset(" name=\"viewport\" content=\"width=device-width\">"), None);
+    }
+
+    #[test]
+    fn test_resolve_action() {
+        assert_eq!(resolve_action("/account/edit", ""), "/account/edit");
+        assert_eq!(resolve_action("/account/edit", "delete?now#top"), "/account/delete");
+        assert_eq!(resolve_action("/account/edit", "../logout"), "/logout");
+        assert_eq!(resolve_action("/account/edit", "/transfer"), "/transfer");
+        assert_eq!(resolve_action("/", "https://example.com/pay"), "/pay");
+        assert_eq!(resolve_action("/", "//example.com"), "/");
+    }
+
+    #[test]
+    fn test_is_external() {
+        let allowed = vec!["https://pay.example.com/".to_owned()];
+        let host = Some("example.com");
+        assert!(!is_external("/transfer", host, &allowed));
+        assert!(!is_external("../edit?a=b:c", host, &allowed));
+        assert!(!is_external("https://EXAMPLE.com/transfer", host, &allowed));
+        assert!(!is_external("https://pay.example.com/checkout", host, &allowed));
+        assert!(!is_external("//pay.example.com", host, &allowed));
+        assert!(is_external("http://pay.example.com/checkout", host, &allowed));
+        assert!(is_external("https://evil.com/collect", host, &allowed));
+        assert!(is_external("//evil.com", host, &allowed));
+        assert!(is_external("mailto:someone@example.com", host, &allowed));
+        assert!(is_external("https://example.com/", None, &allowed));
     }
 }
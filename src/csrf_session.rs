@@ -0,0 +1,58 @@
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest};
+use rocket::Request;
+
+use csrf_token::{remove_cookie, rotate_cookie, CsrfToken};
+use error::CsrfError;
+
+/// Request guard to change the csrf cookie when the privileges of a user change.
+///
+/// An attacker who managed to set the csrf cookie of a victim before they log in, for example
+/// from a sibling subdomain, knows tokens valid for their session. Call [`regenerate`] after a
+/// successful login so those tokens become useless, and [`invalidate`] on logout so tokens of the
+/// ended session can't be used by the next user of the same browser.
+///
+/// [`regenerate`]: #method.regenerate
+/// [`invalidate`]: #method.invalidate
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[post("/login", data = "<login>")]
+/// fn login(csrf: CsrfSession, login: Form<Login>) -> Result<Redirect, CsrfError> {
+///     //...check credentials
+///     csrf.regenerate()?;
+///     Ok(Redirect::to("/"))
+/// }
+///
+/// #[post("/logout")]
+/// fn logout(csrf: CsrfSession) -> Result<Redirect, CsrfError> {
+///     csrf.invalidate()?;
+///     Ok(Redirect::to("/"))
+/// }
+/// ```
+pub struct CsrfSession<'a, 'r: 'a> {
+    request: &'a Request<'r>,
+}
+
+impl<'a, 'r> CsrfSession<'a, 'r> {
+    /// Replace the csrf cookie with one made from a new secret, and get a token for it. Every
+    /// token issued before, including those already inserted into pages, is invalid afterward.
+    pub fn regenerate(&self) -> Result<CsrfToken, CsrfError> {
+        rotate_cookie(self.request)
+    }
+
+    /// Remove the csrf cookie, so every token issued before is invalid. If the response needs a
+    /// token, like a page with a form, a cookie with a new secret is issued for it.
+    pub fn invalidate(&self) -> Result<(), CsrfError> {
+        remove_cookie(self.request)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for CsrfSession<'a, 'r> {
+    type Error = CsrfError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, CsrfError> {
+        Outcome::Success(CsrfSession { request })
+    }
+}
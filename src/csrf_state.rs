@@ -0,0 +1,155 @@
+use rocket::http::SameSite;
+use rocket::{Request, State};
+use std::cmp;
+use std::sync::Arc;
+
+use binding::{request_binding, TokenBinding};
+use error::{CsrfConfigError, CsrfError};
+use keyring::CsrfKeyring;
+use metrics::CsrfMetrics;
+use pattern::Pattern;
+use token::{TokenEngine, COOKIE_NAME};
+
+/// When the lifetime of the csrf cookie is extended.
+///
+/// Tokens are valid as long as the cookie they were made for, so extending the cookie also
+/// extend tokens already inserted into pages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SlidingExpiration {
+    /// Never extend the cookie: tokens expire at a fixed time after the first visit. The cookie
+    /// issued afterward has a new secret, so expired tokens stay invalid.
+    Never,
+    /// Extend the cookie each time a protected request is accepted with a valid token, so the
+    /// timeout become an idle timeout between form submissions. Requests accepted by a header,
+    /// fetch metadata or origin check alone don't extend it.
+    OnVerifiedRequest,
+    /// Extend the cookie on every request with a safe method, like page views, so users reading
+    /// a page keep valid tokens, while submissions alone don't keep the cookie alive.
+    OnSafeRequest,
+    /// Extend the cookie on every request, including page views. This is the default.
+    OnEveryRequest,
+}
+
+/// Prefix of the name of the csrf cookie, which makes browsers enforce its attributes.
+///
+/// A prefixed cookie can't be set by an insecure page, nor, with `__Host-`, by a subdomain, so an
+/// attacker controlling one of them can't plant a cookie of their own to forge tokens for it. The
+/// attributes a prefix requires are set by the fairing, whatever the other cookie options.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CookiePrefix {
+    /// No prefix. This is the default.
+    None,
+    /// `__Secure-`, for which the cookie always has the `Secure` attribute.
+    Secure,
+    /// `__Host-`, for which the cookie always has the `Secure` attribute, the `/` path, and no
+    /// domain, so it is only sent to the host which set it.
+    Host,
+}
+
+#[derive(Clone)]
+pub struct CookieOptions {
+    //name and attributes of the csrf cookie
+    pub name: String,
+    pub same_site: SameSite,
+    pub secure: bool,
+    pub http_only: bool,
+    pub path: String,
+    pub domain: Option<String>,
+    pub prefix: CookiePrefix,
+}
+
+impl Default for CookieOptions {
+    fn default() -> Self {
+        CookieOptions {
+            name: COOKIE_NAME.to_owned(),
+            same_site: SameSite::Lax,
+            secure: false,
+            http_only: true,
+            path: "/".to_owned(),
+            domain: None,
+            prefix: CookiePrefix::None,
+        }
+    }
+}
+
+impl CookieOptions {
+    pub fn with_prefix(&self) -> CookieOptions {
+        //options with the prefix added to the name, and the attributes it requires
+        let mut options = self.clone();
+        let prefix = match self.prefix {
+            CookiePrefix::None => return options,
+            CookiePrefix::Secure => "__Secure-",
+            CookiePrefix::Host => {
+                options.path = "/".to_owned();
+                options.domain = None;
+                "__Host-"
+            }
+        };
+        options.secure = true;
+        if !options.name.starts_with(prefix) {
+            options.name = format!("{}{}", prefix, options.name);
+        }
+        options
+    }
+}
+
+pub struct CsrfState {
+    //configuration shared by the fairing and request guards, via Rocket's managed state
+    pub keyring: CsrfKeyring,
+    pub duration: i64,
+    pub grace_period: i64,
+    pub prefix_durations: Arc<Vec<(Pattern, i64)>>,
+    pub sliding_expiration: SlidingExpiration,
+    pub token_header: String,
+    pub field_name: String,
+    pub token_cookie: Option<String>,
+    pub json_token_field: String,
+    pub cookie_options: CookieOptions,
+    pub metrics: Option<Arc<CsrfMetrics>>,
+    pub session: Option<SessionFn>,
+    pub token_binding: TokenBinding,
+    pub max_body_scan: Option<u64>, //how much of a body data guards search the token in
+}
+
+pub type SessionFn = Arc<Fn(&Request) -> Option<Vec<u8>> + Send + Sync>;
+
+impl CsrfState {
+    pub fn engine(&self) -> Arc<TokenEngine> {
+        self.keyring.engine()
+    }
+
+    pub fn prefix_duration(&self, request: &Request) -> Option<i64> {
+        //lifetime of the tokens of a request, if its path has its own
+        let path = request.uri().path();
+        self.prefix_durations
+            .iter()
+            .find(|(prefix, _)| prefix.matches(path))
+            .map(|(_, duration)| *duration)
+    }
+
+    pub fn duration(&self, request: &Request) -> i64 {
+        self.prefix_duration(request).unwrap_or(self.duration)
+    }
+
+    pub fn max_duration(&self) -> i64 {
+        //how long any token may be valid
+        self.prefix_durations
+            .iter()
+            .map(|(_, duration)| *duration)
+            .fold(self.duration, cmp::max)
+    }
+
+    pub fn binding(&self, request: &Request) -> Option<Vec<u8>> {
+        //what tokens of this request must be bound to, if anything
+        request_binding(request, self.session.as_ref(), self.token_binding)
+    }
+
+    pub fn from<'r>(request: &Request<'r>) -> Result<&'r CsrfState, CsrfError> {
+        //get the state managed by the fairing, which is missing if the fairing is not attached
+        request
+            .guard::<State<CsrfState>>()
+            .succeeded()
+            .map(|state| state.inner())
+            .ok_or(CsrfError::Config(CsrfConfigError::NotAttached))
+    }
+}
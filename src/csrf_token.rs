@@ -1,21 +1,69 @@
-use csrf::{AesGcmCsrfProtection, CsrfProtection, CSRF_COOKIE_NAME};
-use data_encoding::{BASE64, BASE64URL_NOPAD};
-use rocket::{Request, State};
+use data_encoding::BASE64;
+use rocket::Request;
 use rocket::http::{Cookie, Status};
 use rocket::outcome::Outcome;
 use rocket::request::{self, FromRequest};
 use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::cmp;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+use csrf_proxy::hidden_field;
+use csrf_state::{CsrfState, SlidingExpiration};
+use error::CsrfError;
+use token::{CookieSecret, TokenEngine};
+use utils::now;
+
+thread_local! {
+    //last token given to the request being handled by this thread, as Rocket has no request-local
+    //cache: guards and the fairing reuse it instead of generating another one, and template
+    //helpers which can't access the request read it. It is reset by the fairing when a request
+    //begins
+    static CURRENT_TOKEN: RefCell<Option<CsrfToken>> = RefCell::new(None);
+}
+
+pub fn set_current_token(token: Option<CsrfToken>) {
+    CURRENT_TOKEN.with(|current| *current.borrow_mut() = token);
+}
+
+pub fn current_token() -> Option<CsrfToken> {
+    CURRENT_TOKEN.with(|current| current.borrow().clone())
+}
+
+thread_local! {
+    //whether the request being handled by this thread replaced or removed the csrf cookie on
+    //purpose, which must then reach the client whatever the response. It is reset by the fairing
+    //when a request begins
+    static COOKIE_CHANGED: Cell<bool> = Cell::new(false);
+}
+
+pub fn set_cookie_changed(changed: bool) {
+    COOKIE_CHANGED.with(|current| current.set(changed));
+}
+
+pub fn cookie_changed() -> bool {
+    COOKIE_CHANGED.with(|current| current.get())
+}
 
 /// Csrf token to insert into pages.
 ///
 /// The `CsrfToken` type allow you to add tokens into your pages anywhere you want, and is mainly
 /// usefull if you disabled auto-insert when building the fairing registered in Rocket.
 /// This impltement Serde's Serialize so you may insert it directly into your templats as if it was
-/// a String. It also implement FromRequest so you can get it as a request guard. This is also the
+/// a String, or use [`context_value`](#method.context_value) to also get the field name and markup. It also implement FromRequest so you can get it as a request guard. This is also the
 /// only way to get this struct.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct CsrfToken {
     value: String,
+    raw: Zeroizing<Vec<u8>>, //secret of the cookie, wiped once every copy of the token is dropped
+    engine: Arc<TokenEngine>,
+    field_name: String,
+    binding: Option<Vec<u8>>,
+    expires: i64,
 }
 
 impl CsrfToken {
@@ -23,6 +71,101 @@ impl CsrfToken {
     pub fn value(&self) -> &[u8] {
         self.value.as_bytes()
     }
+
+    /// Obtain the value of the token as bytes, like [`value`](#method.value).
+    pub fn as_bytes(&self) -> &[u8] {
+        self.value.as_bytes()
+    }
+
+    /// Get the token as a value for a template context, when tokens are not inserted
+    /// automatically. It is an object with the token as `value`, the name of the form field as
+    /// `field_name`, and the whole hidden input as `field`:
+    ///
+    /// ```html
+    /// <form method="post">{{ csrf.field | safe }} ...</form>
+    /// <meta name="csrf-token" content="{{ csrf.value }}">
+    /// ```
+    pub fn context_value(&self) -> Value {
+        let mut context = Map::new();
+        context.insert("value".to_owned(), Value::String(self.value.clone()));
+        context.insert("field_name".to_owned(), Value::String(self.field_name.clone()));
+        context.insert(
+            "field".to_owned(),
+            Value::String(String::from_utf8_lossy(&hidden_field(self)).into_owned()),
+        );
+        Value::Object(context)
+    }
+
+    /// Get when this token expires, which is when its cookie times out, or sooner for a token
+    /// made with [`with_ttl`](#method.with_ttl). A grace period is not included. The cookie may be
+    /// extended afterward, depending on [SlidingExpiration](enum.SlidingExpiration.html).
+    pub fn expires_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(cmp::max(self.expires, 0) as u64)
+    }
+
+    /// Get how long this token is still valid, zero if it already expired.
+    pub fn remaining(&self) -> Duration {
+        Duration::from_secs(cmp::max(self.expires - now(), 0) as u64)
+    }
+
+    /// Get the name of the form field in which the token must be submitted.
+    pub fn field_name(&self) -> &str {
+        &self.field_name
+    }
+
+    /// Get a new token for the same cookie. Both tokens stay valid, but they can't be linked to
+    /// each other, which allow to give a distinct token to each form of a page.
+    pub fn regenerate(&self) -> CsrfToken {
+        let binding = self.binding.as_ref().map(|binding| &binding[..]);
+        match self.engine.token(&self.raw, binding) {
+            Some(value) => CsrfToken {
+                value,
+                ..self.clone()
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// Get a new token only valid when submitted to `action`, the path a form is sent to, like
+    /// `/account/delete`. A token leaked from one form can then not be used on another one. Tokens
+    /// inserted automatically are scoped this way when
+    /// [`set_action_bound_tokens`](struct.CsrfFairingBuilder.html#method.set_action_bound_tokens)
+    /// is set.
+    pub fn for_action(&self, action: &str) -> CsrfToken {
+        let binding = self.binding.as_ref().map(|binding| &binding[..]);
+        match self.engine.scoped_token(&self.raw, binding, action) {
+            Some(value) => CsrfToken {
+                value,
+                ..self.clone()
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// Get a token which expire sooner than the csrf cookie, for forms which should be submitted
+    /// quickly, like a payment confirmation. The expiry is encrypted into the token, so it can't
+    /// be extended by the client. A ttl longer than the cookie timeout has no effect, as the
+    /// cookie expiry is still enforced.
+    pub fn with_ttl(&self, ttl: Duration) -> CsrfToken {
+        let remaining = cmp::max(self.expires - now(), 0) as u64;
+        let ttl = cmp::min(ttl.as_secs(), remaining) as i64; //never wraps, whatever the duration
+        match self.engine.expiry(&self.raw, ttl) {
+            Some(expiry) => CsrfToken {
+                value: format!("{}.{}", self.value, expiry),
+                expires: cmp::min(self.expires, now() + ttl),
+                ..self.clone()
+            },
+            None => self.clone(), //the full lifetime token is still protected by the cookie
+        }
+    }
+}
+
+impl fmt::Debug for CsrfToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CsrfToken")
+            .field("value", &self.value)
+            .finish()
+    }
 }
 
 impl Serialize for CsrfToken {
@@ -35,39 +178,174 @@ impl Serialize for CsrfToken {
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for CsrfToken {
-    type Error = ();
-
-    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
-        let (csrf_engine, duration) = request
-            .guard::<State<(AesGcmCsrfProtection, i64)>>()
-            .unwrap()
-            .inner();
-
-        let mut cookies = request.cookies();
-        let token_value = cookies
-            .get(CSRF_COOKIE_NAME)
-            .and_then(|cookie| BASE64.decode(cookie.value().as_bytes()).ok())
-            .and_then(|cookie| csrf_engine.parse_cookie(&cookie).ok())
-            .and_then(|cookie| {
-                let value = cookie.value();
-                if value.len() == 64 {
-                    let mut array = [0; 64];
-                    array.copy_from_slice(&value);
-                    Some(array)
-                } else {
-                    None
-                }
-            }); //when request guard is called, parse cookie to get it's encrypted secret (if there is a cookie)
-
-        match csrf_engine.generate_token_pair(token_value.as_ref(), *duration) {
-            Ok((token, cookie)) => {
-                let mut c = Cookie::new(CSRF_COOKIE_NAME, cookie.b64_string());
-                cookies.add(c); //TODO add a timeout, same_site, http_only and secure to the cookie
-                Outcome::Success(CsrfToken {
-                    value: BASE64URL_NOPAD.encode(token.value()),
-                })
-            }
-            Err(_) => Outcome::Failure((Status::InternalServerError, ())),
+    type Error = CsrfError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, CsrfError> {
+        let state = match CsrfState::from(request) {
+            Ok(state) => state,
+            Err(e) => return Outcome::Failure((Status::InternalServerError, e)),
+        };
+
+        if let Some(token) = current_token() {
+            return Outcome::Success(token);
+        } //every guard of a request shares one token, matching the cookie which was set
+
+        let cookie = parse_cookie(request, state); //when request guard is called, parse cookie to get it's encrypted secret (if there is a cookie)
+
+        let token = if state.sliding_expiration != SlidingExpiration::OnEveryRequest {
+            cookie.as_ref().and_then(|cookie| token_for(state, cookie))
+        } else {
+            None
+        }; //if cookie is still valid and must not be extended, only create a token for it
+
+        let token = match token {
+            Some(token) => token,
+            None => match issue_cookie(request, state, cookie.as_ref()) {
+                Ok(token) => token,
+                Err(e) => return Outcome::Failure((Status::InternalServerError, e)),
+            },
+        };
+        let token = match state.prefix_duration(request) {
+            Some(duration) => token.with_ttl(Duration::from_secs(cmp::max(duration, 0) as u64)),
+            None => token,
+        }; //the cookie is shared with other paths, which may renew it with their own timeout
+        expose_token(request, state, &token);
+        if let Some(ref metrics) = state.metrics {
+            metrics.token_issued();
+        }
+        set_current_token(Some(token.clone()));
+        Outcome::Success(token)
+    }
+}
+
+fn expose_token(request: &Request, state: &CsrfState, token: &CsrfToken) {
+    //give the token to scripts via a readable cookie, if configured
+    if let Some(ref name) = state.token_cookie {
+        let options = &state.cookie_options;
+        let mut c = Cookie::build(name.clone(), token.value.clone())
+            .same_site(options.same_site)
+            .secure(options.secure)
+            .http_only(false)
+            .path(options.path.clone())
+            .finish();
+        if let Some(ref domain) = options.domain {
+            c.set_domain(domain.clone());
+        }
+        request.cookies().add(c);
+    }
+}
+
+pub fn renew_cookie(request: &Request) -> Result<CsrfToken, CsrfError> {
+    //extend the lifetime of the csrf cookie of a request, keeping the same underlying value
+    let state = CsrfState::from(request)?;
+    let cookie = parse_cookie(request, state);
+    issue_cookie(request, state, cookie.as_ref())
+}
+
+pub fn rotate_cookie(request: &Request) -> Result<CsrfToken, CsrfError> {
+    //replace the csrf cookie of a request by one with a new secret, so previous tokens are invalid
+    let state = CsrfState::from(request)?;
+    let token = issue_cookie(request, state, None)?;
+    expose_token(request, state, &token);
+    set_current_token(Some(token.clone())); //tokens given before are for the previous cookie
+    set_cookie_changed(true);
+    Ok(token)
+}
+
+pub fn remove_cookie(request: &Request) -> Result<(), CsrfError> {
+    //remove the csrf cookie, and the cookie exposing tokens to scripts if any
+    let state = CsrfState::from(request)?;
+    let options = &state.cookie_options;
+    let names = Some(&options.name).into_iter().chain(state.token_cookie.as_ref());
+    for name in names {
+        let mut c = Cookie::build(name.clone(), "")
+            .path(options.path.clone())
+            .finish();
+        if let Some(ref domain) = options.domain {
+            c.set_domain(domain.clone());
+        }
+        request.cookies().remove(c);
+    }
+    set_current_token(None); //a page with a form will get a new cookie
+    set_cookie_changed(true);
+    Ok(())
+}
+
+pub fn raw_cookie(request: &Request, state: &CsrfState) -> Option<Vec<u8>> {
+    //get the still encrypted csrf cookie of a request
+    request
+        .cookies()
+        .get(&state.cookie_options.name)
+        .and_then(|cookie| BASE64.decode(cookie.value().as_bytes()).ok())
+}
+
+pub fn parse_cookie(request: &Request, state: &CsrfState) -> Option<CookieSecret> {
+    //get and parse the csrf cookie of a request
+    let cookie = raw_cookie(request, state)?;
+    let parsed = state
+        .engine()
+        .parse_cookie(&cookie)
+        .map(|cookie| {
+            cookie
+                .bind(state.binding(request))
+                .scope(request.uri().path())
+        });
+    if parsed.is_none() {
+        if let Some(ref metrics) = state.metrics {
+            metrics.parse_error();
         }
     }
+    parsed
+}
+
+fn token_for(state: &CsrfState, cookie: &CookieSecret) -> Option<CsrfToken> {
+    //create a token for an existing cookie, if this cookie is not expired nor in its grace period
+    let engine = state.engine();
+    if !engine.is_valid(cookie) || engine.in_grace_period(cookie) {
+        return None;
+    }
+    let expires = cookie.expires()?; //cookies made by older versions are renewed to know it
+    Some(CsrfToken {
+        value: state.engine().token(cookie.value(), cookie.binding())?,
+        raw: Zeroizing::new(cookie.value().to_vec()),
+        engine: state.engine(),
+        field_name: state.field_name.clone(),
+        binding: cookie.binding().map(|binding| binding.to_vec()),
+        expires,
+    })
+}
+
+fn issue_cookie(
+    request: &Request,
+    state: &CsrfState,
+    previous: Option<&CookieSecret>,
+) -> Result<CsrfToken, CsrfError> {
+    //set a new cookie valid for the configured duration, and give back a token for it
+    let binding = state.binding(request);
+    let previous = previous.filter(|cookie| state.engine().is_valid(cookie)); //no expired secret
+    let issued = state.engine().issue(
+        previous,
+        state.duration(request),
+        state.grace_period,
+        binding.as_ref().map(|binding| &binding[..]),
+    )?;
+    let options = &state.cookie_options;
+    let mut c = Cookie::build(options.name.clone(), issued.cookie)
+        .same_site(options.same_site)
+        .secure(options.secure)
+        .http_only(options.http_only)
+        .path(options.path.clone())
+        .finish();
+    if let Some(ref domain) = options.domain {
+        c.set_domain(domain.clone());
+    }
+    request.cookies().add(c); //TODO add a timeout to the cookie
+    Ok(CsrfToken {
+        value: issued.token,
+        raw: issued.secret,
+        engine: state.engine(),
+        field_name: state.field_name.clone(),
+        binding,
+        expires: issued.expires,
+    })
 }
@@ -0,0 +1,72 @@
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest};
+use rocket::Request;
+
+use csrf_state::CsrfState;
+use csrf_token::parse_cookie;
+use error::CsrfError;
+
+/// Verify a token against the csrf cookie of a request.
+///
+/// This is meant for handlers on routes the fairing doesn't verify (exceptions, report only
+/// prefixs...), receiving the token in a place the fairing can't parse, such as an XML or
+/// multipart body. The fairing must still be attached, as it holds the key used by tokens.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[post("/import", data = "<xml>")]
+/// fn import(request: &Request, xml: String) -> Result<(), CsrfError> {
+///     let document = parse(&xml);
+///     rocket_csrf::verify_token(request, document.token())?;
+///     //...
+/// }
+/// ```
+pub fn verify_token(request: &Request, token: &str) -> Result<(), CsrfError> {
+    let state = CsrfState::from(request)?;
+    let cookie = parse_cookie(request, state).ok_or(CsrfError::MissingCookie)?;
+    if state.engine().verify(token, &cookie) {
+        Ok(())
+    } else {
+        Err(CsrfError::InvalidToken)
+    }
+}
+
+/// Request guard succeeding only if the request has a valid token in the token header.
+///
+/// The header is `X-CSRF-Token` by default, see [`set_token_header_name`]. Requests without a
+/// valid token fail with `403 Forbidden`. Use it on routes the fairing doesn't verify, to
+/// protect them anyway.
+///
+/// [`set_token_header_name`]: struct.CsrfFairingBuilder.html#method.set_token_header_name
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[post("/api/upload", data = "<upload>")]
+/// fn upload(_verified: CsrfVerified, upload: Data) -> String {
+///     //...
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct CsrfVerified(());
+
+impl<'a, 'r> FromRequest<'a, 'r> for CsrfVerified {
+    type Error = CsrfError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, CsrfError> {
+        let state = match CsrfState::from(request) {
+            Ok(state) => state,
+            Err(e) => return Outcome::Failure((Status::InternalServerError, e)),
+        };
+        let result = match request.headers().get_one(&state.token_header) {
+            Some(token) => verify_token(request, token),
+            None => Err(CsrfError::InvalidToken),
+        };
+        match result {
+            Ok(()) => Outcome::Success(CsrfVerified(())),
+            Err(e) => Outcome::Failure((Status::Forbidden, e)),
+        }
+    }
+}
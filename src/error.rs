@@ -0,0 +1,191 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why a configuration is invalid.
+///
+/// Returned by [`finalize`](struct.CsrfFairingBuilder.html#method.finalize), so a malformed
+/// configuration is reported when the application starts rather than on the first request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CsrfConfigError {
+    /// The default target has dynamic parts other than `<uri>` and `<locale>`
+    InvalidDefaultTarget(String),
+    /// The target of an exception use dynamic parts its source doesn't have
+    IncompatibleExceptionParams {
+        /// Source of the exception
+        source: String,
+        /// Target of the exception
+        target: String,
+    },
+    /// The secret key from Rocket's configuration, `ROCKET_SECRET_KEY` or a secret file is not
+    /// valid base64
+    InvalidSecretEncoding,
+    /// The secret key from Rocket's configuration or a secret file doesn't decode to 32 bytes, but
+    /// to this many. The `secret_key` of Rocket may have any length, but not be empty
+    BadSecretLength(usize),
+    /// A glob or regex pattern can't be compiled
+    InvalidPattern {
+        /// The pattern as configured
+        pattern: String,
+        /// Why it is invalid
+        reason: String,
+    },
+    /// An entry of the `[csrf]` table of Rocket's configuration is invalid
+    InvalidConfigEntry {
+        /// Full name of the entry, like `csrf.timeout`
+        key: String,
+        /// Why it is invalid
+        reason: String,
+    },
+    /// No persistent secret key is set, while the environment requires one
+    MissingSecret,
+    /// The csrf cookie doesn't have the `Secure` attribute, while the environment requires it
+    InsecureCookie,
+    /// The fairing is not attached to Rocket, nor its state managed
+    NotAttached,
+    /// The template of the field inserted in forms has no `{token}` placeholder
+    InvalidFieldTemplate(String),
+    /// The [SecretProvider](trait.SecretProvider.html) couldn't give the secret keys, for this
+    /// reason
+    SecretUnavailable(String),
+    /// The enforcement sample rate is not a number between 0.0 and 1.0
+    InvalidSampleRate(f32),
+    /// The name of the javascript global variable holding the token is not an identifier
+    InvalidJsGlobal(String),
+}
+
+impl fmt::Display for CsrfConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsrfConfigError::InvalidDefaultTarget(target) => write!(
+                f,
+                "default target {} may only have <uri> and <locale> dynamic parts",
+                target
+            ),
+            CsrfConfigError::IncompatibleExceptionParams { source, target } => write!(
+                f,
+                "exception target {} use dynamic parts its source {} doesn't have",
+                target, source
+            ),
+            CsrfConfigError::InvalidSecretEncoding => write!(f, "secret key is not valid base64"),
+            CsrfConfigError::BadSecretLength(len) => {
+                write!(f, "secret key must be 32 bytes long, not {}", len)
+            }
+            CsrfConfigError::InvalidPattern { pattern, reason } => {
+                write!(f, "invalid pattern {}: {}", pattern, reason)
+            }
+            CsrfConfigError::InvalidConfigEntry { key, reason } => {
+                write!(f, "invalid configuration entry {}: {}", key, reason)
+            }
+            CsrfConfigError::MissingSecret => write!(
+                f,
+                "a persistent secret key is required, set csrf_secret_key in Rocket's configuration"
+            ),
+            CsrfConfigError::InsecureCookie => {
+                write!(f, "the csrf cookie must have the Secure attribute")
+            }
+            CsrfConfigError::NotAttached => write!(f, "the csrf fairing is not attached to Rocket"),
+            CsrfConfigError::InvalidFieldTemplate(template) => {
+                write!(f, "field template {} has no {{token}} placeholder", template)
+            }
+            CsrfConfigError::SecretUnavailable(reason) => {
+                write!(f, "secret keys unavailable: {}", reason)
+            }
+            CsrfConfigError::InvalidSampleRate(rate) => {
+                write!(f, "enforcement sample rate {} is not between 0.0 and 1.0", rate)
+            }
+            CsrfConfigError::InvalidJsGlobal(name) => {
+                write!(f, "{} is not a valid javascript identifier", name)
+            }
+        }
+    }
+}
+
+impl Error for CsrfConfigError {
+    fn description(&self) -> &str {
+        "invalid csrf configuration"
+    }
+}
+
+/// Error type of this crate.
+///
+/// Returned by [CsrfFairingBuilder](struct.CsrfFairingBuilder.html) when the configuration is
+/// invalid, and by request and data guards when they fail.
+#[derive(Debug)]
+pub enum CsrfError {
+    /// The configuration is invalid, or the fairing is not attached to Rocket
+    Config(CsrfConfigError),
+    /// A cryptographic operation failed
+    Crypto(String),
+    /// The request has no valid csrf cookie
+    MissingCookie,
+    /// The token is missing, expired, or doesn't match the csrf cookie
+    InvalidToken,
+    /// Reading or writing a body failed
+    Io(io::Error),
+    /// A body could not be parsed into the requested type
+    Parse(String),
+}
+
+impl fmt::Display for CsrfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsrfError::Config(msg) => write!(f, "invalid csrf configuration: {}", msg),
+            CsrfError::Crypto(msg) => write!(f, "csrf cryptographic error: {}", msg),
+            CsrfError::MissingCookie => write!(f, "missing or invalid csrf cookie"),
+            CsrfError::InvalidToken => write!(f, "missing or invalid csrf token"),
+            CsrfError::Io(e) => write!(f, "io error: {}", e),
+            CsrfError::Parse(msg) => write!(f, "invalid body: {}", msg),
+        }
+    }
+}
+
+impl Error for CsrfError {
+    fn description(&self) -> &str {
+        match self {
+            CsrfError::Config(_) => "invalid csrf configuration",
+            CsrfError::Crypto(_) => "csrf cryptographic error",
+            CsrfError::MissingCookie => "missing or invalid csrf cookie",
+            CsrfError::InvalidToken => "missing or invalid csrf token",
+            CsrfError::Io(_) => "io error",
+            CsrfError::Parse(_) => "invalid body",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match self {
+            CsrfError::Config(e) => Some(e),
+            CsrfError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<CsrfConfigError> for CsrfError {
+    fn from(e: CsrfConfigError) -> Self {
+        CsrfError::Config(e)
+    }
+}
+
+impl From<io::Error> for CsrfError {
+    fn from(e: io::Error) -> Self {
+        CsrfError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use error::{CsrfConfigError, CsrfError};
+    use std::io;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            CsrfError::Config(CsrfConfigError::BadSecretLength(16)).to_string(),
+            "invalid csrf configuration: secret key must be 32 bytes long, not 16"
+        );
+        assert_eq!(CsrfError::InvalidToken.to_string(), "missing or invalid csrf token");
+        let e: CsrfError = io::Error::new(io::ErrorKind::Other, "broken pipe").into();
+        assert_eq!(e.to_string(), "io error: broken pipe");
+    }
+}
@@ -0,0 +1,84 @@
+use rocket::http::Method;
+use rocket::Rocket;
+
+/// A route exempted from csrf verification.
+///
+/// Generated by the `#[csrf_exempt]` attribute of the `derive` feature, and given to the fairing
+/// with [`add_exempt_routes`] and the [csrf_exempt_routes!](macro.csrf_exempt_routes.html) macro.
+/// The path is relative to the mount point, which the fairing resolves when it is attached, so
+/// the route stays exempted wherever it is mounted.
+///
+/// [`add_exempt_routes`]: struct.CsrfFairingBuilder.html#method.add_exempt_routes
+#[derive(Debug)]
+pub struct ExemptRoute {
+    /// Method of the route
+    pub method: Method,
+    /// Path of the route, as written in its attribute
+    pub path: &'static str,
+}
+
+/// Collect the routes marked with `#[csrf_exempt]`, to give them to
+/// [`add_exempt_routes`](struct.CsrfFairingBuilder.html#method.add_exempt_routes). Works like
+/// Rocket's `routes!`, with paths to the handlers.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[csrf_exempt]
+/// #[post("/webhook", data = "<payload>")]
+/// fn webhook(payload: Json<Event>) {
+///     //...
+/// }
+///
+/// fn main() {
+///     rocket::ignite()
+///         .mount("/hooks", routes![webhook]) //before attaching the fairing, which look for it
+///         .attach(CsrfFairingBuilder::new()
+///                 .add_exempt_routes(csrf_exempt_routes![webhook])
+///                 .finalize().unwrap())
+///         .launch();
+/// }
+/// ```
+#[macro_export]
+macro_rules! csrf_exempt_routes {
+    ($($($segment:ident)::+),* $(,)*) => {
+        vec![$(&$($segment)::+::EXEMPT_ROUTE),*]
+    };
+}
+
+fn join(base: &str, path: &str) -> String {
+    //path of a route once mounted, the same way Rocket does
+    let path = path.split('?').next().unwrap_or("");
+    match (base.trim_right_matches('/'), path) {
+        ("", path) => path.to_owned(),
+        (base, "/") => base.to_owned(),
+        (base, path) => format!("{}{}", base, path),
+    }
+}
+
+pub fn mounted_paths(rocket: &Rocket, exempt: &[&'static ExemptRoute]) -> Vec<(String, Method)> {
+    //full paths of the mounted routes which were marked exempted
+    rocket
+        .routes()
+        .filter(|route| {
+            exempt.iter().any(|exempt| {
+                exempt.method == route.method
+                    && join(route.base.path(), exempt.path) == route.uri.path()
+            })
+        })
+        .map(|route| (route.uri.path().to_owned(), route.method))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use exempt::join;
+
+    #[test]
+    fn test_join() {
+        assert_eq!(join("/", "/webhook"), "/webhook");
+        assert_eq!(join("/hooks", "/webhook"), "/hooks/webhook");
+        assert_eq!(join("/hooks/", "/<id>?<query>"), "/hooks/<id>");
+        assert_eq!(join("/hooks", "/"), "/hooks");
+    }
+}
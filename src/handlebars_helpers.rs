@@ -0,0 +1,49 @@
+use handlebars::{Handlebars, Helper, RenderContext, RenderError};
+use std::io::Write;
+
+use csrf_proxy::hidden_field;
+use csrf_token::{current_token, CsrfToken};
+
+/// Register the `csrf_field` and `csrf_token` helpers into a Handlebars registry, with the
+/// `handlebars` feature.
+///
+/// `{{csrf_field}}` renders the hidden input holding the token of the request being answered,
+/// and `{{csrf_token}}` the token alone, for templates placing tokens themselves instead of
+/// relying on auto-insert. Call this where your registry is built, like in `Template::custom`
+/// with a `rocket_contrib` providing it.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// rocket::ignite()
+///     .attach(Template::custom(|engines| {
+///         rocket_csrf::register_handlebars(&mut engines.handlebars)
+///     }))
+/// ```
+/// and in your templates:
+/// ```html
+/// <form method="post">{{csrf_field}} ...</form>
+/// <meta name="csrf-token" content="{{csrf_token}}">
+/// ```
+pub fn register_handlebars(handlebars: &mut Handlebars) {
+    handlebars.register_helper("csrf_field", Box::new(csrf_field));
+    handlebars.register_helper("csrf_token", Box::new(csrf_token));
+}
+
+fn token() -> Result<CsrfToken, RenderError> {
+    //the token is taken from the request handled by the current thread, as helpers don't get
+    //the request
+    current_token().ok_or_else(|| {
+        RenderError::new("csrf helper used without a csrf token, is the fairing attached?")
+    })
+}
+
+fn csrf_field(_: &Helper, _: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
+    rc.writer.write_all(&hidden_field(&token()?))?; //markup, written without escaping
+    Ok(())
+}
+
+fn csrf_token(_: &Helper, _: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
+    rc.writer.write_all(token()?.value())?; //base64url, never need escaping
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use std::fmt;
+
+use csrf_token::CsrfToken;
+
+/// Hidden input holding a csrf token, for compile-time template engines.
+///
+/// It displays as the whole `<input type="hidden">` element, with its attributes escaped, so it
+/// can be embedded in Askama templates (with the `safe` filter) or Maud ones (with `PreEscaped`)
+/// when tokens are not inserted automatically. The name and value are also available to build
+/// the element yourself.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[derive(Template)]
+/// #[template(source = "<form method=\"post\">{{ csrf|safe }}</form>", ext = "html")]
+/// struct CommentForm {
+///     csrf: HiddenField,
+/// }
+///
+/// #[get("/comment")]
+/// fn comment(token: CsrfToken) -> CommentForm {
+///     CommentForm { csrf: HiddenField::from(&token) }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct HiddenField {
+    name: String,
+    value: String,
+}
+
+impl HiddenField {
+    /// Name of the form field.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The token.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl<'a> From<&'a CsrfToken> for HiddenField {
+    fn from(token: &'a CsrfToken) -> Self {
+        HiddenField {
+            name: token.field_name().to_owned(),
+            value: String::from_utf8_lossy(token.value()).into_owned(),
+        }
+    }
+}
+
+fn escape(attribute: &str) -> String {
+    //escape a value to put between double quotes
+    attribute
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+impl fmt::Display for HiddenField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+            escape(&self.name),
+            escape(&self.value)
+        )
+    }
+}
+
+/// Render the hidden input holding a csrf token, see [HiddenField](struct.HiddenField.html).
+pub fn csrf_field_html(token: &CsrfToken) -> String {
+    HiddenField::from(token).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use hidden_field::HiddenField;
+
+    #[test]
+    fn test_display() {
+        let field = HiddenField {
+            name: "csrf-token".to_owned(),
+            value: "abcd:ef".to_owned(),
+        };
+        assert_eq!(
+            field.to_string(),
+            "<input type=\"hidden\" name=\"csrf-token\" value=\"abcd:ef\">"
+        );
+        let field = HiddenField {
+            name: "a\"b".to_owned(),
+            ..field
+        };
+        assert!(field.to_string().contains("name=\"a&quot;b\""));
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::{Arc, RwLock};
+use zeroize::Zeroize;
+
+use error::CsrfConfigError;
+use token::{CsrfBackend, TokenEngine, TokenScheme};
+
+struct Keys {
+    secrets: Vec<[u8; 32]>,
+    backend: CsrfBackend,
+    scheme: TokenScheme,
+    engine: Arc<TokenEngine>,
+}
+
+impl Keys {
+    fn new(
+        secrets: Vec<[u8; 32]>,
+        backend: CsrfBackend,
+        scheme: TokenScheme,
+    ) -> Result<Self, CsrfConfigError> {
+        if secrets.is_empty() {
+            return Err(CsrfConfigError::MissingSecret);
+        }
+        Ok(Keys {
+            engine: Arc::new(TokenEngine::new(&secrets, backend, scheme)),
+            secrets,
+            backend,
+            scheme,
+        })
+    }
+
+    fn with_secrets(&self, secrets: Vec<[u8; 32]>) -> Result<Self, CsrfConfigError> {
+        Keys::new(secrets, self.backend, self.scheme)
+    }
+}
+
+impl Drop for Keys {
+    fn drop(&mut self) {
+        self.secrets.zeroize(); //the engine wipes its own copy when the last request using it ends
+    }
+}
+
+/// Handle on the secret keys used by the fairing, to change them while the server is running.
+///
+/// The fairing manages its keyring, so handlers can get it with `State<CsrfKeyring>`. To rotate
+/// keys from elsewhere, like a scheduled task, create the keyring yourself, give a clone of it to
+/// [`set_keyring`], and keep the other one. Changes apply to the following requests, tokens made
+/// with a key stay valid as long as this key is in the keyring.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate rocket;
+/// # extern crate rocket_csrf;
+/// use rocket_csrf::{CsrfFairingBuilder, CsrfKeyring};
+///
+/// # fn new_secret() -> [u8; 32] { [0; 32] }
+/// fn main() {
+///     let keyring = CsrfKeyring::new(vec![new_secret()]).unwrap();
+///     let rotation = keyring.clone();
+///     std::thread::spawn(move || loop {
+///         std::thread::sleep(std::time::Duration::from_secs(24 * 60 * 60));
+///         rotation.rotate(new_secret(), 1); //keep the previous key, for tokens already issued
+///     });
+///     rocket::ignite()
+///         .attach(CsrfFairingBuilder::new()
+///                 .set_keyring(keyring)
+///                 .finalize().unwrap())
+///         .launch();
+/// }
+/// ```
+///
+/// [`set_keyring`]: struct.CsrfFairingBuilder.html#method.set_keyring
+#[derive(Clone)]
+pub struct CsrfKeyring {
+    keys: Arc<RwLock<Keys>>,
+}
+
+impl CsrfKeyring {
+    /// Create a keyring with these secrets, the primary one first. Fail if there is none.
+    pub fn new(secrets: Vec<[u8; 32]>) -> Result<Self, CsrfConfigError> {
+        Ok(CsrfKeyring {
+            keys: Arc::new(RwLock::new(Keys::new(
+                secrets,
+                CsrfBackend::default(),
+                TokenScheme::default(),
+            )?)),
+        })
+    }
+
+    /// Replace every secret, the primary one first. Fail, leaving the keyring as it was, if there
+    /// is none. Tokens made with a key which is not in the list are not valid anymore.
+    pub fn set_secrets(&self, secrets: Vec<[u8; 32]>) -> Result<(), CsrfConfigError> {
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        *keys = keys.with_secrets(secrets)?;
+        Ok(())
+    }
+
+    /// Make a new secret the primary one, keeping at most `keep` of the previous secrets to
+    /// verify tokens made before the rotation.
+    pub fn rotate(&self, secret: [u8; 32], keep: usize) {
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        let secrets = Some(secret)
+            .into_iter()
+            .chain(keys.secrets.iter().cloned().take(keep))
+            .collect();
+        *keys = keys
+            .with_secrets(secrets)
+            .expect("a rotated keyring has a primary secret");
+    }
+
+    /// Remove a secret, so tokens made with it are not valid anymore. Fail, leaving the keyring
+    /// as it was, if it is the only secret.
+    pub fn retire(&self, secret: &[u8; 32]) -> Result<(), CsrfConfigError> {
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        let secrets = keys
+            .secrets
+            .iter()
+            .filter(|key| *key != secret)
+            .cloned()
+            .collect();
+        *keys = keys.with_secrets(secrets)?;
+        Ok(())
+    }
+
+    pub(crate) fn configure(&self, backend: CsrfBackend, scheme: TokenScheme) {
+        //backend and scheme are chosen on the fairing, which may be built after the keyring
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        if keys.backend != backend || keys.scheme != scheme {
+            let secrets = keys.secrets.clone();
+            *keys = Keys::new(secrets, backend, scheme)
+                .expect("a keyring always has a primary secret");
+        }
+    }
+
+    pub(crate) fn engine(&self) -> Arc<TokenEngine> {
+        //the engine for the current secrets, which a request keeps using even if they change
+        self.keys
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .engine
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keyring::CsrfKeyring;
+
+    #[test]
+    fn test_rotate_and_retire() {
+        let keyring = CsrfKeyring::new(vec![[0; 32]]).unwrap();
+        let issued = keyring.engine().issue(None, 3600, 0, None).unwrap();
+        let cookie = ::data_encoding::BASE64.decode(issued.cookie.as_bytes()).unwrap();
+
+        keyring.rotate([1; 32], 1);
+        let engine = keyring.engine();
+        let cookie = engine.parse_cookie(&cookie).unwrap();
+        assert!(engine.verify(&issued.token, &cookie));
+
+        assert!(keyring.retire(&[0; 32]).is_ok());
+        assert!(!keyring.engine().verify(&issued.token, &cookie));
+        assert!(keyring.retire(&[1; 32]).is_err());
+        assert!(keyring.set_secrets(Vec::new()).is_err());
+    }
+}
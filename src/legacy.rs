@@ -0,0 +1,191 @@
+use data_encoding::{BASE64, BASE64URL, BASE64URL_NOPAD};
+use rocket::Request;
+
+use utils::{constant_time_eq, form_decode, parse_args};
+
+const DJANGO_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+const DJANGO_SECRET_LENGTH: usize = 32;
+const RAILS_TOKEN_LENGTH: usize = 32;
+
+/// Token format of another framework, which may still be accepted while migrating to Rocket.
+///
+/// Pages rendered by the previous application keep carrying tokens in their own format until users
+/// reload them. Registering the matching `LegacyFormat` in the builder make those tokens valid
+/// during a transition window, while new pages receive native tokens.
+pub enum LegacyFormat {
+    /// Tokens minted by Django's `CsrfViewMiddleware` with cookie based storage (the default).
+    /// The secret is held in the cookie, so verifying the masked token against it is enough.
+    Django {
+        /// Name of the cookie holding the secret, `csrftoken` by default
+        cookie_name: String,
+        /// Name of the form field holding the masked token, `csrfmiddlewaretoken` by default
+        field_name: String,
+        /// Name of the header holding the masked token, `X-CSRFToken` by default
+        header_name: String,
+    },
+    /// Tokens minted by Rails' `protect_from_forgery`. The real token lives in the Rails session,
+    /// which only the application knows how to decode, so it has to be provided by a closure
+    /// returning the raw (base64 decoded) `_csrf_token` of the session.
+    Rails {
+        /// Name of the form field holding the masked token, `authenticity_token` by default
+        field_name: String,
+        /// Name of the header holding the masked token, `X-CSRF-Token` by default
+        header_name: String,
+        /// Closure returning the raw token stored in the Rails session of this request
+        session_token: Box<Fn(&Request) -> Option<Vec<u8>> + Send + Sync>,
+    },
+}
+
+impl LegacyFormat {
+    /// Django format with Django's default cookie, field and header names.
+    pub fn django() -> Self {
+        LegacyFormat::Django {
+            cookie_name: "csrftoken".to_owned(),
+            field_name: "csrfmiddlewaretoken".to_owned(),
+            header_name: "X-CSRFToken".to_owned(),
+        }
+    }
+
+    /// Rails format with Rails' default field and header names.
+    pub fn rails<F>(session_token: F) -> Self
+    where
+        F: Fn(&Request) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        LegacyFormat::Rails {
+            field_name: "authenticity_token".to_owned(),
+            header_name: "X-CSRF-Token".to_owned(),
+            session_token: Box::new(session_token),
+        }
+    }
+
+    /// Verify if a request carry a valid token in this format, either in a header or in the
+    /// (url-encoded) body.
+    pub fn verify(&self, request: &Request, body: &str) -> bool {
+        match self {
+            LegacyFormat::Django {
+                cookie_name,
+                field_name,
+                header_name,
+            } => {
+                let secret = match request
+                    .cookies()
+                    .get(cookie_name)
+                    .and_then(|cookie| django_unmask(cookie.value().as_bytes()))
+                {
+                    Some(secret) => secret,
+                    None => return false,
+                }; //the cookie may be masked too on older Django versions
+                submitted_tokens(request, body, field_name, header_name)
+                    .iter()
+                    .filter_map(|token| django_unmask(token.as_bytes()))
+                    .any(|token| constant_time_eq(&token, &secret))
+            }
+            LegacyFormat::Rails {
+                field_name,
+                header_name,
+                session_token,
+            } => {
+                let secret = match session_token(request) {
+                    Some(secret) => secret,
+                    None => return false,
+                };
+                submitted_tokens(request, body, field_name, header_name)
+                    .iter()
+                    .filter_map(|token| rails_unmask(token))
+                    .any(|token| constant_time_eq(&token, &secret))
+            }
+        }
+    }
+}
+
+fn submitted_tokens(request: &Request, body: &str, field: &str, header: &str) -> Vec<String> {
+    //collect candidate tokens from the header and from the body, percent-decoded
+    let mut res: Vec<String> = request
+        .headers()
+        .get(header)
+        .map(|value| value.to_owned())
+        .collect();
+    res.extend(
+        parse_args(body)
+            .filter(|(key, _)| key == &field)
+            .filter_map(|(_, value)| form_decode(value)),
+    );
+    res
+}
+
+fn django_unmask(token: &[u8]) -> Option<Vec<u8>> {
+    //a Django token is either the secret itself, or a 32 chars salt followed by the secret
+    //shifted char by char by the salt
+    let position = |c: &u8| DJANGO_CHARS.iter().position(|d| d == c);
+    if token.len() == DJANGO_SECRET_LENGTH {
+        if token.iter().all(|c| position(c).is_some()) {
+            Some(token.to_vec())
+        } else {
+            None
+        }
+    } else if token.len() == 2 * DJANGO_SECRET_LENGTH {
+        let (salt, cipher) = token.split_at(DJANGO_SECRET_LENGTH);
+        salt.iter()
+            .zip(cipher)
+            .map(|(s, c)| {
+                let s = position(s)?;
+                let c = position(c)?;
+                Some(DJANGO_CHARS[(c + DJANGO_CHARS.len() - s) % DJANGO_CHARS.len()])
+            })
+            .collect()
+    } else {
+        None
+    }
+}
+
+fn rails_unmask(token: &str) -> Option<Vec<u8>> {
+    //a Rails token is base64 of either the raw token, or a one time pad followed by the token
+    //xored with that pad
+    let masked = BASE64
+        .decode(token.as_bytes())
+        .or_else(|_| BASE64URL.decode(token.as_bytes()))
+        .or_else(|_| BASE64URL_NOPAD.decode(token.as_bytes()))
+        .ok()?;
+    if masked.len() == RAILS_TOKEN_LENGTH {
+        Some(masked)
+    } else if masked.len() == 2 * RAILS_TOKEN_LENGTH {
+        let (pad, encrypted) = masked.split_at(RAILS_TOKEN_LENGTH);
+        Some(pad.iter().zip(encrypted).map(|(p, e)| p ^ e).collect())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data_encoding::BASE64;
+    use legacy::{django_unmask, rails_unmask};
+
+    #[test]
+    fn test_django_unmask() {
+        let secret = b"abcdefghijklmnopqrstuvwxyzABCDEF";
+        assert_eq!(django_unmask(secret).unwrap(), secret.to_vec());
+
+        let salt = b"bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+        let cipher = b"bcdefghijklmnopqrstuvwxyzABCDEFG"; //every char of secret shifted by one
+        let mut masked = salt.to_vec();
+        masked.extend_from_slice(cipher);
+        assert_eq!(django_unmask(&masked).unwrap(), secret.to_vec());
+
+        assert!(django_unmask(b"too_short").is_none());
+        assert!(django_unmask(b"abcdefghijklmnopqrstuvwxyzABCDE-").is_none());
+    }
+
+    #[test]
+    fn test_rails_unmask() {
+        let raw = [7u8; 32];
+        assert_eq!(rails_unmask(&BASE64.encode(&raw)).unwrap(), raw.to_vec());
+
+        let mut masked = vec![0xffu8; 32];
+        masked.extend(raw.iter().map(|b| b ^ 0xff));
+        assert_eq!(rails_unmask(&BASE64.encode(&masked)).unwrap(), raw.to_vec());
+
+        assert!(rails_unmask("not base64!").is_none());
+        assert!(rails_unmask(&BASE64.encode(&[0u8; 16])).is_none());
+    }
+}
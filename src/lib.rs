@@ -8,6 +8,16 @@
 //!
 //! - Automatically protect all POST, PUT, DELETE and PATCH endpoints
 //! - Ability to define exceptions
+//! - Ability to exempt routes from verification, with the `#[csrf_exempt]` attribute of the
+//!   `derive` feature
+//! - Events explaining why requests are rejected, sent to `log` or `tracing` with the feature of
+//!   the same name
+//! - Tokens given to `rocket_contrib` templates, with the `templates` feature, a `csrf_input()`
+//!   function for Tera, with the `tera` feature, and `csrf_field` and `csrf_token` helpers for
+//!   Handlebars, with the `handlebars` feature
+//! - Tokens inserted in gzip and deflate compressed responses with the `flate2` feature, and in
+//!   brotli compressed responses with the `brotli` feature, other encoded responses are sent
+//!   untouched
 //!
 //! ## Usage
 //!
@@ -41,17 +51,129 @@
 //! You should define a route for csrf violation error, and registe it in the builder, otherwise
 //! errors will simply be redirected to the route matching `/`
 //!
+#[cfg(feature = "brotli")]
+extern crate brotli;
 extern crate csrf;
+#[cfg(feature = "derive")]
+extern crate rocket_csrf_codegen;
 extern crate data_encoding;
+#[cfg(feature = "flate2")]
+extern crate flate2;
+#[cfg(feature = "handlebars")]
+extern crate handlebars;
+extern crate hmac;
 extern crate rand;
+#[cfg(feature = "regex")]
+extern crate regex;
 extern crate rocket;
+#[cfg(feature = "templates")]
+extern crate rocket_contrib;
 extern crate serde;
+extern crate serde_json;
+extern crate sha2;
+#[cfg(feature = "tera")]
+extern crate tera;
+#[cfg(feature = "log")]
+extern crate log;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 
+#[macro_use]
+mod logging;
+
+mod binding;
+mod buffer_pool;
+mod content_encoding;
+mod cors;
+mod csp;
+mod csrf_body;
 mod csrf_proxy;
 mod csrf_fairing;
+mod csrf_field;
+mod csrf_form;
+mod csrf_json;
+mod csrf_session;
+mod csrf_state;
 mod csrf_token;
+mod csrf_verified;
+mod error;
+mod exempt;
+#[cfg(feature = "handlebars")]
+mod handlebars_helpers;
+mod hidden_field;
+mod keyring;
+mod legacy;
+mod metrics;
+mod no_auto_insert;
+mod nonce;
+mod origin;
 mod path;
+mod pattern;
+mod policy;
+mod refresh;
+mod report;
+mod secret;
+mod settings;
+mod stripped_form;
+#[cfg(feature = "templates")]
+mod templates;
+#[cfg(feature = "tera")]
+mod tera_function;
+mod token;
 mod utils;
+mod violation;
+mod zeroize;
 
+pub use self::binding::TokenBinding;
+pub use self::cors::CorsAware;
+pub use self::csp::{JsGlobalMode, WithCspNonce};
+pub use self::csrf_body::CsrfBody;
 pub use self::csrf_fairing::{CsrfFairingBuilder, CsrfFairing};
+pub use self::csrf_field::CsrfTokenField;
+pub use self::csrf_form::CsrfForm;
+pub use self::csrf_json::CsrfJson;
+pub use self::csrf_session::CsrfSession;
+pub use self::csrf_proxy::AmpMode;
+pub use self::csrf_state::{CookiePrefix, SlidingExpiration};
 pub use self::csrf_token::CsrfToken;
+pub use self::csrf_verified::{verify_token, CsrfVerified};
+pub use self::error::{CsrfConfigError, CsrfError};
+pub use self::exempt::ExemptRoute;
+#[cfg(feature = "handlebars")]
+pub use self::handlebars_helpers::register_handlebars;
+pub use self::hidden_field::{csrf_field_html, HiddenField};
+pub use self::keyring::CsrfKeyring;
+pub use self::legacy::LegacyFormat;
+pub use self::metrics::CsrfMetrics;
+pub use self::no_auto_insert::NoAutoInsert;
+pub use self::nonce::{MemoryNonceStore, NonceStore};
+pub use self::origin::{FetchMetadataPolicy, OriginCheck};
+pub use self::policy::{CsrfPolicy, PolicyContext, PolicyOutcome};
+pub use self::report::ViolationReport;
+pub use self::secret::SecretProvider;
+pub use self::token::{CsrfBackend, TokenScheme};
+pub use self::violation::{CsrfViolation, Enforcement, ViolationAction, ViolationReason};
+#[cfg(feature = "derive")]
+pub use rocket_csrf_codegen::{csrf_exempt, CsrfProtectedForm};
+pub use self::refresh::{refresh_route, refresh_routes};
+pub use self::stripped_form::StrippedForm;
+#[cfg(feature = "templates")]
+pub use self::templates::CsrfTemplate;
+#[cfg(feature = "tera")]
+pub use self::tera_function::register_tera;
+
+#[doc(hidden)]
+pub mod __private {
+    //items used by the code generated by rocket_csrf_codegen and by benchmarks, not part of the
+    //public api
+    use csrf_proxy;
+    use csrf_token::CsrfToken;
+
+    pub use csrf_field::current_field as field_name;
+    pub use csrf_form::read_verified_form;
+    pub use utils::parse_args;
+
+    pub fn hidden_field(token: &CsrfToken) -> String {
+        String::from_utf8_lossy(&csrf_proxy::hidden_field(token)).into_owned()
+    }
+}
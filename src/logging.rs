@@ -0,0 +1,36 @@
+//events of the crate, sent to the `log` or `tracing` crate when the feature of the same name is
+//enabled. Without any of them, warnings are printed on stderr and debug events are dropped.
+
+macro_rules! csrf_warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        {
+            ::log::warn!(target: "rocket_csrf", $($arg)*);
+        }
+        #[cfg(feature = "tracing")]
+        {
+            ::tracing::warn!(target: "rocket_csrf", $($arg)*);
+        }
+        #[cfg(not(any(feature = "log", feature = "tracing")))]
+        {
+            eprintln!("[rocket_csrf] {}", format_args!($($arg)*));
+        }
+    }};
+}
+
+macro_rules! csrf_debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        {
+            ::log::debug!(target: "rocket_csrf", $($arg)*);
+        }
+        #[cfg(feature = "tracing")]
+        {
+            ::tracing::debug!(target: "rocket_csrf", $($arg)*);
+        }
+        #[cfg(not(any(feature = "log", feature = "tracing")))]
+        {
+            let _ = format_args!($($arg)*); //keep arguments used, so disabling logs doesn't warn
+        }
+    }};
+}
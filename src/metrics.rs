@@ -0,0 +1,43 @@
+use violation::ViolationReason;
+
+/// Counters fed by the fairing and guards, to export csrf activity to a metrics backend.
+///
+/// Every method has an empty default implementation, so implement only the events you need,
+/// and install your implementation with [`set_metrics`]. Methods are called while handling
+/// requests, so they should only increment counters, not block.
+///
+/// [`set_metrics`]: struct.CsrfFairingBuilder.html#method.set_metrics
+///
+/// # Example
+///
+/// ```rust,ignore
+/// struct PrometheusMetrics {
+///     verifications: IntCounterVec,
+/// }
+///
+/// impl CsrfMetrics for PrometheusMetrics {
+///     fn verification_passed(&self) {
+///         self.verifications.with_label_values(&["passed"]).inc();
+///     }
+///
+///     fn verification_failed(&self, _: ViolationReason) {
+///         self.verifications.with_label_values(&["failed"]).inc();
+///     }
+/// }
+/// ```
+pub trait CsrfMetrics: Send + Sync {
+    /// A token was given to a page or a guard.
+    fn token_issued(&self) {}
+
+    /// A protected request was verified.
+    fn verification_passed(&self) {}
+
+    /// A protected request failed verification, whether it was then enforced or not.
+    fn verification_failed(&self, _reason: ViolationReason) {}
+
+    /// A response body was rewritten to insert tokens.
+    fn auto_insertion(&self) {}
+
+    /// A csrf cookie was sent, but could not be decoded or decrypted.
+    fn parse_error(&self) {}
+}
@@ -0,0 +1,32 @@
+use rocket::response::{self, Responder, Response};
+use rocket::Request;
+
+pub const NO_AUTO_INSERT_HEADER: &str = "X-Rocket-Csrf-No-Auto-Insert";
+
+/// Responder disabling token insertion for the response it wraps.
+///
+/// The `NoAutoInsert` type allow you to opt a route out of auto-insert from the route itself,
+/// which keep working whatever the path this route is mounted on, contrary to
+/// [`set_auto_insert_disable_prefix`]. It tags the wrapped response with a private header, which is
+/// removed by the fairing before the response is sent.
+///
+/// [`set_auto_insert_disable_prefix`]: struct.CsrfFairingBuilder.html#method.set_auto_insert_disable_prefix
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[get("/search")]
+/// fn search() -> NoAutoInsert<Template> {
+///     NoAutoInsert(Template::render("search", ()))
+/// }
+/// ```
+#[derive(Debug)]
+pub struct NoAutoInsert<R>(pub R);
+
+impl<'r, R: Responder<'r>> Responder<'r> for NoAutoInsert<R> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        Response::build_from(self.0.respond_to(request)?)
+            .raw_header(NO_AUTO_INSERT_HEADER, "1")
+            .ok()
+    }
+}
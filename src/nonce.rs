@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use utils::now;
+
+const PURGE_INTERVAL: usize = 1024;
+
+/// Storage of the tokens already used, for one-time tokens.
+///
+/// Given to [`set_nonce_store`], it makes every token verified by the fairing valid only once.
+/// Use [MemoryNonceStore](struct.MemoryNonceStore.html) for a single instance, and implement
+/// this trait on top of Redis or a database when several instances share the load, so a token
+/// used on one of them is refused by the others.
+///
+/// Tokens are remembered for the cookie timeout. With sliding expiration a cookie, and so its
+/// tokens, can live longer than that, so combine one-time tokens with
+/// `SlidingExpiration::Never` or tokens made with `CsrfToken::with_ttl` if replays must be
+/// refused forever.
+///
+/// [`set_nonce_store`]: struct.CsrfFairingBuilder.html#method.set_nonce_store
+pub trait NonceStore: Send + Sync {
+    /// Mark a nonce as used, and return if it was not already. It may be forgotten after
+    /// `expires`, in seconds since the Unix epoch. This must be atomic, two concurrent calls with
+    /// the same nonce can't both return true.
+    fn consume(&self, nonce: &[u8], expires: i64) -> bool;
+}
+
+/// In-memory [NonceStore](trait.NonceStore.html), for applications running as a single instance.
+///
+/// Expired nonces are purged from time to time, so memory use is bounded by the number of
+/// tokens used during a cookie timeout.
+#[derive(Default)]
+pub struct MemoryNonceStore {
+    nonces: Mutex<(HashMap<Vec<u8>, i64>, usize)>, //used nonces with their expiry, insertions since last purge
+}
+
+impl MemoryNonceStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        MemoryNonceStore::default()
+    }
+}
+
+impl NonceStore for MemoryNonceStore {
+    fn consume(&self, nonce: &[u8], expires: i64) -> bool {
+        let now = now();
+        let mut guard = self.nonces.lock().unwrap_or_else(|e| e.into_inner());
+        let (ref mut nonces, ref mut insertions) = *guard;
+        *insertions += 1;
+        if *insertions >= PURGE_INTERVAL {
+            nonces.retain(|_, expires| *expires > now);
+            *insertions = 0;
+        }
+        match nonces.get(nonce) {
+            Some(&used_until) if used_until > now => false,
+            _ => {
+                nonces.insert(nonce.to_vec(), expires);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nonce::{MemoryNonceStore, NonceStore};
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryNonceStore::new();
+        assert!(store.consume(b"token", i64::max_value()));
+        assert!(!store.consume(b"token", i64::max_value()));
+        assert!(store.consume(b"other", i64::max_value()));
+        assert!(store.consume(b"expired", 0));
+        assert!(store.consume(b"expired", 0)); //forgotten once expired
+    }
+}
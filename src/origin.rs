@@ -0,0 +1,114 @@
+use rocket::Request;
+
+/// How the `Origin` header (or the `Referer` header when there is no `Origin`) of protected
+/// requests is checked against trusted origins.
+///
+/// The origin of the application itself, according to the `Host` header, is always trusted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OriginCheck {
+    /// Don't look at these headers. This is the default.
+    Off,
+    /// Requests from an untrusted origin are violations, even with a valid token. Requests
+    /// without both headers are left to the token check.
+    Additional,
+    /// Requests from a trusted origin are accepted without token, the others must have a valid
+    /// token. Suited to stateless APIs whose clients are all browsers.
+    Standalone,
+}
+
+/// How the `Sec-Fetch-Site` header, sent by modern browsers, is used on protected requests.
+///
+/// When the header is present, the request is accepted or rejected from it alone, without
+/// looking for a token. Requests without it, from older browsers or other clients, are left to
+/// the token check.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FetchMetadataPolicy {
+    /// Ignore the header. This is the default.
+    Off,
+    /// Only accept `same-origin` requests, and `none` (user initiated navigation).
+    Strict,
+    /// Also accept `same-site` requests, coming from other subdomains of the same site.
+    LaxAllowSameSite,
+}
+
+impl FetchMetadataPolicy {
+    pub fn allows(self, fetch_site: &str) -> bool {
+        match (self, fetch_site.to_ascii_lowercase().as_str()) {
+            (FetchMetadataPolicy::Off, _) => true,
+            (_, "same-origin") | (_, "none") => true,
+            (FetchMetadataPolicy::LaxAllowSameSite, "same-site") => true,
+            _ => false,
+        }
+    }
+}
+
+pub fn request_origin(request: &Request) -> Option<String> {
+    //origin of a request, from the Origin header or else from the Referer header
+    if let Some(origin) = request.headers().get_one("Origin") {
+        return Some(origin.trim_right_matches('/').to_owned());
+    }
+    request
+        .headers()
+        .get_one("Referer")
+        .and_then(|referer| referer_origin(referer))
+}
+
+fn referer_origin(referer: &str) -> Option<String> {
+    //keep only scheme, host and port of an url
+    let scheme_end = referer.find("://")?;
+    let rest = &referer[scheme_end + 3..];
+    let host_end = rest
+        .find(|c| c == '/' || c == '?' || c == '#')
+        .unwrap_or_else(|| rest.len());
+    if host_end == 0 {
+        return None;
+    }
+    Some(format!("{}{}", &referer[..scheme_end + 3], &rest[..host_end]))
+}
+
+pub fn is_trusted(origin: &str, host: Option<&str>, trusted: &[String]) -> bool {
+    //"null" origins never match, as they don't contain a host
+    if trusted
+        .iter()
+        .any(|trusted| trusted.trim_right_matches('/').eq_ignore_ascii_case(origin))
+    {
+        return true;
+    }
+    match (origin.splitn(2, "://").nth(1), host) {
+        (Some(origin_host), Some(host)) => origin_host.eq_ignore_ascii_case(host),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use origin::{is_trusted, referer_origin, FetchMetadataPolicy};
+
+    #[test]
+    fn test_referer_origin() {
+        assert_eq!(referer_origin("https://example.com/some/page?a=b").unwrap(), "https://example.com");
+        assert_eq!(referer_origin("http://example.com:8000").unwrap(), "http://example.com:8000");
+        assert_eq!(referer_origin("https://example.com?x").unwrap(), "https://example.com");
+        assert!(referer_origin("/relative/path").is_none());
+        assert!(referer_origin("https:///path").is_none());
+    }
+
+    #[test]
+    fn test_fetch_metadata_policy() {
+        assert!(FetchMetadataPolicy::Strict.allows("same-origin"));
+        assert!(FetchMetadataPolicy::Strict.allows("none"));
+        assert!(!FetchMetadataPolicy::Strict.allows("same-site"));
+        assert!(!FetchMetadataPolicy::Strict.allows("cross-site"));
+        assert!(FetchMetadataPolicy::LaxAllowSameSite.allows("Same-Site"));
+        assert!(!FetchMetadataPolicy::LaxAllowSameSite.allows("cross-site"));
+    }
+
+    #[test]
+    fn test_is_trusted() {
+        let trusted = vec!["https://app.example.com/".to_owned()];
+        assert!(is_trusted("https://app.example.com", None, &trusted));
+        assert!(is_trusted("https://api.example.com", Some("api.example.com"), &trusted));
+        assert!(!is_trusted("https://evil.com", Some("api.example.com"), &trusted));
+        assert!(!is_trusted("null", Some("api.example.com"), &trusted));
+    }
+}
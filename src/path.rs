@@ -30,7 +30,7 @@ impl Path {
                     }
                 })
                 .collect();
-        if path[0..path.len()-1].iter().any(|a|
+        if path[0..path.len().saturating_sub(1)].iter().any(|a|
                                              if let PathPart::MultiDynamic(_) = a {true} else {false}
                                              ) {
             panic!("PathPart::MultiDynamic can only be found at end of path"); //TODO return error instead of panic
@@ -135,6 +135,19 @@ impl Path {
         Some(res)
     }
 
+    pub fn dynamic_parts(&self) -> Vec<&str> {
+        //names of the dynamic parts of the path and of its query
+        let query = self.param.iter().flat_map(|param| param.values());
+        self.path
+            .iter()
+            .chain(query)
+            .filter_map(|part| match part {
+                PathPart::Dynamic(name) | PathPart::MultiDynamic(name) => Some(name.as_str()),
+                PathPart::Static(_) => None,
+            })
+            .collect()
+    }
+
     pub fn map(&self, param: &HashMap<&str, String>) -> Option<String> {
         //Generate a path from a reference and a hashmap
         let mut res = String::new();
@@ -0,0 +1,203 @@
+#[cfg(feature = "regex")]
+use regex::Regex;
+use std::collections::HashMap;
+
+use error::CsrfConfigError;
+use path::Path;
+
+pub enum Pattern {
+    //a path matcher, parsed once when the fairing is finalized
+    Prefix(String),
+    Path(Path),
+    Glob(String),
+    #[cfg(feature = "regex")]
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn path(pattern: &str) -> Result<Self, CsrfConfigError> {
+        //pattern for a whole path: a regex, a glob, or a path with <dynamic> parts
+        Ok(match Pattern::special(pattern)? {
+            Some(pattern) => pattern,
+            None => Pattern::Path(Path::from(pattern)),
+        })
+    }
+
+    pub fn prefix(pattern: &str) -> Result<Self, CsrfConfigError> {
+        //pattern for a prefix list: a regex, a glob, or a plain prefix
+        Ok(match Pattern::special(pattern)? {
+            Some(pattern) => pattern,
+            None => Pattern::Prefix(pattern.to_owned()),
+        })
+    }
+
+    #[cfg(feature = "regex")]
+    fn special(pattern: &str) -> Result<Option<Self>, CsrfConfigError> {
+        if pattern.starts_with('^') {
+            Regex::new(pattern)
+                .map(|regex| Some(Pattern::Regex(regex)))
+                .map_err(|e| CsrfConfigError::InvalidPattern {
+                    pattern: pattern.to_owned(),
+                    reason: e.to_string(),
+                })
+        } else if pattern.contains('*') {
+            Ok(Some(Pattern::Glob(pattern.to_owned())))
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn special(pattern: &str) -> Result<Option<Self>, CsrfConfigError> {
+        if pattern.starts_with('^') {
+            Err(CsrfConfigError::InvalidPattern {
+                pattern: pattern.to_owned(),
+                reason: "regexs require the regex feature".to_owned(),
+            })
+        } else if pattern.contains('*') {
+            Ok(Some(Pattern::Glob(pattern.to_owned())))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn extract(&self, uri: &str) -> Option<HashMap<&str, String>> {
+        //try to match an uri, give back its dynamic parts (named groups for regexs) if it matched
+        match self {
+            Pattern::Prefix(prefix) => if uri.starts_with(prefix.as_str()) {
+                Some(HashMap::new())
+            } else {
+                None
+            },
+            Pattern::Path(path) => path.extract(uri),
+            Pattern::Glob(glob) => {
+                let path = uri.split('?').next().unwrap_or("");
+                if glob_matches(glob.as_bytes(), path.as_bytes()) {
+                    Some(HashMap::new())
+                } else {
+                    None
+                }
+            }
+            #[cfg(feature = "regex")]
+            Pattern::Regex(regex) => {
+                let captures = regex.captures(uri)?;
+                Some(
+                    regex
+                        .capture_names()
+                        .filter_map(|name| name)
+                        .filter_map(|name| {
+                            captures
+                                .name(name)
+                                .map(|value| (name, value.as_str().to_owned()))
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        //names of the dynamic parts extracted from matching uris
+        match self {
+            Pattern::Path(path) => path.dynamic_parts(),
+            #[cfg(feature = "regex")]
+            Pattern::Regex(regex) => regex.capture_names().filter_map(|name| name).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn matches(&self, uri: &str) -> bool {
+        self.extract(uri).is_some()
+    }
+}
+
+fn glob_matches(pattern: &[u8], path: &[u8]) -> bool {
+    //"*" match anything but a '/', "**" match anything, and a "/**" may match nothing at all.
+    //matches[i * (n + 1) + j] tells if pattern[i..] matches path[j..], filled from the ends so
+    //the time taken stays proportional to the product of both lengths, whatever the stars
+    let n = path.len();
+    let mut matches = vec![false; (pattern.len() + 1) * (n + 1)];
+    let at = |i: usize, j: usize| i * (n + 1) + j;
+    matches[at(pattern.len(), n)] = true;
+    for i in (0..pattern.len()).rev() {
+        for j in (0..=n).rev() {
+            let skipped = pattern[i..].starts_with(b"/**") && matches[at(i + 3, j)];
+            let matched = match pattern[i] {
+                b'*' if pattern.get(i + 1) == Some(&b'*') => {
+                    matches[at(i + 2, j)] || (j < n && matches[at(i, j + 1)])
+                }
+                b'*' => {
+                    matches[at(i + 1, j)] || (j < n && path[j] != b'/' && matches[at(i, j + 1)])
+                }
+                c => j < n && path[j] == c && matches[at(i + 1, j + 1)],
+            };
+            matches[at(i, j)] = skipped || matched;
+        }
+    }
+    matches[at(0, 0)]
+}
+
+#[cfg(test)]
+mod tests {
+    use pattern::Pattern;
+
+    #[test]
+    fn test_glob() {
+        let glob = Pattern::path("/api/**").unwrap();
+        assert!(glob.matches("/api"));
+        assert!(glob.matches("/api/v1/users"));
+        assert!(glob.matches("/api/v1/users?page=2"));
+        assert!(!glob.matches("/apis"));
+        assert!(!glob.matches("/other/api/v1"));
+
+        let glob = Pattern::path("/users/*/avatar").unwrap();
+        assert!(glob.matches("/users/42/avatar"));
+        assert!(!glob.matches("/users/42/43/avatar"));
+
+        let glob = Pattern::prefix("/static/*.css").unwrap();
+        assert!(glob.matches("/static/main.css"));
+        assert!(!glob.matches("/static/css/main.css"));
+
+        let glob = Pattern::path("/a/**/b/*/c").unwrap();
+        assert!(glob.matches("/a/b/x/c"));
+        assert!(glob.matches("/a/x/y/b/z/c"));
+        assert!(!glob.matches("/a/b/x/y/c"));
+    }
+
+    #[test]
+    fn test_glob_pathological() {
+        //with backtracking, each star multiplied the work by the length of the path
+        let glob = Pattern::path(&format!("/{}b", "*a".repeat(20))).unwrap();
+        let path = format!("/{}", "a".repeat(4000));
+        let start = ::std::time::Instant::now();
+        assert!(!glob.matches(&path));
+        assert!(start.elapsed() < ::std::time::Duration::from_secs(5));
+        let glob = Pattern::path(&format!("/{}", "**a".repeat(20))).unwrap();
+        assert!(glob.matches(&path));
+    }
+
+    #[test]
+    fn test_prefix_and_path() {
+        let prefix = Pattern::prefix("/admin").unwrap();
+        assert!(prefix.matches("/admin/users"));
+        assert!(!prefix.matches("/user/admin"));
+
+        let path = Pattern::path("/some/<other>/path").unwrap();
+        assert_eq!(path.extract("/some/thing/path").unwrap()["other"], "thing");
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex() {
+        let regex = Pattern::path(r"^/(?P<lang>[a-z]{2})/legacy/\d+$").unwrap();
+        assert_eq!(regex.extract("/fr/legacy/12").unwrap()["lang"], "fr");
+        assert!(!regex.matches("/fr/legacy/twelve"));
+        assert!(Pattern::path("^(").is_err());
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[test]
+    fn test_regex_without_feature() {
+        assert!(Pattern::path("^/legacy").is_err());
+    }
+}
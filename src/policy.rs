@@ -0,0 +1,312 @@
+use rocket::http::ContentType;
+use rocket::Request;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp;
+
+use csrf_json::json_tokens;
+use csrf_state::CsrfState;
+use legacy::LegacyFormat;
+use origin::{is_trusted, request_origin, FetchMetadataPolicy, OriginCheck};
+use token::CookieSecret;
+use utils::{form_decode, now, parse_args};
+use violation::ViolationReason;
+
+/// What a [CsrfPolicy](trait.CsrfPolicy.html) decided about a request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyOutcome {
+    /// The request is legitimate, stop checking it
+    Accept,
+    /// The request is a csrf violation, stop checking it
+    Reject(ViolationReason),
+    /// This policy can't tell, let the next one decide
+    Continue,
+}
+
+/// What a [CsrfPolicy](trait.CsrfPolicy.html) may look at, in addition to the request.
+pub struct PolicyContext<'a> {
+    state: &'a CsrfState,
+    cookie: Option<&'a CookieSecret>,
+    body: Cow<'a, str>,
+    body_len: usize,
+    verified: RefCell<Option<String>>, //last token verified successfully, to consume it
+}
+
+impl<'a> PolicyContext<'a> {
+    pub(crate) fn new(state: &'a CsrfState, cookie: Option<&'a CookieSecret>, peek: &'a [u8]) -> Self {
+        PolicyContext {
+            state,
+            cookie,
+            body: String::from_utf8_lossy(peek), //tokens are ascii, so they survive a body in another charset, or cut in the middle of a char
+            body_len: peek.len(),
+            verified: RefCell::new(None),
+        }
+    }
+
+    /// Whether the request has a valid csrf cookie.
+    pub fn has_cookie(&self) -> bool {
+        self.cookie.is_some()
+    }
+
+    /// Verify a token against the csrf cookie of the request.
+    pub fn verify_token(&self, token: &str) -> bool {
+        let valid = match self.cookie {
+            Some(cookie) => self.state.engine().verify(token, cookie),
+            None => false,
+        };
+        csrf_debug!("token pair verified: {}", valid);
+        if valid {
+            *self.verified.borrow_mut() = Some(token.to_owned());
+        }
+        valid
+    }
+
+    /// The begining of the body, as peeked by Rocket (at most 4Kio), lossily decoded as UTF-8.
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub(crate) fn body_len(&self) -> usize {
+        self.body_len
+    }
+
+    pub(crate) fn verified_token(&self) -> Option<String> {
+        self.verified.borrow().clone()
+    }
+
+    pub(crate) fn cookie_expired(&self) -> bool {
+        match self.cookie {
+            Some(cookie) => !self.state.engine().is_valid(cookie),
+            None => false,
+        }
+    }
+}
+
+/// A check run by the fairing on protected requests.
+///
+/// The fairing run an ordered chain of policies on every request with an unsafe method, and
+/// stop at the first one accepting or rejecting it. Requests which no policy accepted are
+/// violations. Add your own with [`add_policy`], for example to accept a content type the
+/// fairing can't parse.
+///
+/// [`add_policy`]: struct.CsrfFairingBuilder.html#method.add_policy
+///
+/// # Example
+///
+/// ```rust,ignore
+/// struct XmlTokenPolicy;
+///
+/// impl CsrfPolicy for XmlTokenPolicy {
+///     fn check(&self, request: &Request, context: &PolicyContext) -> PolicyOutcome {
+///         match find_xml_token(context.body()) {
+///             Some(token) if context.verify_token(&token) => PolicyOutcome::Accept,
+///             _ => PolicyOutcome::Continue,
+///         }
+///     }
+/// }
+/// ```
+pub trait CsrfPolicy: Send + Sync {
+    /// Decide about a request.
+    fn check(&self, request: &Request, context: &PolicyContext) -> PolicyOutcome;
+}
+
+pub struct CustomHeaderPolicy {
+    //accept requests carrying a header a cross origin page can't set without a CORS preflight
+    pub header: String,
+    pub value: Option<String>,
+    pub prefixes: Vec<String>,
+}
+
+impl CsrfPolicy for CustomHeaderPolicy {
+    fn check(&self, request: &Request, _: &PolicyContext) -> PolicyOutcome {
+        let uri = request.uri().to_string();
+        if !self.prefixes.is_empty() && !self.prefixes.iter().any(|prefix| uri.starts_with(prefix)) {
+            return PolicyOutcome::Continue;
+        } //not in a scope where the header is enough
+        if request.headers().get(&self.header).any(|found| {
+            self.value
+                .as_ref()
+                .map(|value| value == found)
+                .unwrap_or(true)
+        }) {
+            PolicyOutcome::Accept
+        } else {
+            PolicyOutcome::Continue
+        }
+    }
+}
+
+impl CsrfPolicy for FetchMetadataPolicy {
+    fn check(&self, request: &Request, _: &PolicyContext) -> PolicyOutcome {
+        if *self == FetchMetadataPolicy::Off {
+            return PolicyOutcome::Continue;
+        }
+        match request.headers().get_one("Sec-Fetch-Site") {
+            Some(fetch_site) if self.allows(fetch_site) => PolicyOutcome::Accept,
+            Some(_) => PolicyOutcome::Reject(ViolationReason::CrossSiteRequest),
+            None => PolicyOutcome::Continue, //not sent by this client, rely on other checks
+        }
+    }
+}
+
+pub struct OriginPolicy {
+    pub check: OriginCheck,
+    pub trusted: Vec<String>,
+}
+
+impl CsrfPolicy for OriginPolicy {
+    fn check(&self, request: &Request, _: &PolicyContext) -> PolicyOutcome {
+        let trusted = request_origin(request)
+            .map(|origin| is_trusted(&origin, request.headers().get_one("Host"), &self.trusted));
+        match (self.check, trusted) {
+            (OriginCheck::Standalone, Some(true)) => PolicyOutcome::Accept,
+            (OriginCheck::Additional, Some(false)) => {
+                PolicyOutcome::Reject(ViolationReason::UntrustedOrigin)
+            }
+            _ => PolicyOutcome::Continue,
+        }
+    }
+}
+
+pub struct HeaderTokenPolicy {
+    //accept requests with a valid token in a header, sent by scripts
+    pub header: String,
+}
+
+impl CsrfPolicy for HeaderTokenPolicy {
+    fn check(&self, request: &Request, context: &PolicyContext) -> PolicyOutcome {
+        if request
+            .headers()
+            .get(&self.header)
+            .any(|token| context.verify_token(token))
+        {
+            PolicyOutcome::Accept
+        } else {
+            PolicyOutcome::Continue
+        }
+    }
+}
+
+fn origin_matches_host(request: &Request) -> bool {
+    //browsers send Origin on cross origin POST, absence of the header is not suspicious by itself
+    let origin = match request.headers().get_one("Origin") {
+        Some(origin) => origin,
+        None => return true,
+    };
+    let host = match request.headers().get_one("Host") {
+        Some(host) => host,
+        None => return false,
+    };
+    origin
+        .splitn(2, "://")
+        .nth(1)
+        .map(|origin| origin.trim_right_matches('/') == host)
+        .unwrap_or(false) //"null" origins never match
+}
+
+pub struct DeferredPolicy {
+    //on these prefixs, the body token is verified by the CsrfForm data guard
+    pub prefixes: Vec<String>,
+}
+
+impl CsrfPolicy for DeferredPolicy {
+    fn check(&self, request: &Request, context: &PolicyContext) -> PolicyOutcome {
+        let uri = request.uri().to_string();
+        if !self.prefixes.iter().any(|prefix| uri.starts_with(prefix)) {
+            PolicyOutcome::Continue
+        } else if !context.has_cookie() {
+            PolicyOutcome::Reject(ViolationReason::MissingCookie)
+        } else if origin_matches_host(request) {
+            PolicyOutcome::Accept
+        } else {
+            PolicyOutcome::Reject(ViolationReason::InvalidToken)
+        }
+    }
+}
+
+pub fn content_length(request: &Request) -> Option<u64> {
+    request
+        .headers()
+        .get_one("Content-Length")
+        .and_then(|length| length.parse::<u64>().ok())
+}
+
+pub fn is_parsed(request: &Request, content_types: &[ContentType]) -> bool {
+    //whether the body of a request has a type the token is searched in
+    request
+        .content_type()
+        .map(|ct| content_types.iter().any(|parsed| parsed == ct))
+        .unwrap_or(false)
+}
+
+pub struct BodyTokenPolicy {
+    //accept requests with a valid token in the part of the body we can see
+    pub field_name: String,
+    pub json_token_field: String,
+    pub json_max_depth: usize,
+    pub max_body_size: Option<u64>,
+    pub content_types: Vec<ContentType>,
+}
+
+impl CsrfPolicy for BodyTokenPolicy {
+    fn check(&self, request: &Request, context: &PolicyContext) -> PolicyOutcome {
+        let forms_limit = if request.content_type().map(|ct| ct.is_form()).unwrap_or(false) {
+            request.limits().get("forms")
+        } else {
+            None
+        };
+        let limit = match (self.max_body_size, forms_limit) {
+            (Some(max), Some(forms)) => Some(cmp::min(max, forms)),
+            (max, forms) => max.or(forms),
+        }; //never search more than Rocket would accept as a form
+        if let (Some(length), Some(limit)) = (content_length(request), limit) {
+            if length > limit {
+                return PolicyOutcome::Reject(ViolationReason::BodyTooLarge);
+            } //body too large to look for a token in it, don't even try
+        }
+
+        if !is_parsed(request, &self.content_types) {
+            return PolicyOutcome::Continue;
+        } //don't guess the format of the body, the fairing rejects it if nothing accepts it
+
+        let is_json = request.content_type().map(|ct| ct.is_json()).unwrap_or(false);
+        let tokens: Vec<String> = if is_json {
+            json_tokens(context.body(), &self.json_token_field, self.json_max_depth)
+        } else {
+            parse_args(context.body())
+                .filter(|(key, _)| key == &self.field_name)
+                .filter_map(|(_, token)| form_decode(token))
+                .collect()
+        };
+        if tokens.iter().any(|token| context.verify_token(token)) {
+            PolicyOutcome::Accept
+        } else {
+            PolicyOutcome::Continue
+        }
+    }
+}
+
+pub struct LegacyPolicy {
+    //accept tokens minted by the application we are migrating from, until the deadline
+    pub formats: Vec<LegacyFormat>,
+    pub deadline: Option<i64>,
+}
+
+impl CsrfPolicy for LegacyPolicy {
+    fn check(&self, request: &Request, context: &PolicyContext) -> PolicyOutcome {
+        let active = match self.deadline {
+            Some(deadline) => now() < deadline,
+            None => true,
+        };
+        if active
+            && self
+                .formats
+                .iter()
+                .any(|format| format.verify(request, context.body()))
+        {
+            PolicyOutcome::Accept
+        } else {
+            PolicyOutcome::Continue
+        }
+    }
+}
@@ -0,0 +1,29 @@
+(function () {
+  var script = document.currentScript;
+  var endpoint = script.src.replace(/refresh\.js(\?.*)?$/, 'token');
+
+  function update(token) {
+    var fields = document.querySelectorAll('input[name="{field}"]');
+    for (var i = 0; i < fields.length; i++) {
+      fields[i].value = token;
+    }
+    var metas = document.querySelectorAll('meta[name="csrf-token"]');
+    for (var j = 0; j < metas.length; j++) {
+      metas[j].setAttribute('content', token);
+    }
+  }
+
+  function refresh() {
+    var xhr = new XMLHttpRequest();
+    xhr.open('GET', endpoint);
+    xhr.withCredentials = true;
+    xhr.onload = function () {
+      if (xhr.status === 200) {
+        update(JSON.parse(xhr.responseText).token);
+      }
+    };
+    xhr.send();
+  }
+
+  setInterval(refresh, {interval} * 1000);
+})();
@@ -0,0 +1,148 @@
+use rocket::handler;
+use rocket::http::{ContentType, Method, Status};
+use rocket::outcome::Outcome;
+use rocket::{Data, Request, Response, Route};
+use std::io::Cursor;
+use std::str::from_utf8;
+use std::time::UNIX_EPOCH;
+
+use csrf_state::CsrfState;
+use csrf_token::{renew_cookie, CsrfToken};
+use token::FORM_FIELD;
+
+const REFRESH_SCRIPT: &str = include_str!("refresh.js");
+
+/// Routes allowing pages to renew their tokens before they expire.
+///
+/// Mount them anywhere, then include the script in pages containing long lived forms. The script
+/// periodically fetches a new token from the `token` route mounted next to it, and updates every
+/// hidden field and `<meta name="csrf-token">` of the page with it.
+///
+/// - `GET <mount point>/token` returns `{"token": "...", "timeout": <seconds>}`, renewing the
+///   csrf cookie unless [SlidingExpiration](enum.SlidingExpiration.html) forbid it.
+/// - `GET <mount point>/refresh.js` returns the script.
+///
+/// # Example
+///
+///  ```rust,no_run
+/// # extern crate rocket;
+/// # extern crate rocket_csrf;
+/// use rocket_csrf::CsrfFairingBuilder;
+/// # use rocket::Rocket;
+///
+/// fn main() {
+///     rocket::ignite()
+///         .attach(rocket_csrf::CsrfFairingBuilder::new()
+///                 .finalize().unwrap())
+///         .mount("/csrf", rocket_csrf::refresh_routes())
+///         //add your routes, other fairings...
+///         .launch();
+/// }
+/// ```
+/// and in your pages:
+/// ```html
+/// <script src="/csrf/refresh.js"></script>
+/// ```
+pub fn refresh_routes() -> Vec<Route> {
+    vec![
+        Route::new(Method::Get, "/token", token),
+        Route::new(Method::Get, "/refresh.js", script),
+    ]
+}
+
+/// Route returning a fresh token, for single page applications renewing their token with fetch.
+///
+/// A `GET` on `path` renews the csrf cookie, whatever the
+/// [SlidingExpiration](enum.SlidingExpiration.html), and returns
+/// `{"token": "...", "expires_at": <unix time>}`. The secret of the cookie is kept, so tokens
+/// already in other tabs stay valid, and a cross site page requesting it can't invalidate them.
+/// Use [CsrfSession](struct.CsrfSession.html) to change the secret.
+///
+/// # Example
+///
+///  ```rust,no_run
+/// # extern crate rocket;
+/// # extern crate rocket_csrf;
+/// fn main() {
+///     rocket::ignite()
+///         .attach(rocket_csrf::CsrfFairingBuilder::new()
+///                 .finalize().unwrap())
+///         .mount("/", vec![rocket_csrf::refresh_route("/csrf/refresh")])
+///         .launch();
+/// }
+/// ```
+/// and in your scripts:
+/// ```js
+/// fetch("/csrf/refresh", {credentials: "same-origin"})
+///     .then(response => response.json())
+///     .then(refreshed => token = refreshed.token);
+/// ```
+pub fn refresh_route(path: &str) -> Route {
+    Route::new(Method::Get, path, refresh)
+}
+
+fn refresh<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+    let token = match renew_cookie(request) {
+        Ok(token) => token,
+        Err(_) => return Outcome::Failure(Status::InternalServerError),
+    };
+    let expires_at = token
+        .expires_at()
+        .duration_since(UNIX_EPOCH)
+        .map(|expires_at| expires_at.as_secs())
+        .unwrap_or(0);
+    let body = format!(
+        "{{\"token\":\"{}\",\"expires_at\":{}}}",
+        from_utf8(token.value()).unwrap_or(""),
+        expires_at
+    ); //token is base64url, it never need escaping
+    Outcome::Success(
+        Response::build()
+            .header(ContentType::JSON)
+            .raw_header("Cache-Control", "no-store")
+            .sized_body(Cursor::new(body))
+            .finalize(),
+    )
+}
+
+fn timeout(request: &Request) -> i64 {
+    CsrfState::from(request)
+        .map(|state| state.duration)
+        .unwrap_or(0)
+}
+
+fn token<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+    let token = match request.guard::<CsrfToken>() {
+        Outcome::Success(token) => token,
+        _ => return Outcome::Failure(Status::InternalServerError),
+    }; //the guard also renew the cookie, as configured
+    let body = format!(
+        "{{\"token\":\"{}\",\"timeout\":{}}}",
+        from_utf8(token.value()).unwrap_or(""),
+        timeout(request)
+    ); //token is base64url, it never need escaping
+    Outcome::Success(
+        Response::build()
+            .header(ContentType::JSON)
+            .raw_header("Cache-Control", "no-store")
+            .sized_body(Cursor::new(body))
+            .finalize(),
+    )
+}
+
+fn script<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+    let interval = ::std::cmp::max(timeout(request) / 2, 30); //refresh well before expiry
+    let field = CsrfState::from(request)
+        .map(|state| state.field_name.as_str())
+        .unwrap_or(FORM_FIELD);
+    let body = REFRESH_SCRIPT
+        .replace("{interval}", &interval.to_string())
+        .replace("{field}", field);
+    Outcome::Success(
+        Response::build()
+            .header(ContentType::JavaScript)
+            .raw_header("Cache-Control", "no-cache")
+            .sized_body(Cursor::new(body))
+            .finalize(),
+    )
+}
@@ -0,0 +1,166 @@
+use rocket::handler;
+use rocket::http::{Method, Status};
+use rocket::outcome::Outcome;
+use rocket::{Data, Request, Route, State};
+use serde_json::{self, Map, Value};
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+use utils::now;
+use violation::ViolationReason;
+
+const REPORT_LIMIT: u64 = 8 * 1024;
+const REPORT_WINDOW: i64 = 60;
+
+/// A structured report of a csrf violation.
+///
+/// Reports are built by the fairing for each violation it sees, and received from clients on
+/// the uri set with [`set_report_uri`], as JSON objects with the same fields. They are all given
+/// to the function set with [`set_report_handler`], so violations can be monitored from one
+/// place, like CSP reports. Anyone can send a report to the report uri, so those received from
+/// clients are marked with `from_client`, and shouldn't be trusted.
+///
+/// [`set_report_uri`]: struct.CsrfFairingBuilder.html#method.set_report_uri
+/// [`set_report_handler`]: struct.CsrfFairingBuilder.html#method.set_report_handler
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViolationReport {
+    /// Uri of the request which violated csrf protection
+    pub uri: String,
+    /// Method of this request
+    pub method: String,
+    /// Why it is a violation
+    pub reason: String,
+    /// When it happened, in seconds since the Unix epoch
+    pub timestamp: i64,
+    /// Whether this report was sent by a client on the report uri, rather than built by the
+    /// fairing
+    pub from_client: bool,
+}
+
+impl ViolationReport {
+    /// Build a report for a request the fairing found violating csrf protection.
+    pub fn new(request: &Request, reason: ViolationReason) -> Self {
+        ViolationReport {
+            uri: request.uri().to_string(),
+            method: request.method().to_string(),
+            reason: reason.to_string(),
+            timestamp: now(),
+            from_client: false,
+        }
+    }
+
+    /// Parse a report sent by a client. The timestamp is optional, and default to now. The report
+    /// is always marked `from_client`, whatever the JSON says.
+    pub fn from_json(json: &str) -> Option<Self> {
+        let value: Value = serde_json::from_str(json).ok()?;
+        let field = |name: &str| value.get(name).and_then(Value::as_str).map(str::to_owned);
+        Some(ViolationReport {
+            uri: field("uri")?,
+            method: field("method")?,
+            reason: field("reason")?,
+            timestamp: value
+                .get("timestamp")
+                .and_then(Value::as_i64)
+                .unwrap_or_else(now),
+            from_client: true,
+        })
+    }
+
+    /// Serialize this report as a JSON object.
+    pub fn to_json(&self) -> String {
+        let mut map = Map::new();
+        map.insert("uri".to_owned(), Value::from(self.uri.clone()));
+        map.insert("method".to_owned(), Value::from(self.method.clone()));
+        map.insert("reason".to_owned(), Value::from(self.reason.clone()));
+        map.insert("timestamp".to_owned(), Value::from(self.timestamp));
+        map.insert("from_client".to_owned(), Value::from(self.from_client));
+        Value::Object(map).to_string()
+    }
+}
+
+pub type ReportHandler = Arc<Fn(&ViolationReport) + Send + Sync>;
+
+pub fn log_report(report: &ViolationReport) {
+    csrf_warn!("Csrf violation report: {}", report.to_json());
+}
+
+pub struct ReportState {
+    //handler of received reports, managed so the report route can reach it
+    handler: ReportHandler,
+    limit: u32,
+    window: Mutex<(i64, u32)>, //start of the current window, reports accepted since
+}
+
+impl ReportState {
+    pub fn new(handler: ReportHandler, limit: u32) -> Self {
+        ReportState {
+            handler,
+            limit,
+            window: Mutex::new((0, 0)),
+        }
+    }
+
+    fn accept(&self) -> bool {
+        let mut window = match self.window.lock() {
+            Ok(window) => window,
+            Err(_) => return false,
+        };
+        let now = now();
+        if now - window.0 >= REPORT_WINDOW {
+            *window = (now, 0);
+        }
+        if window.1 >= self.limit {
+            return false;
+        }
+        window.1 += 1;
+        true
+    }
+}
+
+pub fn report_route(report_uri: &str) -> Route {
+    Route::new(Method::Post, report_uri, receive_report)
+}
+
+fn receive_report<'r>(request: &'r Request, data: Data) -> handler::Outcome<'r> {
+    let mut body = String::new();
+    if data.open().take(REPORT_LIMIT).read_to_string(&mut body).is_err() {
+        return Outcome::Failure(Status::BadRequest);
+    }
+    let report = match ViolationReport::from_json(&body) {
+        Some(report) => report,
+        None => return Outcome::Failure(Status::BadRequest),
+    };
+    match request.guard::<State<ReportState>>() {
+        Outcome::Success(state) => {
+            if !state.accept() {
+                return Outcome::Failure(Status::TooManyRequests);
+            } //anyone can send reports, don't let them flood the handler
+            (state.handler)(&report)
+        }
+        _ => log_report(&report),
+    }
+    handler::Outcome::from(request, Status::NoContent)
+}
+
+#[cfg(test)]
+mod tests {
+    use report::ViolationReport;
+
+    #[test]
+    fn test_json_roundtrip() {
+        let report = ViolationReport {
+            uri: "/comment".to_owned(),
+            method: "POST".to_owned(),
+            reason: "invalid csrf token".to_owned(),
+            timestamp: 1_500_000_000,
+            from_client: true,
+        };
+        assert_eq!(ViolationReport::from_json(&report.to_json()), Some(report.clone()));
+        let forged = ViolationReport {
+            from_client: false,
+            ..report.clone()
+        };
+        assert_eq!(ViolationReport::from_json(&forged.to_json()), Some(report)); //clients can't pass as the fairing
+        assert!(ViolationReport::from_json("{\"uri\": \"/comment\"}").is_none());
+    }
+}
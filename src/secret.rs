@@ -0,0 +1,162 @@
+use data_encoding::BASE64;
+use rocket::config::{Config, Value};
+use std::fs;
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+use error::CsrfConfigError;
+use token::derive_key;
+
+/// Source of the secret keys, like a mounted secret file, Vault or a KMS.
+///
+/// Given to [`set_secret_provider`], it is asked for the keys each time the fairing is attached,
+/// so they don't have to be in the code nor in environment variables. Use
+/// [`set_secret_from_file`] for keys in a file.
+///
+/// [`set_secret_provider`]: struct.CsrfFairingBuilder.html#method.set_secret_provider
+/// [`set_secret_from_file`]: struct.CsrfFairingBuilder.html#method.set_secret_from_file
+///
+/// # Example
+///
+/// ```rust,ignore
+/// struct VaultSecret(VaultClient);
+///
+/// impl SecretProvider for VaultSecret {
+///     fn secrets(&self) -> Result<Vec<[u8; 32]>, CsrfConfigError> {
+///         self.0
+///             .read_keys("secret/csrf")
+///             .map_err(|e| CsrfConfigError::SecretUnavailable(e.to_string()))
+///     }
+/// }
+/// ```
+pub trait SecretProvider: Send + Sync {
+    /// Get the secret keys, the primary one first. Attaching the fairing fails if this fails or
+    /// gives no key.
+    fn secrets(&self) -> Result<Vec<[u8; 32]>, CsrfConfigError>;
+}
+
+pub struct FileSecretProvider {
+    //base64 keys, one per line, the primary one first
+    pub path: PathBuf,
+}
+
+impl SecretProvider for FileSecretProvider {
+    fn secrets(&self) -> Result<Vec<[u8; 32]>, CsrfConfigError> {
+        let content = fs::read_to_string(&self.path).map_err(|e| {
+            CsrfConfigError::SecretUnavailable(format!("{}: {}", self.path.display(), e))
+        })?;
+        let content = Zeroizing::new(content);
+        content
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(decode_secret)
+            .collect()
+    }
+}
+
+pub fn decode_secret(value: &str) -> Result<[u8; 32], CsrfConfigError> {
+    //decode a base64 secret, which must be 32 bytes long
+    let b64 = BASE64
+        .decode(value.as_bytes())
+        .map_err(|_| CsrfConfigError::InvalidSecretEncoding)?;
+    let b64 = Zeroizing::new(b64);
+    if b64.len() != 32 {
+        return Err(CsrfConfigError::BadSecretLength(b64.len()));
+    }
+    let mut array = [0; 32];
+    array.copy_from_slice(&b64);
+    Ok(array)
+}
+
+pub fn derive_secret(value: &str) -> Result<[u8; 32], CsrfConfigError> {
+    //derive our key from a base64 secret shared with Rocket, whatever its length
+    let secret = BASE64
+        .decode(value.as_bytes())
+        .map_err(|_| CsrfConfigError::InvalidSecretEncoding)?;
+    let secret = Zeroizing::new(secret);
+    if secret.is_empty() {
+        return Err(CsrfConfigError::BadSecretLength(0));
+    }
+    Ok(*derive_key(&secret))
+}
+
+pub fn rocket_secret(config: &Config) -> Result<Option<[u8; 32]>, CsrfConfigError> {
+    //Rocket keeps the secret_key of its configuration to itself, so read it where Rocket did, in
+    //the global table of Rocket.toml first, then in the table of the active environment
+    let content = match fs::read_to_string(&config.config_path) {
+        Ok(content) => Zeroizing::new(content),
+        Err(_) => return Ok(None),
+    }; //no Rocket.toml, the configuration was built in code
+    let table = match content.parse::<Value>() {
+        Ok(Value::Table(table)) => table,
+        _ => return Ok(None),
+    };
+    let environment = config.environment.to_string();
+    let secret = ["global", environment.as_str()]
+        .iter()
+        .filter_map(|name| table.get(*name)?.get("secret_key")?.as_str())
+        .next();
+    match secret {
+        Some(secret) => derive_secret(secret).map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data_encoding::BASE64;
+    use rocket::config::{Config, Environment};
+    use std::env;
+    use std::fs;
+
+    use error::CsrfConfigError;
+    use secret::{derive_secret, rocket_secret, FileSecretProvider, SecretProvider};
+
+    #[test]
+    fn test_file_provider() {
+        let path = env::temp_dir().join(format!("rocket_csrf_secret_{}", ::std::process::id()));
+        let content = format!("{}\n\n  {}  \n", BASE64.encode(&[1; 32]), BASE64.encode(&[2; 32]));
+        fs::write(&path, content).unwrap();
+        let provider = FileSecretProvider { path: path.clone() };
+        assert_eq!(provider.secrets(), Ok(vec![[1; 32], [2; 32]]));
+
+        fs::write(&path, BASE64.encode(&[1; 16])).unwrap();
+        assert_eq!(provider.secrets(), Err(CsrfConfigError::BadSecretLength(16)));
+
+        fs::remove_file(&path).unwrap();
+        match provider.secrets() {
+            Err(CsrfConfigError::SecretUnavailable(_)) => (),
+            _ => panic!("missing file accepted"),
+        }
+    }
+
+    #[test]
+    fn test_rocket_secret() {
+        let root = env::temp_dir().join(format!("rocket_csrf_config_{}", ::std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let config = Config::build(Environment::Staging).root(&root).unwrap();
+        assert_eq!(rocket_secret(&config), Ok(None));
+
+        let staging = BASE64.encode(&[1; 32]);
+        let global = BASE64.encode(&[2; 32]);
+        let path = root.join("Rocket.toml");
+        fs::write(&path, format!("[staging]\nsecret_key = \"{}\"\n", staging)).unwrap();
+        assert_eq!(rocket_secret(&config), Ok(Some(derive_secret(&staging).unwrap())));
+        assert_ne!(derive_secret(&staging).unwrap(), [1; 32]); //never the key of Rocket itself
+
+        fs::write(
+            &path,
+            format!(
+                "[global]\nsecret_key = \"{}\"\n[staging]\nsecret_key = \"{}\"\n",
+                global, staging
+            ),
+        ).unwrap();
+        assert_eq!(rocket_secret(&config), Ok(Some(derive_secret(&global).unwrap())));
+
+        let production = Config::build(Environment::Production).root(&root).unwrap();
+        fs::write(&path, format!("[staging]\nsecret_key = \"{}\"\n", staging)).unwrap();
+        assert_eq!(rocket_secret(&production), Ok(None));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
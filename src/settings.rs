@@ -0,0 +1,297 @@
+use rocket::config::{Table, Value};
+use rocket::http::{Method, SameSite};
+use std::collections::HashMap;
+
+use csrf_state::{CookieOptions, CookiePrefix};
+use error::CsrfConfigError;
+use path::Path;
+use pattern::Pattern;
+
+pub const CONFIG_TABLE: &str = "csrf";
+
+const KEYS: &[&str] = &[
+    "timeout",
+    "default_target",
+    "default_target_method",
+    "exceptions",
+    "auto_insert",
+    "auto_insert_disable_prefix",
+    "auto_insert_max_chunk_size",
+    "cookie",
+];
+
+const COOKIE_KEYS: &[&str] = &[
+    "name",
+    "same_site",
+    "secure",
+    "http_only",
+    "path",
+    "domain",
+    "prefix",
+];
+
+pub struct Settings {
+    //part of the configuration which may be overridden by the [csrf] table of Rocket's config
+    pub duration: i64,
+    pub default_target: (Path, Method),
+    pub exceptions: Vec<(Pattern, Path, Method)>,
+    pub auto_insert: bool,
+    pub auto_insert_disable_prefix: Vec<Pattern>,
+    pub auto_insert_max_size: u64,
+    pub cookie_options: CookieOptions,
+}
+
+pub fn default_target(target: &str, method: Method) -> Result<(Path, Method), CsrfConfigError> {
+    let path = Path::from(target);
+    let mut hashmap = HashMap::new();
+    hashmap.insert("uri", "".to_owned());
+    hashmap.insert("locale", "".to_owned());
+    if path.map(&hashmap).is_none() {
+        return Err(CsrfConfigError::InvalidDefaultTarget(target.to_owned()));
+    } //verify if this path is valid as default path, i.e. its only dynamic parts are <uri> and <locale>
+    Ok((path, method))
+}
+
+pub fn exception(
+    source: &str,
+    target: &str,
+    method: Method,
+) -> Result<(Pattern, Path, Method), CsrfConfigError> {
+    let (pattern, path) = (Pattern::path(source)?, Path::from(target));
+    let compatible = {
+        let mut param: HashMap<&str, String> = pattern
+            .names()
+            .into_iter()
+            .map(|name| (name, String::new()))
+            .collect();
+        param.insert("locale", String::new()); //the locale of the request is always given
+        path.map(&param).is_some()
+    };
+    if !compatible {
+        return Err(CsrfConfigError::IncompatibleExceptionParams {
+            source: source.to_owned(),
+            target: target.to_owned(),
+        });
+    } //the target must be buildable from what the source extract
+    Ok((pattern, path, method))
+}
+
+pub fn prefixes(prefixes: &[String]) -> Result<Vec<Pattern>, CsrfConfigError> {
+    //compile patterns once, not on every request
+    prefixes.iter().map(|prefix| Pattern::prefix(prefix)).collect()
+}
+
+fn invalid(key: &str, reason: &str) -> CsrfConfigError {
+    CsrfConfigError::InvalidConfigEntry {
+        key: format!("{}.{}", CONFIG_TABLE, key),
+        reason: reason.to_owned(),
+    }
+}
+
+fn string<'a>(table: &'a Table, key: &str) -> Result<Option<&'a str>, CsrfConfigError> {
+    match table.get(key) {
+        Some(value) => value
+            .as_str()
+            .map(Some)
+            .ok_or_else(|| invalid(key, "expected a string")),
+        None => Ok(None),
+    }
+}
+
+fn boolean(table: &Table, key: &str) -> Result<Option<bool>, CsrfConfigError> {
+    match table.get(key) {
+        Some(value) => value
+            .as_bool()
+            .map(Some)
+            .ok_or_else(|| invalid(key, "expected a boolean")),
+        None => Ok(None),
+    }
+}
+
+fn integer(table: &Table, key: &str) -> Result<Option<i64>, CsrfConfigError> {
+    match table.get(key) {
+        Some(value) => value
+            .as_integer()
+            .map(Some)
+            .ok_or_else(|| invalid(key, "expected an integer")),
+        None => Ok(None),
+    }
+}
+
+fn method(key: &str, method: &str) -> Result<Method, CsrfConfigError> {
+    method
+        .parse::<Method>()
+        .map_err(|_| invalid(key, "expected an http method"))
+}
+
+fn check_keys(table: &Table, known: &[&str], prefix: &str) -> Result<(), CsrfConfigError> {
+    //typos would otherwise be silently ignored
+    match table.keys().find(|key| !known.contains(&key.as_str())) {
+        Some(key) => Err(invalid(&format!("{}{}", prefix, key), "unknown key")),
+        None => Ok(()),
+    }
+}
+
+impl Settings {
+    pub fn apply(&mut self, table: &Table) -> Result<(), CsrfConfigError> {
+        //override settings with the values of the [csrf] table
+        check_keys(table, KEYS, "")?;
+        if let Some(timeout) = integer(table, "timeout")? {
+            self.duration = timeout;
+        }
+        let target_method = match string(table, "default_target_method")? {
+            Some(target_method) => method("default_target_method", target_method)?,
+            None => self.default_target.1,
+        };
+        match string(table, "default_target")? {
+            Some(target) => self.default_target = default_target(target, target_method)?,
+            None => self.default_target.1 = target_method,
+        }
+        if let Some(exceptions) = table.get("exceptions") {
+            self.exceptions = exceptions
+                .as_array()
+                .ok_or_else(|| invalid("exceptions", "expected an array of tables"))?
+                .iter()
+                .map(exception_entry)
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(auto_insert) = boolean(table, "auto_insert")? {
+            self.auto_insert = auto_insert;
+        }
+        if let Some(disable_prefix) = table.get("auto_insert_disable_prefix") {
+            let disable_prefix = disable_prefix
+                .as_array()
+                .and_then(|prefixes| {
+                    prefixes
+                        .iter()
+                        .map(|prefix| prefix.as_str().map(|prefix| prefix.to_owned()))
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| invalid("auto_insert_disable_prefix", "expected an array of strings"))?;
+            self.auto_insert_disable_prefix = prefixes(&disable_prefix)?;
+        }
+        if let Some(max_size) = integer(table, "auto_insert_max_chunk_size")? {
+            if max_size < 0 {
+                return Err(invalid("auto_insert_max_chunk_size", "expected a positive integer"));
+            }
+            self.auto_insert_max_size = max_size as u64;
+        }
+        if let Some(cookie) = table.get("cookie") {
+            let cookie = cookie
+                .as_table()
+                .ok_or_else(|| invalid("cookie", "expected a table"))?;
+            self.apply_cookie(cookie)?;
+        }
+        Ok(())
+    }
+
+    fn apply_cookie(&mut self, table: &Table) -> Result<(), CsrfConfigError> {
+        check_keys(table, COOKIE_KEYS, "cookie.")?;
+        let options = &mut self.cookie_options;
+        if let Some(name) = string(table, "name")? {
+            options.name = name.to_owned();
+        }
+        if let Some(same_site) = string(table, "same_site")? {
+            options.same_site = match same_site.to_lowercase().as_str() {
+                "strict" => SameSite::Strict,
+                "lax" => SameSite::Lax,
+                _ => return Err(invalid("cookie.same_site", "expected \"strict\" or \"lax\"")),
+            };
+        }
+        if let Some(secure) = boolean(table, "secure")? {
+            options.secure = secure;
+        }
+        if let Some(http_only) = boolean(table, "http_only")? {
+            options.http_only = http_only;
+        }
+        if let Some(path) = string(table, "path")? {
+            options.path = path.to_owned();
+        }
+        if let Some(domain) = string(table, "domain")? {
+            options.domain = Some(domain.to_owned());
+        }
+        if let Some(prefix) = string(table, "prefix")? {
+            options.prefix = match prefix.to_lowercase().as_str() {
+                "none" => CookiePrefix::None,
+                "secure" => CookiePrefix::Secure,
+                "host" => CookiePrefix::Host,
+                _ => return Err(invalid("cookie.prefix", "expected none, secure or host")),
+            };
+        }
+        Ok(())
+    }
+}
+
+fn exception_entry(value: &Value) -> Result<(Pattern, Path, Method), CsrfConfigError> {
+    //an exception, as a { source, target, method } table
+    let table = value
+        .as_table()
+        .ok_or_else(|| invalid("exceptions", "expected an array of tables"))?;
+    let source = string(table, "source")?.ok_or_else(|| invalid("exceptions", "missing source"))?;
+    let target = string(table, "target")?.ok_or_else(|| invalid("exceptions", "missing target"))?;
+    let target_method = match string(table, "method")? {
+        Some(target_method) => method("exceptions", target_method)?,
+        None => Method::Get,
+    };
+    exception(source, target, target_method)
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::config::{Table, Value};
+    use rocket::http::Method;
+
+    use csrf_state::{CookieOptions, CookiePrefix};
+    use error::CsrfConfigError;
+    use settings::{default_target, Settings};
+
+    fn settings() -> Settings {
+        Settings {
+            duration: 3600,
+            default_target: default_target("/", Method::Get).unwrap(),
+            exceptions: Vec::new(),
+            auto_insert: true,
+            auto_insert_disable_prefix: Vec::new(),
+            auto_insert_max_size: 16 * 1024,
+            cookie_options: CookieOptions::default(),
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut exception = Table::new();
+        exception.insert("source".to_owned(), Value::from("/legacy/<id>"));
+        exception.insert("target".to_owned(), Value::from("/error?id=<id>"));
+        let mut cookie = Table::new();
+        cookie.insert("secure".to_owned(), Value::from(true));
+        cookie.insert("prefix".to_owned(), Value::from("host"));
+        let mut table = Table::new();
+        table.insert("timeout".to_owned(), Value::from(600));
+        table.insert("default_target".to_owned(), Value::from("/csrf-violation"));
+        table.insert("exceptions".to_owned(), Value::Array(vec![Value::Table(exception)]));
+        table.insert("auto_insert".to_owned(), Value::from(false));
+        table.insert("cookie".to_owned(), Value::Table(cookie));
+
+        let mut settings = settings();
+        settings.apply(&table).unwrap();
+        assert_eq!(settings.duration, 600);
+        assert_eq!(settings.exceptions.len(), 1);
+        assert!(!settings.auto_insert);
+        assert!(settings.cookie_options.secure);
+        assert_eq!(settings.cookie_options.prefix, CookiePrefix::Host);
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let mut table = Table::new();
+        table.insert("timout".to_owned(), Value::from(600));
+        match settings().apply(&table) {
+            Err(CsrfConfigError::InvalidConfigEntry { key, .. }) => assert_eq!(key, "csrf.timout"),
+            _ => panic!("unknown key accepted"),
+        }
+
+        let mut table = Table::new();
+        table.insert("auto_insert".to_owned(), Value::from("yes"));
+        assert!(settings().apply(&table).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use rocket::data::{self, FromData};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::{FormItems, FromForm};
+use rocket::{Data, Request};
+use std::io::Read;
+use std::ops::Deref;
+
+use csrf_state::CsrfState;
+use error::CsrfError;
+use token::FORM_FIELD;
+use utils::{form_decode, parse_args};
+
+pub const DEFAULT_FORM_LIMIT: u64 = 32 * 1024;
+
+/// Form data guard ignoring the csrf token field.
+///
+/// Rocket fairings can't modify request bodies, so the token inserted into forms reach handlers.
+/// With `Form<T>`, this fails strict parsing unless `T` has a `csrf-token` field. `StrippedForm`
+/// remove this field (and only this one) before parsing strictly, so unexpected fields are still
+/// rejected. Verification is still done by the fairing. The body size is limited by the `forms`
+/// limit of Rocket's configuration.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[post("/login", data = "<login>")]
+/// fn login(login: StrippedForm<Login>) -> Redirect {
+///     //...
+/// }
+/// ```
+#[derive(Debug)]
+pub struct StrippedForm<T>(pub T);
+
+impl<T> StrippedForm<T> {
+    /// Consume the guard, returning the parsed form.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for StrippedForm<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+pub fn strip_token(form: &str, field_name: &str) -> String {
+    //remove every csrf token field from an url-encoded form, with keys decoded as FormItems does
+    form.split('&')
+        .filter(|kv| {
+            parse_args(kv)
+                .next()
+                .and_then(|(key, _)| form_decode(key))
+                .map(|key| key != field_name)
+                .unwrap_or(true)
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+pub fn field_name(request: &Request) -> String {
+    //name of the token field, as configured in the fairing if it is attached
+    CsrfState::from(request)
+        .map(|state| state.field_name.clone())
+        .unwrap_or_else(|_| FORM_FIELD.to_owned())
+}
+
+pub fn read_form(request: &Request, data: Data) -> Result<String, CsrfError> {
+    //read a form body, up to the configured limit
+    let limit = request.limits().get("forms").unwrap_or(DEFAULT_FORM_LIMIT);
+    let mut form = String::new();
+    data.open().take(limit).read_to_string(&mut form)?;
+    Ok(form)
+}
+
+impl<T> FromData for StrippedForm<T>
+where
+    T: for<'f> FromForm<'f>,
+{
+    type Error = CsrfError;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, CsrfError> {
+        let form = match read_form(request, data) {
+            Ok(form) => form,
+            Err(e) => return Outcome::Failure((Status::BadRequest, e)),
+        };
+        let stripped = strip_token(&form, &field_name(request));
+        match T::from_form(&mut FormItems::from(stripped.as_str()), true) {
+            Ok(value) => Outcome::Success(StrippedForm(value)),
+            Err(_) => Outcome::Failure((Status::UnprocessableEntity, CsrfError::Parse(form))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stripped_form::strip_token;
+
+    #[test]
+    fn test_strip_token() {
+        assert_eq!(strip_token("a=1&csrf-token=abc&b=2", "csrf-token"), "a=1&b=2");
+        assert_eq!(strip_token("csrf-token=abc", "csrf-token"), "");
+        assert_eq!(strip_token("a=1&flag&b=csrf-token", "csrf-token"), "a=1&flag&b=csrf-token");
+        assert_eq!(strip_token("a=1&_xsrf=abc&csrf-token=def", "_xsrf"), "a=1&csrf-token=def");
+        assert_eq!(strip_token("a=1&csrf%5Ftoken=abc", "csrf_token"), "a=1");
+        assert_eq!(strip_token("csrf%2Dtoken=abc&b=2", "csrf-token"), "b=2");
+        assert_eq!(strip_token("my+field=abc&b=2", "my field"), "b=2");
+    }
+}
@@ -0,0 +1,82 @@
+use rocket::http::Status;
+use rocket::response::{self, Responder, Response};
+use rocket::Request;
+use rocket_contrib::Template;
+use serde::Serialize;
+use serde_json::{self, Map, Value};
+use std::borrow::Cow;
+
+use csrf_token::CsrfToken;
+use no_auto_insert::NO_AUTO_INSERT_HEADER;
+
+const TOKEN_VARIABLE: &str = "csrf_token";
+const FIELD_VARIABLE: &str = "csrf_field";
+
+/// Template whose context get the csrf token of the request, with the `templates` feature.
+///
+/// `CsrfTemplate::render` takes the same arguments as `Template::render`, and adds two variables
+/// to the context: `csrf_token`, the token itself, and `csrf_field`, the whole hidden input to
+/// put in forms. The response is then left untouched by auto-insert, as the template places
+/// tokens where they belong. The context must serialize to a map, or to nothing.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[get("/comment")]
+/// fn comment() -> CsrfTemplate {
+///     CsrfTemplate::render("comment", &context)
+/// }
+/// ```
+/// with, in `comment.html.tera`:
+/// ```html
+/// <form method="post">
+///     {{ csrf_field | safe }}
+///     <!-- or <input type="hidden" name="csrf-token" value="{{ csrf_token }}"> -->
+/// </form>
+/// ```
+#[derive(Debug)]
+pub struct CsrfTemplate {
+    name: Cow<'static, str>,
+    context: Result<Value, String>, //serialization errors are reported when responding
+}
+
+impl CsrfTemplate {
+    /// Render the template `name` with `context`, to which the csrf variables are added.
+    pub fn render<S, C>(name: S, context: C) -> Self
+    where
+        S: Into<Cow<'static, str>>,
+        C: Serialize,
+    {
+        CsrfTemplate {
+            name: name.into(),
+            context: serde_json::to_value(context).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl<'r> Responder<'r> for CsrfTemplate {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut context = match self.context {
+            Ok(Value::Object(context)) => context,
+            Ok(Value::Null) => Map::new(),
+            Ok(_) => {
+                csrf_warn!("Context of template {} is not a map", self.name);
+                return Err(Status::InternalServerError);
+            }
+            Err(e) => {
+                csrf_warn!("Could not serialize context of template {}: {}", self.name, e);
+                return Err(Status::InternalServerError);
+            }
+        };
+        let token = match request.guard::<CsrfToken>().succeeded() {
+            Some(token) => token,
+            None => return Err(Status::InternalServerError),
+        }; //the fairing is not attached
+        let csrf = token.context_value();
+        context.insert(TOKEN_VARIABLE.to_owned(), csrf["value"].clone());
+        context.insert(FIELD_VARIABLE.to_owned(), csrf["field"].clone());
+        Response::build_from(Template::render(self.name, context).respond_to(request)?)
+            .raw_header(NO_AUTO_INSERT_HEADER, "1")
+            .ok()
+    }
+}
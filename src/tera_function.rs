@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+use tera::{self, Tera, Value};
+
+use csrf_proxy::hidden_field;
+use csrf_token::current_token;
+
+/// Register the `csrf_input()` function into a Tera instance, with the `tera` feature.
+///
+/// The function renders the hidden input holding the token of the request being answered, for
+/// templates placing tokens themselves instead of relying on auto-insert. Its output must not be
+/// escaped. Call this where your Tera engine is built, like in `Template::custom` with a
+/// `rocket_contrib` providing it.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// rocket::ignite()
+///     .attach(Template::custom(|engines| rocket_csrf::register_tera(&mut engines.tera)))
+/// ```
+/// and in your templates:
+/// ```html
+/// <form method="post">{{ csrf_input() | safe }} ...</form>
+/// ```
+pub fn register_tera(tera: &mut Tera) {
+    tera.register_global_function("csrf_input", Box::new(csrf_input));
+}
+
+fn csrf_input(_: HashMap<String, Value>) -> tera::Result<Value> {
+    //the token is taken from the request handled by the current thread, as Tera functions don't
+    //get the request
+    match current_token() {
+        Some(token) => Ok(Value::String(
+            String::from_utf8_lossy(&hidden_field(&token)).into_owned(),
+        )),
+        None => Err("csrf_input() called without a csrf token, is the fairing attached?".into()),
+    }
+}
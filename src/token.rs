@@ -0,0 +1,861 @@
+use csrf::{
+    self, AesGcmCsrfProtection, ChaCha20Poly1305CsrfProtection, CsrfProtection,
+    HmacCsrfProtection, UnencryptedCsrfCookie, UnencryptedCsrfToken,
+};
+use data_encoding::{BASE64, BASE64URL_NOPAD};
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, Rng};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use error::CsrfError;
+use utils::{constant_time_eq, now};
+
+//every use of the csrf crate is kept in this module: it still encrypts and signs cookies and
+//tokens, this only isolates the dependency, so the rest of the crate never sees its types
+
+pub const COOKIE_NAME: &str = csrf::CSRF_COOKIE_NAME;
+pub const FORM_FIELD: &str = csrf::CSRF_FORM_FIELD;
+
+const KEY_ID_SEPARATOR: char = ':';
+const BINDING_SEPARATOR: char = '~';
+const SIGNED_ID_LEN: usize = 32;
+const NONCE_LEN: usize = 32;
+const MAC_LEN: usize = 32;
+const TIME_LEN: usize = 8;
+const DERIVED_KEY_INFO: &[u8] = b"rocket_csrf secret key";
+
+/// Cryptographic primitive protecting cookies and tokens.
+///
+/// Every backend is safe to use, they differ in cost and in what a client can see. Switching
+/// backend invalidates every token and cookie already issued.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsrfBackend {
+    /// AES-256-GCM, encrypting cookies and tokens. Fastest where the CPU has AES instructions.
+    /// This is the default.
+    AesGcm,
+    /// ChaCha20-Poly1305, encrypting cookies and tokens. Faster than AES-GCM on CPUs without AES
+    /// instructions, like many ARM ones.
+    ChaCha20Poly1305,
+    /// HMAC-SHA256, only signing cookies and tokens, which is the cheapest. Their content, a
+    /// random value and an expiry time, is readable by clients, but can't be forged.
+    Hmac,
+}
+
+impl Default for CsrfBackend {
+    fn default() -> Self {
+        CsrfBackend::AesGcm
+    }
+}
+
+impl CsrfBackend {
+    fn protection(self, key: &Zeroizing<[u8; 32]>) -> Box<CsrfProtection> {
+        //the csrf crate takes the key by value and keeps it, expanded into its cipher, without
+        //wiping either on drop: this copy is the only key material that outlives the engine
+        match self {
+            CsrfBackend::AesGcm => Box::new(AesGcmCsrfProtection::from_key(**key)),
+            CsrfBackend::ChaCha20Poly1305 => {
+                Box::new(ChaCha20Poly1305CsrfProtection::from_key(**key))
+            }
+            CsrfBackend::Hmac => Box::new(HmacCsrfProtection::from_key(**key)),
+        }
+    }
+}
+
+/// How cookies and tokens are made.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenScheme {
+    /// The cookie holds a random secret and its expiry, encrypted with the backend, and tokens
+    /// are made from this secret, encrypted too. This is the default.
+    EncryptedPair,
+    /// Signed double submit: the cookie holds a random identifier and its expiry, signed with
+    /// HMAC-SHA256, and tokens are HMACs of this identifier and of a random nonce given along, so
+    /// they can't be linked to each other. Verifying a request only compute HMACs, without any decryption, which is
+    /// cheaper for high throughput APIs. The backend is not used with this scheme.
+    SignedDoubleSubmit,
+}
+
+impl Default for TokenScheme {
+    fn default() -> Self {
+        TokenScheme::EncryptedPair
+    }
+}
+
+struct Key {
+    id: String, //prefix of tokens made with this key
+    secret: Zeroizing<[u8; 32]>,
+    inner: Box<CsrfProtection>, //not wiped on drop, see CsrfBackend::protection
+}
+
+impl Key {
+    fn new(key: &[u8; 32], backend: CsrfBackend) -> Self {
+        let secret = Zeroizing::new(*key);
+        Key {
+            id: key_id(key),
+            inner: backend.protection(&secret),
+            secret,
+        }
+    }
+
+    fn mac(&self, label: &[u8], parts: &[&[u8]]) -> Hmac<Sha256> {
+        //label separate the uses of the key, so a mac made for a cookie is never a valid token
+        let mut mac =
+            Hmac::<Sha256>::new_varkey(&*self.secret).expect("hmac accept keys of any size");
+        mac.input(label);
+        for part in parts {
+            mac.input(part);
+        }
+        mac
+    }
+
+    fn sign(&self, label: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+        self.mac(label, parts).result().code().to_vec()
+    }
+
+    fn check(&self, label: &[u8], parts: &[&[u8]], signature: &[u8]) -> bool {
+        self.mac(label, parts).verify(signature).is_ok() //in constant time
+    }
+}
+
+pub struct TokenEngine {
+    keys: Vec<Key>, //the primary key, then older keys only used for verification
+    scheme: TokenScheme,
+}
+
+enum CookieContent {
+    //with a grace period, cookies are valid until the end of it, and also carry the end of their
+    //timeout, after which they are renewed. Decrypted cookies of the csrf crate can't be wiped on
+    //drop, it gives no mutable access to them
+    Encrypted {
+        cookie: UnencryptedCsrfCookie,
+        timeout: Option<UnencryptedCsrfCookie>,
+        expires: Option<i64>, //given along in clear, cookies made by older versions don't have it
+    },
+    Signed {
+        id: Zeroizing<Vec<u8>>,
+        expires: i64,
+        timeout: Option<i64>,
+    },
+}
+
+pub struct CookieSecret {
+    //decrypted or authenticated content of a csrf cookie, which may be expired
+    inner: CookieContent,
+    binding: Option<Vec<u8>>, //what tokens must be bound to, from the request the cookie came with
+    action: Option<String>,   //path the request the cookie came with was sent to
+}
+
+impl CookieSecret {
+    fn new(inner: CookieContent) -> Self {
+        CookieSecret {
+            inner,
+            binding: None,
+            action: None,
+        }
+    }
+
+    pub fn value(&self) -> &[u8] {
+        match &self.inner {
+            CookieContent::Encrypted { cookie, .. } => cookie.value(),
+            CookieContent::Signed { id, .. } => &id[..],
+        }
+    }
+
+    pub fn bind(mut self, binding: Option<Vec<u8>>) -> Self {
+        self.binding = binding;
+        self
+    }
+
+    pub fn binding(&self) -> Option<&[u8]> {
+        self.binding.as_ref().map(|binding| &binding[..])
+    }
+
+    pub fn expires(&self) -> Option<i64> {
+        //end of the timeout of the cookie, only informative as encrypted cookies don't protect it
+        match &self.inner {
+            CookieContent::Encrypted { expires, .. } => *expires,
+            CookieContent::Signed {
+                expires, timeout, ..
+            } => Some(timeout.unwrap_or(*expires)),
+        }
+    }
+
+    pub fn scope(mut self, action: &str) -> Self {
+        self.action = Some(action.to_owned());
+        self
+    }
+}
+
+pub struct IssuedCookie {
+    pub token: String,  //token for the cookie, base64url encoded
+    pub cookie: String, //encrypted cookie, base64 encoded
+    pub secret: Zeroizing<Vec<u8>>, //secret value of the cookie, to mint more tokens later
+    pub expires: i64,    //end of the timeout of the cookie
+}
+
+fn hkdf_sha256(salt: &[u8], ikm: &[u8], info: &[u8]) -> Zeroizing<[u8; 32]> {
+    //HKDF (RFC 5869) with SHA-256, giving a single block of output
+    let mut prk = Hmac::<Sha256>::new_varkey(salt).expect("hmac accept keys of any size");
+    prk.input(ikm);
+    let prk = Zeroizing::new(prk.result().code().to_vec());
+    let mut okm = Hmac::<Sha256>::new_varkey(&prk).expect("hmac accept keys of any size");
+    okm.input(info);
+    okm.input(&[1]);
+    let mut key = Zeroizing::new([0; 32]);
+    key.copy_from_slice(&okm.result().code());
+    key
+}
+
+pub fn derive_key(secret: &[u8]) -> Zeroizing<[u8; 32]> {
+    //key of this crate derived from a secret of any length, like Rocket's secret_key, so the same
+    //secret never protects both Rocket's private cookies and our tokens
+    hkdf_sha256(&[], secret, DERIVED_KEY_INFO)
+}
+
+fn secret_array(secret: &[u8]) -> Option<Zeroizing<[u8; 64]>> {
+    if secret.len() == 64 {
+        let mut array = Zeroizing::new([0; 64]);
+        array.copy_from_slice(secret);
+        Some(array)
+    } else {
+        None
+    }
+}
+
+fn time_bytes(time: i64) -> [u8; TIME_LEN] {
+    let mut bytes = [0; TIME_LEN];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (time >> (8 * (TIME_LEN - 1 - i))) as u8;
+    }
+    bytes
+}
+
+fn bytes_time(bytes: &[u8]) -> i64 {
+    bytes
+        .iter()
+        .fold(0, |time, byte| (time << 8) | i64::from(*byte))
+}
+
+fn key_id(key: &[u8; 32]) -> String {
+    //short and stable identifier of a key: 16 bits of its FNV-1a hash, so a token tells next to
+    //nothing about the key which made it
+    let hash = key.iter().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    });
+    format!("{:04x}", hash >> 16)
+}
+
+impl TokenEngine {
+    pub fn new(keys: &[[u8; 32]], backend: CsrfBackend, scheme: TokenScheme) -> Self {
+        //the first key is the primary one, there must be at least one
+        assert!(!keys.is_empty(), "a token engine needs at least one key");
+        TokenEngine {
+            keys: keys.iter().map(|key| Key::new(key, backend)).collect(),
+            scheme,
+        }
+    }
+
+    fn primary(&self) -> &Key {
+        &self.keys[0]
+    }
+
+    fn encode_token(&self, token: &[u8]) -> String {
+        format!(
+            "{}{}{}",
+            self.primary().id,
+            KEY_ID_SEPARATOR,
+            BASE64URL_NOPAD.encode(token)
+        )
+    }
+
+    fn split_token<'t>(&self, token: &'t str) -> Option<(Vec<&'t Key>, Vec<u8>)> {
+        //keys which may have made a token, and the token itself, base64url decoded
+        let (id, token) = match token.find(KEY_ID_SEPARATOR) {
+            Some(i) => (Some(&token[..i]), &token[i + 1..]),
+            None => (None, token), //made before keys had ids, any key may have made it
+        };
+        let keys = self
+            .keys
+            .iter()
+            .filter(|key| id.map(|id| id == key.id).unwrap_or(true))
+            .collect();
+        let token = BASE64URL_NOPAD.decode(token.as_bytes()).ok()?;
+        Some((keys, token))
+    }
+
+    fn bind(&self, token: String, binding: Option<&[u8]>, action: Option<&str>) -> Option<String> {
+        //tie a token to what identifies the client, like its session, and to the path of the form
+        //it is for, with a mac of all of them given along. The token is part of the mac, so
+        //tokens with the same binding can't be linked
+        let (_, nonce) = self.split_token(&token)?;
+        let signature = match (binding, action) {
+            (None, None) => return Some(token),
+            (Some(binding), None) => self.primary().sign(b"binding", &[&nonce, binding]),
+            (binding, Some(action)) => self.primary().sign(
+                b"action",
+                &[&nonce, binding.unwrap_or(&[]), action.as_bytes()],
+            ),
+        };
+        Some(format!(
+            "{}{}{}",
+            token,
+            BINDING_SEPARATOR,
+            BASE64URL_NOPAD.encode(&signature)
+        ))
+    }
+
+    fn check_binding<'t>(
+        &self,
+        token: &'t str,
+        binding: Option<&[u8]>,
+        action: Option<&str>,
+    ) -> Option<&'t str> {
+        //the token without its binding, if it is bound to the same thing as the request, or if
+        //neither of them is bound. A token made for a form is only valid on the path of this form
+        let (token, signature) = match token.find(BINDING_SEPARATOR) {
+            Some(i) => (&token[..i], &token[i + 1..]),
+            None if binding.is_none() => return Some(token),
+            None => return None,
+        };
+        let (keys, nonce) = self.split_token(token)?;
+        let signature = BASE64URL_NOPAD.decode(signature.as_bytes()).ok()?;
+        let bound = binding
+            .map(|binding| {
+                keys.iter()
+                    .any(|key| key.check(b"binding", &[&nonce, binding], &signature))
+            })
+            .unwrap_or(false);
+        let scoped = action
+            .map(|action| {
+                let parts: &[&[u8]] = &[&nonce, binding.unwrap_or(&[]), action.as_bytes()];
+                keys.iter().any(|key| key.check(b"action", parts, &signature))
+            })
+            .unwrap_or(false);
+        if bound || scoped {
+            Some(token)
+        } else {
+            None
+        }
+    }
+
+    pub fn issue(
+        &self,
+        previous: Option<&CookieSecret>,
+        ttl: i64,
+        grace: i64,
+        binding: Option<&[u8]>,
+    ) -> Result<IssuedCookie, CsrfError> {
+        //create a cookie valid for ttl seconds, plus grace seconds during which it is still
+        //accepted but should be renewed, keeping the secret of the previous one if any, and a
+        //token for it bound to binding
+        if self.scheme == TokenScheme::SignedDoubleSubmit {
+            let id = previous
+                .map(|previous| previous.value())
+                .filter(|id| id.len() == SIGNED_ID_LEN)
+                .map(|id| id.to_vec())
+                .unwrap_or_else(|| thread_rng().gen::<[u8; SIGNED_ID_LEN]>().to_vec());
+            let id = Zeroizing::new(id);
+            let expires = time_bytes(now() + ttl + grace);
+            let timeout = if grace > 0 {
+                time_bytes(now() + ttl).to_vec()
+            } else {
+                Vec::new()
+            };
+            let signature = self.primary().sign(b"cookie", &[&id, &expires, &timeout]);
+            let cookie = [&id[..], &expires[..], &timeout[..], &signature[..]].concat();
+            return Ok(IssuedCookie {
+                token: self
+                    .bind(self.signed_token(&id), binding, None)
+                    .ok_or_else(|| CsrfError::Crypto("unbindable token".to_owned()))?,
+                cookie: BASE64.encode(&cookie),
+                secret: id,
+                expires: now() + ttl,
+            });
+        }
+        let value = previous.and_then(|previous| secret_array(previous.value()));
+        let primary = &self.primary().inner;
+        let (token, cookie) = primary
+            .generate_token_pair(value.as_ref().map(|value| &**value), ttl + grace)
+            .map_err(|e| CsrfError::Crypto(format!("{:?}", e)))?;
+        let secret = primary
+            .parse_cookie(cookie.value())
+            .map(|cookie| Zeroizing::new(cookie.value().to_vec()))
+            .map_err(|e| CsrfError::Crypto(format!("{:?}", e)))?;
+        let timeout = if grace > 0 {
+            let timeout = secret_array(&secret)
+                .ok_or_else(|| CsrfError::Crypto("invalid cookie secret".to_owned()))
+                .and_then(|secret| {
+                    primary
+                        .generate_cookie(&secret, ttl)
+                        .map_err(|e| CsrfError::Crypto(format!("{:?}", e)))
+                })?;
+            timeout.value().to_vec() //same length and secret as the cookie
+        } else {
+            Vec::new()
+        };
+        let expires = now() + ttl;
+        let cookie = [cookie.value(), &timeout[..], &time_bytes(expires)[..]].concat();
+        Ok(IssuedCookie {
+            token: self
+                .bind(self.encode_token(token.value()), binding, None)
+                .ok_or_else(|| CsrfError::Crypto("unbindable token".to_owned()))?,
+            cookie: BASE64.encode(&cookie),
+            secret,
+            expires,
+        })
+    }
+
+    fn decrypt_cookie(&self, cookie: &[u8]) -> Option<UnencryptedCsrfCookie> {
+        //a cookie is only accepted by the key which encrypted it, try them all
+        self.keys
+            .iter()
+            .filter_map(|key| key.inner.parse_cookie(cookie).ok())
+            .next()
+    }
+
+    pub fn parse_cookie(&self, cookie: &[u8]) -> Option<CookieSecret> {
+        //decrypt or authenticate a cookie, already base64 decoded
+        if self.scheme == TokenScheme::SignedDoubleSubmit {
+            let timeout_len = match cookie.len().checked_sub(SIGNED_ID_LEN + TIME_LEN + MAC_LEN) {
+                Some(len) if len == 0 || len == TIME_LEN => len,
+                _ => return None,
+            };
+            let (id, rest) = cookie.split_at(SIGNED_ID_LEN);
+            let (expires, rest) = rest.split_at(TIME_LEN);
+            let (timeout, signature) = rest.split_at(timeout_len);
+            return if self
+                .keys
+                .iter()
+                .any(|key| key.check(b"cookie", &[id, expires, timeout], signature))
+            {
+                Some(CookieSecret::new(CookieContent::Signed {
+                    id: Zeroizing::new(id.to_vec()),
+                    expires: bytes_time(expires),
+                    timeout: if timeout.is_empty() { None } else { Some(bytes_time(timeout)) },
+                }))
+            } else {
+                None
+            };
+        }
+        if let Some((cookie, timeout)) = self.decrypt_pair(cookie) {
+            return Some(CookieSecret::new(CookieContent::Encrypted {
+                cookie,
+                timeout,
+                expires: None,
+            }));
+        } //made before the expiry was given along
+        if cookie.len() < TIME_LEN {
+            return None;
+        }
+        let (cookie, expires) = cookie.split_at(cookie.len() - TIME_LEN);
+        self.decrypt_pair(cookie)
+            .map(|(cookie, timeout)| {
+                CookieSecret::new(CookieContent::Encrypted {
+                    cookie,
+                    timeout,
+                    expires: Some(bytes_time(expires)),
+                })
+            })
+    }
+
+    fn decrypt_pair(
+        &self,
+        cookie: &[u8],
+    ) -> Option<(UnencryptedCsrfCookie, Option<UnencryptedCsrfCookie>)> {
+        //a cookie, or with a grace period, a cookie followed by one expiring at the end of its
+        //timeout
+        if let Some(cookie) = self.decrypt_cookie(cookie) {
+            return Some((cookie, None));
+        }
+        if cookie.len() % 2 != 0 {
+            return None;
+        }
+        let (cookie, timeout) = cookie.split_at(cookie.len() / 2);
+        let cookie = self.decrypt_cookie(cookie)?;
+        let timeout = self.decrypt_cookie(timeout)?;
+        if !constant_time_eq(cookie.value(), timeout.value()) {
+            return None;
+        }
+        Some((cookie, Some(timeout)))
+    }
+
+    fn signed_token(&self, id: &[u8]) -> String {
+        //mac of the cookie identifier and a random nonce given along, so every token differ, and
+        //none can be derived from another one
+        let nonce = thread_rng().gen::<[u8; NONCE_LEN]>();
+        let signature = self.primary().sign(b"token", &[id, &nonce]);
+        self.encode_token(&[&nonce[..], &signature[..]].concat())
+    }
+
+    fn pair_valid(&self, token: &UnencryptedCsrfToken, cookie: &UnencryptedCsrfCookie) -> bool {
+        //the csrf crate compares pairs with ==, which returns at the first difference, so compare
+        //them in constant time first, and only let it check the expiry of matching pairs
+        constant_time_eq(token.value(), cookie.value())
+            && self.primary().inner.verify_token_pair(token, cookie)
+    }
+
+    pub fn token(&self, secret: &[u8], binding: Option<&[u8]>) -> Option<String> {
+        //mint a new token for a cookie secret, bound to binding
+        self.bind(self.mint(secret)?, binding, None)
+    }
+
+    pub fn scoped_token(
+        &self,
+        secret: &[u8],
+        binding: Option<&[u8]>,
+        action: &str,
+    ) -> Option<String> {
+        //mint a new token for a cookie secret, only valid on requests sent to the path action
+        self.bind(self.mint(secret)?, binding, Some(action))
+    }
+
+    fn mint(&self, secret: &[u8]) -> Option<String> {
+        //a token for a cookie secret, neither bound nor scoped
+        if self.scheme == TokenScheme::SignedDoubleSubmit {
+            if secret.len() != SIGNED_ID_LEN {
+                return None;
+            }
+            Some(self.signed_token(secret))
+        } else {
+            let token = self
+                .primary()
+                .inner
+                .generate_token(&secret_array(secret)?)
+                .ok()?;
+            Some(self.encode_token(token.value()))
+        }
+    }
+
+    pub fn expiry(&self, secret: &[u8], ttl: i64) -> Option<String> {
+        //mint an encrypted or signed expiry for a cookie secret, to be appended to a token
+        if self.scheme == TokenScheme::SignedDoubleSubmit {
+            let expires = time_bytes(now() + ttl);
+            let signature = self.primary().sign(b"expiry", &[secret, &expires]);
+            return Some(BASE64URL_NOPAD.encode(&[&expires[..], &signature[..]].concat()));
+        }
+        let expiry = self
+            .primary()
+            .inner
+            .generate_cookie(&secret_array(secret)?, ttl)
+            .ok()?;
+        Some(BASE64URL_NOPAD.encode(expiry.value()))
+    }
+
+    fn verify_signed(&self, token: &str, expiry: Option<&str>, id: &[u8], expires: i64) -> bool {
+        //verify a token of the signed double submit scheme, and the cookie it was sent with
+        let now = now();
+        if expires <= now {
+            return false;
+        }
+        let (keys, token) = match self.split_token(token) {
+            Some(split) => split,
+            None => return false,
+        };
+        if token.len() != NONCE_LEN + MAC_LEN {
+            return false;
+        }
+        let (nonce, signature) = token.split_at(NONCE_LEN);
+        if !keys.iter().any(|key| key.check(b"token", &[id, nonce], signature)) {
+            return false;
+        }
+        match expiry {
+            Some(expiry) => match BASE64URL_NOPAD.decode(expiry.as_bytes()) {
+                Ok(ref expiry) if expiry.len() == TIME_LEN + MAC_LEN => {
+                    let (time, signature) = expiry.split_at(TIME_LEN);
+                    bytes_time(time) > now
+                        && self
+                            .keys
+                            .iter()
+                            .any(|key| key.check(b"expiry", &[id, time], signature))
+                }
+                _ => false,
+            },
+            None => true,
+        }
+    }
+
+    pub fn verify(&self, token: &str, cookie: &CookieSecret) -> bool {
+        //verify a token sent by a client, and its embedded expiry if it was created via with_ttl
+        let mut parts = token.splitn(2, '.');
+        let token = match parts
+            .next()
+            .and_then(|token| {
+                let action = cookie.action.as_ref().map(|action| action.as_str());
+                self.check_binding(token, cookie.binding(), action)
+            })
+        {
+            Some(token) => token,
+            None => return false,
+        };
+        let cookie = match &cookie.inner {
+            CookieContent::Encrypted { cookie, .. } => cookie,
+            CookieContent::Signed { id, expires, .. } => {
+                return self.verify_signed(token, parts.next(), id, *expires)
+            }
+        };
+        let token = match self.split_token(token).and_then(|(keys, token)| {
+            keys.iter()
+                .filter_map(|key| key.inner.parse_token(&token).ok())
+                .next()
+        }) {
+            Some(token) => token,
+            None => return false,
+        };
+        if !self.pair_valid(&token, cookie) {
+            return false;
+        }
+        match parts.next() {
+            Some(expiry) => BASE64URL_NOPAD
+                .decode(expiry.as_bytes())
+                .ok()
+                .and_then(|expiry| self.decrypt_cookie(&expiry))
+                .map(|expiry| self.pair_valid(&token, &expiry))
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    pub fn nonce(&self, token: &str) -> Option<Vec<u8>> {
+        //what identifies a token whatever its key id and expiry, to refuse it once used. Tokens
+        //can't be altered without the key, so a replayed token always has the same nonce
+        let token = token.split(|c| c == '.' || c == BINDING_SEPARATOR).next()?;
+        self.split_token(token).map(|(_, token)| token)
+    }
+
+    pub fn in_grace_period(&self, cookie: &CookieSecret) -> bool {
+        //whether a cookie is past its timeout, and only valid thanks to the grace period
+        match &cookie.inner {
+            CookieContent::Encrypted {
+                timeout: Some(timeout),
+                ..
+            } => {
+                let token = UnencryptedCsrfToken::new(timeout.value().to_vec());
+                !self.pair_valid(&token, timeout)
+            }
+            CookieContent::Signed {
+                timeout: Some(timeout),
+                ..
+            } => *timeout <= now(),
+            _ => false,
+        }
+    }
+
+    pub fn is_valid(&self, cookie: &CookieSecret) -> bool {
+        //check a cookie is not expired, by verifying a fresh token against it
+        self.token(cookie.value(), cookie.binding())
+            .map(|token| self.verify(&token, cookie))
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::ManuallyDrop;
+    use std::ptr;
+
+    use token::{derive_key, hkdf_sha256, CsrfBackend, Key, TokenEngine, TokenScheme};
+
+    fn aes_engine(keys: &[[u8; 32]]) -> TokenEngine {
+        TokenEngine::new(keys, CsrfBackend::AesGcm, TokenScheme::EncryptedPair)
+    }
+
+    #[test]
+    fn test_issue_and_verify() {
+        let engine = aes_engine(&[[0; 32]]);
+        let issued = engine.issue(None, 3600, 0, None).unwrap();
+        let cookie = ::data_encoding::BASE64.decode(issued.cookie.as_bytes()).unwrap();
+        let cookie = engine.parse_cookie(&cookie).unwrap();
+        assert!(engine.verify(&issued.token, &cookie));
+        assert!(engine.verify(&engine.token(&issued.secret, None).unwrap(), &cookie));
+        assert!(!engine.verify("not a token", &cookie));
+
+        let other = aes_engine(&[[1; 32]]).issue(None, 3600, 0, None).unwrap();
+        assert!(!engine.verify(&other.token, &cookie));
+    }
+
+    #[test]
+    fn test_hkdf() {
+        //first block of the output of RFC 5869 test case 1
+        let salt: Vec<u8> = (0..13).collect();
+        let info: Vec<u8> = (0xf0..0xfa).collect();
+        let key = hkdf_sha256(&salt, &[0x0b; 22], &info);
+        assert_eq!(
+            ::data_encoding::HEXLOWER.encode(&key[..]),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf"
+        );
+        assert_ne!(&derive_key(&[0; 32])[..], &[0; 32][..]);
+        assert_eq!(&derive_key(&[0; 16])[..], &derive_key(&[0; 16])[..]);
+    }
+
+    #[test]
+    fn test_malformed_tokens() {
+        for scheme in &[TokenScheme::EncryptedPair, TokenScheme::SignedDoubleSubmit] {
+            let engine = TokenEngine::new(&[[0; 32]], CsrfBackend::AesGcm, *scheme);
+            let issued = engine.issue(None, 3600, 0, None).unwrap();
+            let cookie = ::data_encoding::BASE64.decode(issued.cookie.as_bytes()).unwrap();
+            let cookie = engine.parse_cookie(&cookie).unwrap();
+            let token = &issued.token;
+            let mut flipped = token.clone().into_bytes();
+            let last = flipped.len() - 2; //the last char of base64 without padding has unused bits
+            flipped[last] = if flipped[last] == b'A' { b'B' } else { b'A' };
+            let malformed = [
+                String::new(),
+                token[..token.len() - 1].to_owned(),
+                format!("{}A", token),
+                String::from_utf8(flipped).unwrap(),
+                format!("{}.", token),
+                format!("{}~", token),
+                token.replace(':', ""),
+                format!("x{}", token),
+            ];
+            for token in &malformed {
+                assert!(!engine.verify(token, &cookie), "{} accepted", token);
+            }
+        }
+    }
+
+    #[test]
+    fn test_expiry() {
+        let engine = aes_engine(&[[0; 32]]);
+        let issued = engine.issue(None, 3600, 0, None).unwrap();
+        let cookie = ::data_encoding::BASE64.decode(issued.cookie.as_bytes()).unwrap();
+        let cookie = engine.parse_cookie(&cookie).unwrap();
+        let valid = format!("{}.{}", issued.token, engine.expiry(&issued.secret, 60).unwrap());
+        assert!(engine.verify(&valid, &cookie));
+        let expired = format!("{}.{}", issued.token, engine.expiry(&issued.secret, -60).unwrap());
+        assert!(!engine.verify(&expired, &cookie));
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old = aes_engine(&[[0; 32]]);
+        let issued = old.issue(None, 3600, 0, None).unwrap();
+        let cookie = ::data_encoding::BASE64.decode(issued.cookie.as_bytes()).unwrap();
+
+        let rotated = aes_engine(&[[1; 32], [0; 32]]);
+        let cookie = rotated.parse_cookie(&cookie).unwrap();
+        assert!(rotated.verify(&issued.token, &cookie)); //old tokens are still valid
+        let token = rotated.token(&issued.secret, None).unwrap();
+        assert!(!token.starts_with(&issued.token[..5])); //new ones are made with the new key
+        assert!(rotated.verify(&token, &cookie));
+        assert!(!old.verify(&token, &cookie));
+
+        let forgotten = aes_engine(&[[1; 32]]);
+        assert!(!forgotten.verify(&issued.token, &cookie));
+    }
+
+    #[test]
+    fn test_backends() {
+        for &backend in &[CsrfBackend::AesGcm, CsrfBackend::ChaCha20Poly1305, CsrfBackend::Hmac] {
+            let engine = TokenEngine::new(&[[0; 32]], backend, TokenScheme::EncryptedPair);
+            let issued = engine.issue(None, 3600, 0, None).unwrap();
+            let cookie = ::data_encoding::BASE64.decode(issued.cookie.as_bytes()).unwrap();
+            let cookie = engine.parse_cookie(&cookie).unwrap();
+            assert!(engine.verify(&issued.token, &cookie));
+            assert!(engine.is_valid(&cookie));
+        }
+    }
+
+    #[test]
+    fn test_signed_double_submit() {
+        let engine = TokenEngine::new(
+            &[[0; 32]],
+            CsrfBackend::AesGcm,
+            TokenScheme::SignedDoubleSubmit,
+        );
+        let issued = engine.issue(None, 3600, 0, None).unwrap();
+        let raw = ::data_encoding::BASE64.decode(issued.cookie.as_bytes()).unwrap();
+        let cookie = engine.parse_cookie(&raw).unwrap();
+        assert!(engine.verify(&issued.token, &cookie));
+        let token = engine.token(&issued.secret, None).unwrap();
+        assert_ne!(token, issued.token);
+        assert!(engine.verify(&token, &cookie));
+        let valid = format!("{}.{}", token, engine.expiry(&issued.secret, 60).unwrap());
+        assert!(engine.verify(&valid, &cookie));
+        let expired = format!("{}.{}", token, engine.expiry(&issued.secret, -60).unwrap());
+        assert!(!engine.verify(&expired, &cookie));
+
+        let mut forged = raw.clone();
+        forged[0] ^= 1;
+        assert!(engine.parse_cookie(&forged).is_none());
+        let other = engine.issue(None, 3600, 0, None).unwrap();
+        assert!(!engine.verify(&other.token, &cookie));
+    }
+
+    #[test]
+    fn test_binding() {
+        let engine = aes_engine(&[[0; 32]]);
+        let issued = engine.issue(None, 3600, 0, Some(b"session")).unwrap();
+        let raw = ::data_encoding::BASE64.decode(issued.cookie.as_bytes()).unwrap();
+        let bound = |binding: Option<&[u8]>| {
+            engine
+                .parse_cookie(&raw)
+                .unwrap()
+                .bind(binding.map(|binding| binding.to_vec()))
+        };
+        assert!(engine.verify(&issued.token, &bound(Some(b"session"))));
+        assert!(!engine.verify(&issued.token, &bound(Some(b"other session"))));
+        assert!(!engine.verify(&issued.token, &bound(None)));
+        let unbound = engine.token(&issued.secret, None).unwrap();
+        assert!(!engine.verify(&unbound, &bound(Some(b"session"))));
+        assert!(engine.is_valid(&bound(Some(b"session"))));
+    }
+
+    #[test]
+    fn test_grace_period() {
+        for scheme in &[TokenScheme::EncryptedPair, TokenScheme::SignedDoubleSubmit] {
+            let engine = TokenEngine::new(&[[0; 32]], CsrfBackend::AesGcm, *scheme);
+            let parse = |cookie: &str| {
+                let raw = ::data_encoding::BASE64.decode(cookie.as_bytes()).unwrap();
+                engine.parse_cookie(&raw).unwrap()
+            };
+            let late = engine.issue(None, -60, 3600, None).unwrap();
+            let cookie = parse(&late.cookie);
+            assert!(engine.verify(&late.token, &cookie));
+            assert!(engine.in_grace_period(&cookie));
+            assert_eq!(cookie.expires(), Some(late.expires));
+
+            let fresh = engine.issue(Some(&cookie), 3600, 3600, None).unwrap();
+            assert_eq!(fresh.secret, late.secret);
+            assert!(!engine.in_grace_period(&parse(&fresh.cookie)));
+
+            let expired = engine.issue(None, -3600, 60, None).unwrap();
+            assert!(!engine.verify(&expired.token, &parse(&expired.cookie)));
+        }
+    }
+
+    #[test]
+    fn test_action_scope() {
+        let engine = aes_engine(&[[0; 32]]);
+        let issued = engine.issue(None, 3600, 0, None).unwrap();
+        let raw = ::data_encoding::BASE64.decode(issued.cookie.as_bytes()).unwrap();
+        let scoped = |action: &str| engine.parse_cookie(&raw).unwrap().scope(action);
+        let token = engine.scoped_token(&issued.secret, None, "/transfer").unwrap();
+        assert!(engine.verify(&token, &scoped("/transfer")));
+        assert!(!engine.verify(&token, &scoped("/logout")));
+        assert!(engine.verify(&issued.token, &scoped("/logout"))); //unscoped tokens are valid anywhere
+
+        let session = |action: &str| {
+            engine
+                .parse_cookie(&raw)
+                .unwrap()
+                .bind(Some(b"session".to_vec()))
+                .scope(action)
+        };
+        let token = engine
+            .scoped_token(&issued.secret, Some(b"session"), "/transfer")
+            .unwrap();
+        assert!(engine.verify(&token, &session("/transfer")));
+        assert!(!engine.verify(&token, &scoped("/transfer")));
+    }
+
+    #[test]
+    fn test_key_wiped_on_drop() {
+        let mut key = ManuallyDrop::new(Key::new(&[1; 32], CsrfBackend::AesGcm));
+        let secret: *const [u8; 32] = &*key.secret;
+        unsafe {
+            ptr::drop_in_place(&mut *key as *mut Key); //the storage outlives the key
+            assert_eq!(ptr::read_volatile(secret), [0; 32]);
+        }
+    }
+}
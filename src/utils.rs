@@ -1,3 +1,7 @@
+use rocket::http::uri::URI as Uri;
+use std::{cmp, iter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
 pub fn parse_args(args: &str) -> impl Iterator<Item = (&str, &str)> {
     //transform a group of argument into an iterator of key and value
     args.split('&').filter_map(|kv| parse_keyvalue(&kv))
@@ -13,9 +17,44 @@ fn parse_keyvalue(kv: &str) -> Option<(&str, &str)> {
     }
 }
 
+pub fn form_decode(value: &str) -> Option<String> {
+    //decode a key or value of an url-encoded form as browsers encode them, with `+` for spaces
+    //and everything but a few characters, like the `:` and `~` of tokens, percent-encoded
+    Uri::percent_decode(value.replace('+', " ").as_bytes())
+        .ok()
+        .map(|value| value.into_owned())
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    //compare two slices without returning early, neither on the first difference nor when their
+    //lengths differ, so the time taken only depends on the length of the longest one
+    let len = cmp::max(a.len(), b.len());
+    let padded_a = a.iter().chain(iter::repeat(&0)).take(len);
+    let padded_b = b.iter().chain(iter::repeat(&0)).take(len);
+    let different_len = (a.len() != b.len()) as u8;
+    padded_a.zip(padded_b).fold(different_len, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub fn now() -> i64 {
+    //current time, in seconds since the Unix epoch
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub fn sample_ratio(value: &[u8]) -> f32 {
+    //map a value to a deterministic number in [0, 1), with FNV-1a which, contrary to the hasher
+    //of the standard library, gives the same result whatever the Rust release
+    let hash = value.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    (hash % 10_000) as f32 / 10_000.0
+}
+
 #[cfg(test)]
 mod tests {
-    use utils::{parse_keyvalue, parse_args};
+    use utils::{constant_time_eq, form_decode, parse_keyvalue, parse_args, sample_ratio};
     #[test]
     fn test_parse_keyvalue() {
         assert_eq!(parse_keyvalue("a_key=a_value").unwrap(),("a_key", "a_value"));
@@ -40,4 +79,28 @@ mod tests {
         assert_eq!(it.next().unwrap(), ("key3", ""));
         assert!(it.next().is_none());
     }
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"same", b"same"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"same", b"sane"));
+        assert!(!constant_time_eq(b"same", b"same_but_longer"));
+        assert!(!constant_time_eq(b"same", b"same\0")); //shorter slices are padded, not equal
+        assert!(!constant_time_eq(b"", b"\0"));
+    }
+    #[test]
+    fn test_sample_ratio() {
+        let ratio = sample_ratio(b"some cookie value");
+        assert!(ratio >= 0.0 && ratio < 1.0);
+        assert_eq!(ratio, sample_ratio(b"some cookie value"));
+        assert_eq!(sample_ratio(b""), 0.6037); //FNV-1a offset basis, stable across releases
+    }
+
+    #[test]
+    fn test_form_decode() {
+        assert_eq!(form_decode("a0f1%3Aab%7Ecd").unwrap(), "a0f1:ab~cd");
+        assert_eq!(form_decode("a+b%2B").unwrap(), "a b+");
+        assert_eq!(form_decode("ab:cd~").unwrap(), "ab:cd~");
+        assert!(form_decode("%FF").is_none());
+    }
 }
@@ -0,0 +1,154 @@
+use rocket::handler;
+use rocket::http::{Method, Status};
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest};
+use rocket::{Data, Request, Route};
+use std::cell::Cell;
+use std::fmt;
+
+pub const VIOLATION_ROUTE: &str = "/__rocket_csrf/violation";
+
+thread_local! {
+    //violation found by the fairing for the request being handled by this thread, if any
+    static CURRENT_VIOLATION: Cell<Option<ViolationReason>> = Cell::new(None);
+}
+
+pub fn set_current_violation(reason: Option<ViolationReason>) {
+    CURRENT_VIOLATION.with(|current| current.set(reason));
+}
+
+/// What the fairing does with requests violating csrf protection, when they match no exception.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViolationAction {
+    /// Reroute the request to the default target. This is the default.
+    Reroute,
+    /// Fail the request with this status, so it is answered by the matching Rocket catcher.
+    /// Usually `Status::Forbidden`, which suits APIs better than a reroute.
+    Respond(Status),
+}
+
+/// Whether violations are enforced or only reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Enforcement {
+    /// Violations are handled according to the [ViolationAction](enum.ViolationAction.html).
+    /// This is the default.
+    Enforce,
+    /// Violations are logged and given to the violation callback, if any, but requests reach
+    /// their original route unchanged. Use it to roll the fairing out on an existing application
+    /// without breaking forms which don't send a token yet.
+    ReportOnly,
+}
+
+pub type ViolationCallback = Box<Fn(&Request, ViolationReason) + Send + Sync>;
+
+pub fn violation_route() -> Route {
+    //route to which violations are rerouted when they must fail with a status
+    Route::new(Method::Get, format!("{}/<status>", VIOLATION_ROUTE), fail_with_status)
+}
+
+fn fail_with_status<'r>(request: &'r Request, _: Data) -> handler::Outcome<'r> {
+    let status = request
+        .get_param::<u16>(0)
+        .ok()
+        .and_then(Status::from_code)
+        .unwrap_or(Status::Forbidden);
+    Outcome::Failure(status)
+}
+
+/// Why a request was considered a csrf violation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViolationReason {
+    /// The fairing state could not be found, nothing could be verified
+    Misconfigured,
+    /// The request has no valid csrf cookie
+    MissingCookie,
+    /// The csrf cookie of the request has expired
+    ExpiredCookie,
+    /// The request doesn't seem to carry a token at all
+    MissingToken,
+    /// No token matching the csrf cookie was found in the request
+    InvalidToken,
+    /// The body is larger than the configured limits, so it was not searched for a token
+    BodyTooLarge,
+    /// No valid token was found, but the body is longer than what could be searched, so the
+    /// token may just be further in it. Usually means a form larger than the data limits rather
+    /// than an attack.
+    ///
+    /// Fairings only get a reference to the body, so the fairing can't read more of it than what
+    /// Rocket peeked and still give it whole to the route. Verify such forms with the
+    /// [CsrfForm](struct.CsrfForm.html) or [CsrfBody](struct.CsrfBody.html) data guards and
+    /// [`set_deferred_verification_prefix`](struct.CsrfFairingBuilder.html#method.set_deferred_verification_prefix)
+    /// instead. `CsrfBody` reads up to
+    /// [`set_max_body_scan`](struct.CsrfFairingBuilder.html#method.set_max_body_scan) bytes, then
+    /// gives the route the whole body.
+    TokenBeyondLimit,
+    /// The body has a content type the token is not searched in, and no header token or other
+    /// check accepted the request
+    UnsupportedContentType,
+    /// The `Origin` or `Referer` header is not a trusted origin
+    UntrustedOrigin,
+    /// The `Sec-Fetch-Site` header show a cross site request
+    CrossSiteRequest,
+    /// The token is valid, but was already used, while one-time tokens are enabled
+    ReplayedToken,
+}
+
+impl fmt::Display for ViolationReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ViolationReason::Misconfigured => "csrf state not found",
+            ViolationReason::MissingCookie => "missing csrf cookie",
+            ViolationReason::ExpiredCookie => "expired csrf cookie",
+            ViolationReason::MissingToken => "missing csrf token",
+            ViolationReason::InvalidToken => "invalid csrf token",
+            ViolationReason::BodyTooLarge => "body larger than data limits",
+            ViolationReason::TokenBeyondLimit => "token not found within data limits",
+            ViolationReason::UnsupportedContentType => "unsupported body content type",
+            ViolationReason::UntrustedOrigin => "untrusted origin",
+            ViolationReason::CrossSiteRequest => "cross site request",
+            ViolationReason::ReplayedToken => "csrf token already used",
+        })
+    }
+}
+
+/// Request guard telling why the request violated csrf protection.
+///
+/// Use it in the handler of the default target, or of exception targets, to tell users what went
+/// wrong, for example to ask them to reload the page when their cookie expired. It forwards on
+/// requests which are not violations, so take it as an `Option<CsrfViolation>` if the route may
+/// also be reached directly. In report only mode, the original route can use it too.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[get("/csrf-violation")]
+/// fn violation(violation: Option<CsrfViolation>) -> String {
+///     match violation.map(|violation| violation.reason()) {
+///         Some(ViolationReason::ExpiredCookie) => "Your session expired, please retry".to_owned(),
+///         Some(reason) => format!("Request rejected: {}", reason),
+///         None => "Nothing to see here".to_owned(),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CsrfViolation {
+    reason: ViolationReason,
+}
+
+impl CsrfViolation {
+    /// Why the request is a violation.
+    pub fn reason(&self) -> ViolationReason {
+        self.reason
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for CsrfViolation {
+    type Error = ();
+
+    fn from_request(_: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        match CURRENT_VIOLATION.with(|current| current.get()) {
+            Some(reason) => Outcome::Success(CsrfViolation { reason }),
+            None => Outcome::Forward(()),
+        }
+    }
+}
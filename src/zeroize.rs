@@ -0,0 +1,114 @@
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+
+//the part of the API of the zeroize crate this crate uses, written with volatile writes, as the
+//releases of zeroize which are still published need a newer compiler than Rocket 0.3 does.
+//Only the memory a value owns when it is wiped is cleared: copies made before, like the key the
+//csrf crate expands into its ciphers or the cookie secrets it decrypts, and buffers a vector
+//left behind when it grew, are not
+
+pub trait Zeroize {
+    fn zeroize(&mut self);
+}
+
+fn wipe(bytes: *mut u8, len: usize) {
+    for i in 0..len {
+        unsafe { ptr::write_volatile(bytes.add(i), 0) };
+    }
+    atomic::compiler_fence(Ordering::SeqCst); //keep later reads of this memory after the wipe
+}
+
+impl Zeroize for [u8; 32] {
+    fn zeroize(&mut self) {
+        wipe(self.as_mut_ptr(), self.len());
+    }
+}
+
+impl Zeroize for [u8; 64] {
+    fn zeroize(&mut self) {
+        wipe(self.as_mut_ptr(), self.len());
+    }
+}
+
+impl Zeroize for Vec<u8> {
+    fn zeroize(&mut self) {
+        wipe(self.as_mut_ptr(), self.capacity()); //the spare capacity may hold older content
+        self.clear();
+    }
+}
+
+impl Zeroize for Vec<[u8; 32]> {
+    fn zeroize(&mut self) {
+        wipe(self.as_mut_ptr() as *mut u8, self.capacity() * 32);
+        self.clear();
+    }
+}
+
+impl Zeroize for String {
+    fn zeroize(&mut self) {
+        unsafe { self.as_mut_vec() }.zeroize(); //only zeros are left, which is valid UTF-8
+    }
+}
+
+pub struct Zeroizing<T: Zeroize>(T);
+
+impl<T: Zeroize> Zeroizing<T> {
+    pub fn new(value: T) -> Self {
+        Zeroizing(value)
+    }
+}
+
+impl<T: Zeroize> Deref for Zeroizing<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> DerefMut for Zeroizing<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Zeroize + Clone> Clone for Zeroizing<T> {
+    fn clone(&self) -> Self {
+        Zeroizing(self.0.clone())
+    }
+}
+
+impl<T: Zeroize> Drop for Zeroizing<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zeroize::Zeroize;
+
+    #[test]
+    fn test_zeroize() {
+        let mut array = [1; 32];
+        array.zeroize();
+        assert_eq!(array, [0; 32]);
+
+        let mut vec = Vec::with_capacity(8);
+        vec.extend_from_slice(&[1, 2, 3]);
+        let (bytes, capacity) = (vec.as_ptr(), vec.capacity());
+        vec.zeroize();
+        assert!(vec.is_empty());
+        assert_eq!(vec.capacity(), capacity); //still owned, so still readable
+        assert_eq!(unsafe { ::std::slice::from_raw_parts(bytes, 3) }, &[0, 0, 0]);
+
+        let mut keys = vec![[1; 32], [2; 32]];
+        keys.zeroize();
+        assert!(keys.is_empty());
+
+        let mut string = "secret".to_owned();
+        string.zeroize();
+        assert!(string.is_empty());
+    }
+}